@@ -10,10 +10,12 @@ use alloc::sync::Arc;
 
 use corelib::domain_info::DomainInfo;
 pub use corelib::{
-    backtrace, blk_crash_trick, checkout_shared_data, create_domain, get_domain, impl_has_timer,
-    kernel, new_mutex, new_spinlock, register_domain, reload_domain, update_domain, write_console,
-    CoreFunction, LinuxError, LinuxResult, SafePtr,
+    backtrace, checkout_shared_data, create_domain, get_domain, register_domain, reload_domain,
+    take_injected_panic, update_domain, write_console, CoreFunction, LinuxError, LinuxResult,
+    SafePtr,
 };
+#[cfg(not(feature = "host"))]
+pub use corelib::{impl_has_timer, kernel, new_mutex, new_spinlock};
 pub use domain_main::domain_main;
 use ksync::Mutex;
 pub type DomainInfoSet = Mutex<DomainInfo>;
@@ -23,22 +25,46 @@ pub fn domain_info() -> Arc<DomainInfoSet> {
     unsafe { res.downcast_unchecked() }
 }
 
-#[cfg(feature = "unwind")]
+/// Run `f`, the panic injected by the tcb is raised here, so it unwinds like a real panic
+/// of the domain.
+#[inline]
+fn enter<F: FnOnce() -> LinuxResult<R>, R>(f: F) -> LinuxResult<R> {
+    if take_injected_panic() {
+        panic!("injected panic");
+    }
+    f()
+}
+
+#[cfg(all(feature = "unwind", not(feature = "host")))]
 pub fn catch_unwind<F: FnOnce() -> LinuxResult<R>, R>(f: F) -> LinuxResult<R> {
-    let res = unwinding::panic::catch_unwind(f).unwrap_or_else(|_| {
+    let res = unwinding::panic::catch_unwind(|| enter(f)).unwrap_or_else(|_| {
         println_color!(31, "[Panic] catch unwind error");
         Err(LinuxError::DOMAINCRASH)
     });
     res
 }
 
-#[cfg(feature = "unwind")]
+#[cfg(all(feature = "unwind", not(feature = "host")))]
 #[inline]
 pub fn unwind_from_panic() {
     use alloc::boxed::Box;
     unwinding::panic::begin_panic(Box::new(()));
 }
 
+/// The panic of a domain in a host program unwinds by std, `unwinding` is not linked.
+///
+/// std prints the panic message, the panic is reported to the tcb here like the panic
+/// handler of a domain does.
+#[cfg(feature = "host")]
+pub fn catch_unwind<F: FnOnce() -> LinuxResult<R>, R>(f: F) -> LinuxResult<R> {
+    extern crate std;
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| enter(f))).unwrap_or_else(|_| {
+        backtrace(rref::domain_id());
+        println_color!(31, "[Panic] catch unwind error");
+        Err(LinuxError::DOMAINCRASH)
+    })
+}
+
 pub mod sync {
     pub use spin::Mutex;
 }
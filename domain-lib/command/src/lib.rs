@@ -1,283 +1,610 @@
+//! The binary protocol used by `/proc/sys/rust/domain/command`.
+//!
+//! Every request and response is a frame with a fixed header followed by the payload.
+//! All integers are little-endian, strings are prefixed by a `u16` length.
+//!
+//! ```text
+//! | magic: 2 | version: 1 | opcode: 1 | payload len: 4 | request id: 8 | payload |
+//! ```
+//!
+//! The request id is chosen by the user and echoed in the response. Domain files are
+//! uploaded in a session: `start` returns the session id, each `send` carries a chunk
+//! and its checksum, `stop` carries the detached signature of the file and registers it,
+//! `exit` aborts the session. `unload_tree` unloads a domain together with the domains which
+//! depend on it, the dependents first.
 #![no_std]
 extern crate alloc;
 
-use alloc::{format, vec::Vec};
+use alloc::vec::Vec;
+use core::fmt::{Display, Formatter};
 
-#[derive(Debug)]
+pub const MAGIC: [u8; 2] = *b"DC";
+pub const VERSION: u8 = 3;
+pub const HEADER_SIZE: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+enum Opcode {
+    Start = 1,
+    Send = 2,
+    Stop = 3,
+    Exit = 4,
+    Update = 5,
+    Load = 6,
+    Unload = 7,
+    UnloadTree = 8,
+    RegisterPath = 9,
+    Ok = 0x80,
+    Receive = 0x81,
+    Error = 0x82,
+}
+
+impl TryFrom<u8> for Opcode {
+    type Error = ErrorCode;
+
+    fn try_from(value: u8) -> Result<Self, ErrorCode> {
+        let op = match value {
+            1 => Opcode::Start,
+            2 => Opcode::Send,
+            3 => Opcode::Stop,
+            4 => Opcode::Exit,
+            5 => Opcode::Update,
+            6 => Opcode::Load,
+            7 => Opcode::Unload,
+            8 => Opcode::UnloadTree,
+            9 => Opcode::RegisterPath,
+            0x80 => Opcode::Ok,
+            0x81 => Opcode::Receive,
+            0x82 => Opcode::Error,
+            _ => return Err(ErrorCode::UnknownCommand),
+        };
+        Ok(op)
+    }
+}
+
+/// The errors reported by the kernel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u16)]
+pub enum ErrorCode {
+    BadMagic = 1,
+    UnsupportedVersion = 2,
+    Malformed = 3,
+    UnknownCommand = 4,
+    InvalidDomainType = 5,
+    InvalidSession = 6,
+    TooManySessions = 7,
+    ChecksumMismatch = 8,
+    OutOfOrder = 9,
+    SizeMismatch = 10,
+    RegisterFailed = 11,
+    UpdateFailed = 12,
+    LoadFailed = 13,
+    UnloadFailed = 14,
+    TooLarge = 15,
+    OutOfMemory = 16,
+    PermissionDenied = 17,
+    BadSignature = 18,
+    InvalidArgs = 19,
+    FileNotFound = 20,
+}
+
+impl TryFrom<u16> for ErrorCode {
+    type Error = ErrorCode;
+
+    fn try_from(value: u16) -> Result<Self, ErrorCode> {
+        let code = match value {
+            1 => ErrorCode::BadMagic,
+            2 => ErrorCode::UnsupportedVersion,
+            3 => ErrorCode::Malformed,
+            4 => ErrorCode::UnknownCommand,
+            5 => ErrorCode::InvalidDomainType,
+            6 => ErrorCode::InvalidSession,
+            7 => ErrorCode::TooManySessions,
+            8 => ErrorCode::ChecksumMismatch,
+            9 => ErrorCode::OutOfOrder,
+            10 => ErrorCode::SizeMismatch,
+            11 => ErrorCode::RegisterFailed,
+            12 => ErrorCode::UpdateFailed,
+            13 => ErrorCode::LoadFailed,
+            14 => ErrorCode::UnloadFailed,
+            15 => ErrorCode::TooLarge,
+            16 => ErrorCode::OutOfMemory,
+            17 => ErrorCode::PermissionDenied,
+            18 => ErrorCode::BadSignature,
+            19 => ErrorCode::InvalidArgs,
+            20 => ErrorCode::FileNotFound,
+            _ => return Err(ErrorCode::Malformed),
+        };
+        Ok(code)
+    }
+}
+
+impl Display for ErrorCode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        let msg = match self {
+            ErrorCode::BadMagic => "bad magic",
+            ErrorCode::UnsupportedVersion => "unsupported protocol version",
+            ErrorCode::Malformed => "malformed frame",
+            ErrorCode::UnknownCommand => "unknown command",
+            ErrorCode::InvalidDomainType => "invalid domain type",
+            ErrorCode::InvalidSession => "invalid upload session",
+            ErrorCode::TooManySessions => "too many upload sessions",
+            ErrorCode::ChecksumMismatch => "chunk checksum mismatch",
+            ErrorCode::OutOfOrder => "chunk out of order",
+            ErrorCode::SizeMismatch => "domain size mismatch",
+            ErrorCode::RegisterFailed => "register domain failed",
+            ErrorCode::UpdateFailed => "update domain failed",
+            ErrorCode::LoadFailed => "load domain failed",
+            ErrorCode::UnloadFailed => "unload domain failed",
+            ErrorCode::TooLarge => "domain file too large",
+            ErrorCode::OutOfMemory => "out of memory",
+            ErrorCode::PermissionDenied => "permission denied",
+            ErrorCode::BadSignature => "bad domain signature",
+            ErrorCode::InvalidArgs => "invalid domain arguments",
+            ErrorCode::FileNotFound => "domain file not found",
+        };
+        write!(f, "{}", msg)
+    }
+}
+
+#[derive(Debug, PartialEq)]
 pub enum Command<'a> {
     Start(StartCommand<'a>),
     Send(SendCommand<'a>),
-    Stop(StopCommand),
+    Stop(StopCommand<'a>),
     Exit(ExitCommand),
     Update(UpdateCommand<'a>),
     Load(LoadCommand<'a>),
     Unload(UnloadCommand<'a>),
+    UnloadTree(UnloadCommand<'a>),
+    RegisterPath(RegisterPathCommand<'a>),
 }
 
 /// Command to update domain
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct UpdateCommand<'a> {
     pub domain_ident: &'a str,
     pub register_domain_elf_ident: &'a str,
     pub domain_type: u8,
 }
 
-/// Command to start to send domain data
-#[derive(Debug)]
+/// Command to start an upload session
+#[derive(Debug, PartialEq)]
 pub struct StartCommand<'a> {
     pub register_domain_elf_ident: &'a str,
     pub domain_type: u8,
     pub domain_size: usize,
 }
 
+/// Command to register a domain file read by the kernel itself
+///
+/// `path` is an absolute path or a file name looked up in the domain search path, the
+/// detached signature is read from `<path>.sig` if it exists. The kernel reads any file, so
+/// the sender needs `CAP_SYS_ADMIN`.
+#[derive(Debug, PartialEq)]
+pub struct RegisterPathCommand<'a> {
+    pub register_domain_elf_ident: &'a str,
+    pub domain_type: u8,
+    pub path: &'a str,
+}
+
 /// Command to Load domain
-#[derive(Debug)]
+///
+/// Several instances can be loaded from the same elf, the kernel replies `Ok(n)` and the
+/// instance is named `<domain_ident>-<n>`.
+#[derive(Debug, PartialEq)]
 pub struct LoadCommand<'a> {
     pub register_domain_elf_ident: &'a str,
     pub domain_ident: &'a str,
     pub domain_type: u8,
+    /// The `key=value` arguments of the domain separated by `,`, the domain uses its
+    /// default arguments if it is empty
+    pub args: &'a str,
 }
-#[derive(Debug)]
+
 /// Command to Unload domain
+#[derive(Debug, PartialEq)]
 pub struct UnloadCommand<'a> {
     pub domain_ident: &'a str,
 }
 
-/// Command to send domain data
-#[derive(Debug)]
+/// Command to send a chunk of domain data
+#[derive(Debug, PartialEq)]
 pub struct SendCommand<'a> {
-    pub id: u64,
-    pub data_id: usize,
-    pub bytes: usize,
+    pub session: u64,
+    pub data_id: u32,
+    /// [`checksum`] of `data`
+    pub checksum: u32,
     pub data: &'a [u8],
 }
-/// Command to stop sending domain data
-#[derive(Debug)]
-pub struct StopCommand {
-    pub id: u64,
+
+/// Command to finish the upload session and register the domain
+#[derive(Debug, PartialEq)]
+pub struct StopCommand<'a> {
+    pub session: u64,
+    /// The detached signature of the domain file, empty if it is not signed
+    pub signature: &'a [u8],
 }
 
-#[derive(Debug)]
+/// Command to abort the upload session
+#[derive(Debug, PartialEq)]
 pub struct ExitCommand {
-    pub id: u64,
+    pub session: u64,
 }
 
-impl Command<'_> {
-    pub fn parse(data: &[u8]) -> Option<Command> {
-        let mut iter = data.splitn(2, |&x| x == b':');
-        let command = iter.next()?;
-        let command = core::str::from_utf8(command).ok()?;
-        match command {
-            "start" => {
-                let start_command = Self::parse_start(iter.next()?)?;
-                Some(Command::Start(start_command))
-            }
-            "send" => {
-                let send_command = Self::parse_send(iter.next()?)?;
-                Some(Command::Send(send_command))
-            }
-            "stop" => {
-                let stop_command = Self::parse_stop(iter.next()?)?;
-                Some(Command::Stop(stop_command))
-            }
-            "update" => {
-                let update_command = Self::parse_update(iter.next()?)?;
-                Some(Command::Update(update_command))
-            }
-            "load" => {
-                let load_command = Self::parse_load(iter.next()?)?;
-                Some(Command::Load(load_command))
-            }
-            "unload" => {
-                let unload_command = Self::parse_unload(iter.next()?)?;
-                Some(Command::Unload(unload_command))
-            }
-            _ => None,
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Response {
+    Ok(u64),
+    Receive {
+        session: u64,
+        data_id: u32,
+        bytes: u32,
+    },
+    Error(ErrorCode),
+}
+
+/// CRC-32 (IEEE) of the data.
+pub fn checksum(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
         }
     }
+    !crc
+}
 
-    fn parse_load(data: &[u8]) -> Option<LoadCommand> {
-        let mut iter = data.splitn(3, |&x| x == b':');
-        let register_domain_elf_ident = iter.next()?;
-        let register_domain_elf_ident = core::str::from_utf8(register_domain_elf_ident).ok()?;
+struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    fn new(opcode: Opcode, request_id: u64) -> Self {
+        let mut buf = Vec::with_capacity(64);
+        buf.extend_from_slice(&MAGIC);
+        buf.push(VERSION);
+        buf.push(opcode as u8);
+        buf.extend_from_slice(&0u32.to_le_bytes());
+        buf.extend_from_slice(&request_id.to_le_bytes());
+        Self { buf }
+    }
+    fn u8(mut self, v: u8) -> Self {
+        self.buf.push(v);
+        self
+    }
+    fn u16(mut self, v: u16) -> Self {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+    fn u32(mut self, v: u32) -> Self {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+    fn u64(mut self, v: u64) -> Self {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+    fn str(self, s: &str) -> Self {
+        self.u16_bytes(s.as_bytes())
+    }
+    fn u16_bytes(self, data: &[u8]) -> Self {
+        assert!(data.len() <= u16::MAX as usize, "data is too long");
+        self.u16(data.len() as u16).bytes(data)
+    }
+    fn bytes(mut self, data: &[u8]) -> Self {
+        self.buf.extend_from_slice(data);
+        self
+    }
+    fn finish(mut self) -> Vec<u8> {
+        let len = (self.buf.len() - HEADER_SIZE) as u32;
+        self.buf[4..8].copy_from_slice(&len.to_le_bytes());
+        self.buf
+    }
+}
 
-        let domain_ident = iter.next()?;
-        let domain_ident = core::str::from_utf8(domain_ident).ok()?;
+struct Reader<'a> {
+    data: &'a [u8],
+}
 
-        let domain_type = iter.next()?;
-        let domain_type = core::str::from_utf8(domain_type).ok()?;
-        let domain_type_num = domain_type.parse::<u8>().ok()?;
-        let domain_type = domain_type_num;
-        Some(LoadCommand {
-            register_domain_elf_ident,
-            domain_ident,
-            domain_type,
-        })
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], ErrorCode> {
+        if self.data.len() < n {
+            return Err(ErrorCode::Malformed);
+        }
+        let (head, tail) = self.data.split_at(n);
+        self.data = tail;
+        Ok(head)
+    }
+    fn u8(&mut self) -> Result<u8, ErrorCode> {
+        Ok(self.take(1)?[0])
+    }
+    fn u16(&mut self) -> Result<u16, ErrorCode> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+    fn u32(&mut self) -> Result<u32, ErrorCode> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+    fn u64(&mut self) -> Result<u64, ErrorCode> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+    fn str(&mut self) -> Result<&'a str, ErrorCode> {
+        core::str::from_utf8(self.bytes()?).map_err(|_| ErrorCode::Malformed)
+    }
+    fn bytes(&mut self) -> Result<&'a [u8], ErrorCode> {
+        let len = self.u16()? as usize;
+        self.take(len)
+    }
+    fn rest(&mut self) -> &'a [u8] {
+        core::mem::take(&mut self.data)
     }
+    fn finish<T>(self, value: T) -> Result<T, ErrorCode> {
+        if self.data.is_empty() {
+            Ok(value)
+        } else {
+            Err(ErrorCode::Malformed)
+        }
+    }
+}
 
-    fn parse_unload(data: &[u8]) -> Option<UnloadCommand> {
-        let domain_ident = core::str::from_utf8(data).ok()?;
-        Some(UnloadCommand { domain_ident })
-    }
-
-    fn parse_start(data: &[u8]) -> Option<StartCommand> {
-        let mut iter = data.splitn(3, |&x| x == b':');
-        let domain_ident = iter.next()?;
-        let domain_ident = core::str::from_utf8(domain_ident).ok()?;
-        let domain_type = iter.next()?;
-        let domain_type = core::str::from_utf8(domain_type).ok()?;
-        let domain_type_num = domain_type.parse::<u8>().ok()?;
-        let domain_type = domain_type_num;
-        let domain_size = iter.next()?;
-        let domain_size = core::str::from_utf8(domain_size).ok()?;
-        let domain_size = domain_size.parse::<usize>().ok()?;
-        Some(StartCommand {
-            register_domain_elf_ident: domain_ident,
-            domain_type,
-            domain_size,
-        })
+/// Parse the header, return the request id, the opcode and the payload.
+///
+/// The request id is returned with the error if the header is readable.
+fn decode_frame(data: &[u8]) -> Result<(u64, Opcode, Reader), (u64, ErrorCode)> {
+    if data.len() < HEADER_SIZE {
+        return Err((0, ErrorCode::Malformed));
+    }
+    let request_id = u64::from_le_bytes(data[8..16].try_into().unwrap());
+    if data[0..2] != MAGIC {
+        return Err((0, ErrorCode::BadMagic));
+    }
+    if data[2] != VERSION {
+        return Err((request_id, ErrorCode::UnsupportedVersion));
     }
+    let opcode = Opcode::try_from(data[3]).map_err(|e| (request_id, e))?;
+    let len = u32::from_le_bytes(data[4..8].try_into().unwrap()) as usize;
+    if data.len() - HEADER_SIZE != len {
+        return Err((request_id, ErrorCode::Malformed));
+    }
+    Ok((
+        request_id,
+        opcode,
+        Reader {
+            data: &data[HEADER_SIZE..],
+        },
+    ))
+}
 
-    fn parse_send(data: &[u8]) -> Option<SendCommand> {
-        let mut iter = data.splitn(4, |&x| x == b':');
-        let id = iter.next()?;
-        let id = core::str::from_utf8(id).ok()?;
-        let id = id.parse::<u64>().ok()?;
-        let data_id = iter.next()?;
-        let data_id = core::str::from_utf8(data_id).ok()?;
-        let data_id = data_id.parse::<usize>().ok()?;
-        let bytes = iter.next()?;
-        let bytes = core::str::from_utf8(bytes).ok()?;
-        let bytes = bytes.parse::<usize>().ok()?;
-        let data = iter.next()?;
-        Some(SendCommand {
-            id,
-            data_id,
-            bytes,
-            data,
-        })
+impl Command<'_> {
+    /// Parse a request frame, return the request id and the command.
+    pub fn decode(data: &[u8]) -> Result<(u64, Command), (u64, ErrorCode)> {
+        let (request_id, opcode, mut r) = decode_frame(data)?;
+        let command = Self::decode_payload(opcode, &mut r)
+            .and_then(|command| r.finish(command))
+            .map_err(|e| (request_id, e))?;
+        Ok((request_id, command))
     }
 
-    fn parse_stop(data: &[u8]) -> Option<StopCommand> {
-        let id = core::str::from_utf8(data).ok()?;
-        let id = id.parse::<u64>().ok()?;
-        Some(StopCommand { id })
-    }
-
-    fn parse_update(data: &[u8]) -> Option<UpdateCommand> {
-        let mut iter = data.splitn(3, |&x| x == b':');
-        let old_domain_ident = iter.next()?;
-        let old_domain_ident = core::str::from_utf8(old_domain_ident).ok()?;
-        let new_domain_ident = iter.next()?;
-        let new_domain_ident = core::str::from_utf8(new_domain_ident).ok()?;
-        let domain_type = iter.next()?;
-        let domain_type = core::str::from_utf8(domain_type).ok()?;
-        let domain_type_num = domain_type.parse::<u8>().ok()?;
-        let domain_type = domain_type_num;
-        Some(UpdateCommand {
-            domain_ident: old_domain_ident,
-            register_domain_elf_ident: new_domain_ident,
-            domain_type,
-        })
+    fn decode_payload<'a>(opcode: Opcode, r: &mut Reader<'a>) -> Result<Command<'a>, ErrorCode> {
+        let command = match opcode {
+            Opcode::Start => Command::Start(StartCommand {
+                register_domain_elf_ident: r.str()?,
+                domain_type: r.u8()?,
+                domain_size: r.u64()? as usize,
+            }),
+            Opcode::Send => Command::Send(SendCommand {
+                session: r.u64()?,
+                data_id: r.u32()?,
+                checksum: r.u32()?,
+                data: r.rest(),
+            }),
+            Opcode::Stop => Command::Stop(StopCommand {
+                session: r.u64()?,
+                signature: r.bytes()?,
+            }),
+            Opcode::Exit => Command::Exit(ExitCommand { session: r.u64()? }),
+            Opcode::Update => Command::Update(UpdateCommand {
+                domain_ident: r.str()?,
+                register_domain_elf_ident: r.str()?,
+                domain_type: r.u8()?,
+            }),
+            Opcode::Load => Command::Load(LoadCommand {
+                register_domain_elf_ident: r.str()?,
+                domain_ident: r.str()?,
+                domain_type: r.u8()?,
+                args: r.str()?,
+            }),
+            Opcode::Unload => Command::Unload(UnloadCommand {
+                domain_ident: r.str()?,
+            }),
+            Opcode::UnloadTree => Command::UnloadTree(UnloadCommand {
+                domain_ident: r.str()?,
+            }),
+            Opcode::RegisterPath => Command::RegisterPath(RegisterPathCommand {
+                register_domain_elf_ident: r.str()?,
+                domain_type: r.u8()?,
+                path: r.str()?,
+            }),
+            _ => return Err(ErrorCode::UnknownCommand),
+        };
+        Ok(command)
     }
 
-    pub fn to_bytes(&self) -> Vec<u8> {
+    pub fn encode(&self, request_id: u64) -> Vec<u8> {
         match self {
-            Command::Start(start_command) => format!(
-                "start:{}:{}:{}",
-                start_command.register_domain_elf_ident,
-                start_command.domain_type,
-                start_command.domain_size
-            )
-            .as_bytes()
-            .to_vec(),
-            Command::Send(send_command) => {
-                let mut head = format!(
-                    "send:{}:{}:{}:",
-                    send_command.id, send_command.data_id, send_command.bytes
-                )
-                .as_bytes()
-                .to_vec();
-                head.extend_from_slice(send_command.data);
-                head
+            Command::Start(start_command) => Writer::new(Opcode::Start, request_id)
+                .str(start_command.register_domain_elf_ident)
+                .u8(start_command.domain_type)
+                .u64(start_command.domain_size as u64),
+            Command::Send(send_command) => Writer::new(Opcode::Send, request_id)
+                .u64(send_command.session)
+                .u32(send_command.data_id)
+                .u32(send_command.checksum)
+                .bytes(send_command.data),
+            Command::Stop(stop_command) => Writer::new(Opcode::Stop, request_id)
+                .u64(stop_command.session)
+                .u16_bytes(stop_command.signature),
+            Command::Exit(exit_command) => {
+                Writer::new(Opcode::Exit, request_id).u64(exit_command.session)
+            }
+            Command::Update(update_command) => Writer::new(Opcode::Update, request_id)
+                .str(update_command.domain_ident)
+                .str(update_command.register_domain_elf_ident)
+                .u8(update_command.domain_type),
+            Command::Load(load_command) => Writer::new(Opcode::Load, request_id)
+                .str(load_command.register_domain_elf_ident)
+                .str(load_command.domain_ident)
+                .u8(load_command.domain_type)
+                .str(load_command.args),
+            Command::Unload(unload_command) => {
+                Writer::new(Opcode::Unload, request_id).str(unload_command.domain_ident)
+            }
+            Command::UnloadTree(unload_command) => {
+                Writer::new(Opcode::UnloadTree, request_id).str(unload_command.domain_ident)
+            }
+            Command::RegisterPath(register_command) => {
+                Writer::new(Opcode::RegisterPath, request_id)
+                    .str(register_command.register_domain_elf_ident)
+                    .u8(register_command.domain_type)
+                    .str(register_command.path)
             }
-            Command::Load(load_command) => format!(
-                "load:{}:{}:{}",
-                load_command.register_domain_elf_ident,
-                load_command.domain_ident,
-                load_command.domain_type
-            )
-            .as_bytes()
-            .to_vec(),
-            Command::Unload(unload_command) => format!("unload:{}", unload_command.domain_ident)
-                .as_bytes()
-                .to_vec(),
-            Command::Stop(stop_command) => format!("stop:{}", stop_command.id).as_bytes().to_vec(),
-            Command::Exit(exit_command) => format!("exit:{}", exit_command.id).as_bytes().to_vec(),
-            Command::Update(update_command) => format!(
-                "update:{}:{}:{}",
-                update_command.domain_ident,
-                update_command.register_domain_elf_ident,
-                update_command.domain_type
-            )
-            .as_bytes()
-            .to_vec(),
         }
+        .finish()
     }
 }
 
-#[derive(Debug)]
-pub enum Response {
-    Ok(usize),
-    Receive(usize, usize, usize),
-}
-
 impl Response {
-    pub fn to_bytes(&self) -> Vec<u8> {
+    pub fn encode(&self, request_id: u64) -> Vec<u8> {
         match self {
-            Response::Ok(id) => format!("ok:{}", id).as_bytes().to_vec(),
-            Response::Receive(id, data_id, bytes) => {
-                format!("receive:{}:{}:{}", id, data_id, bytes)
-                    .as_bytes()
-                    .to_vec()
-            }
+            Response::Ok(value) => Writer::new(Opcode::Ok, request_id).u64(*value),
+            Response::Receive {
+                session,
+                data_id,
+                bytes,
+            } => Writer::new(Opcode::Receive, request_id)
+                .u64(*session)
+                .u32(*data_id)
+                .u32(*bytes),
+            Response::Error(code) => Writer::new(Opcode::Error, request_id).u16(*code as u16),
         }
+        .finish()
     }
-    pub fn parse(data: &[u8]) -> Option<Response> {
-        let mut iter = data.splitn(2, |&x| x == b':');
-        let response = iter.next()?;
-        let response = core::str::from_utf8(response).ok()?;
-        match response {
-            "ok" => {
-                let id = iter.next()?;
-                let id = core::str::from_utf8(id).ok()?;
-                let id = id.parse::<usize>().ok()?;
-                Some(Response::Ok(id))
-            }
-            "receive" => {
-                let data = iter.next()?;
-                let mut iter = data.splitn(3, |&x| x == b':');
-                let id = iter.next()?;
-                let id = core::str::from_utf8(id).ok()?;
-                let id = id.parse::<usize>().ok()?;
-                let data_id = iter.next()?;
-                let data_id = core::str::from_utf8(data_id).ok()?;
-                let data_id = data_id.parse::<usize>().ok()?;
-                let bytes = iter.next()?;
-                let bytes = core::str::from_utf8(bytes).ok()?;
-                let bytes = bytes.parse::<usize>().ok()?;
-                Some(Response::Receive(id, data_id, bytes))
-            }
-            _ => None,
-        }
+
+    /// Parse a response frame, return the request id and the response.
+    pub fn decode(data: &[u8]) -> Result<(u64, Response), ErrorCode> {
+        let (request_id, opcode, mut r) = decode_frame(data).map_err(|(_, e)| e)?;
+        let response = match opcode {
+            Opcode::Ok => Response::Ok(r.u64()?),
+            Opcode::Receive => Response::Receive {
+                session: r.u64()?,
+                data_id: r.u32()?,
+                bytes: r.u32()?,
+            },
+            Opcode::Error => Response::Error(ErrorCode::try_from(r.u16()?)?),
+            _ => return Err(ErrorCode::UnknownCommand),
+        };
+        Ok((request_id, r.finish(response)?))
     }
 }
 
-// start : domain_ident : DomainTypeRaw : domain size
-// send  : id           : data_id       : bytes          : data
-// stop  : id
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-// ok      : id
-// receive : id           : data_id       : bytes
+    #[test]
+    fn test_command_round_trip() {
+        let data = [1u8, 2, 3, 4, 5];
+        let commands = [
+            Command::Start(StartCommand {
+                register_domain_elf_ident: "null:1",
+                domain_type: 3,
+                domain_size: 4096,
+            }),
+            Command::Send(SendCommand {
+                session: 7,
+                data_id: 2,
+                checksum: checksum(&data),
+                data: &data,
+            }),
+            Command::Stop(StopCommand {
+                session: 7,
+                signature: &[0xaa; 64],
+            }),
+            Command::Update(UpdateCommand {
+                domain_ident: "null",
+                register_domain_elf_ident: "null:2",
+                domain_type: 3,
+            }),
+            Command::Load(LoadCommand {
+                register_domain_elf_ident: "null",
+                domain_ident: "null_block",
+                domain_type: 3,
+                args: "param_irq_mode=2,param_completion_time_nsec=1000",
+            }),
+            Command::Unload(UnloadCommand {
+                domain_ident: "null",
+            }),
+            Command::UnloadTree(UnloadCommand {
+                domain_ident: "logger",
+            }),
+            Command::RegisterPath(RegisterPathCommand {
+                register_domain_elf_ident: "null",
+                domain_type: 3,
+                path: "gnull.domain",
+            }),
+        ];
+        for (id, command) in commands.iter().enumerate() {
+            let bytes = command.encode(id as u64);
+            let (request_id, decoded) = Command::decode(&bytes).unwrap();
+            assert_eq!(request_id, id as u64);
+            assert_eq!(&decoded, command);
+        }
+    }
+
+    #[test]
+    fn test_bad_frame() {
+        let mut bytes = Command::Stop(StopCommand {
+            session: 1,
+            signature: &[],
+        })
+        .encode(9);
+        bytes[2] = VERSION + 1;
+        assert_eq!(
+            Command::decode(&bytes),
+            Err((9, ErrorCode::UnsupportedVersion))
+        );
+        let bytes = Command::Stop(StopCommand {
+            session: 1,
+            signature: &[],
+        })
+        .encode(9);
+        assert_eq!(
+            Command::decode(&bytes[..bytes.len() - 1]),
+            Err((9, ErrorCode::Malformed))
+        );
+        assert_eq!(Command::decode(b"stop:1"), Err((0, ErrorCode::Malformed)));
+    }
 
-// update : old_domain_ident : new_domain_ident : DomainTypeRaw
+    #[test]
+    fn test_response_round_trip() {
+        let responses = [
+            Response::Ok(3),
+            Response::Receive {
+                session: 3,
+                data_id: 1,
+                bytes: 4096,
+            },
+            Response::Error(ErrorCode::ChecksumMismatch),
+        ];
+        for response in responses {
+            let bytes = response.encode(42);
+            assert_eq!(Response::decode(&bytes), Ok((42, response)));
+        }
+    }
+
+    #[test]
+    fn test_checksum() {
+        assert_eq!(checksum(b"123456789"), 0xCBF4_3926);
+    }
+}
@@ -30,6 +30,7 @@ impl Display for DomainInfo {
             writeln!(f, "Domain ID: {}", id)?;
             writeln!(f, "  - Name: {}", data.name)?;
             writeln!(f, "  - Type: {:?}", data.ty)?;
+            writeln!(f, "  - State: {}", data.state)?;
             writeln!(f, "  - Panic count: {}", data.panic_count)?;
             writeln!(f, "  - File: {}", data.file_info.name)?;
             writeln!(f, "  - Size: {} bytes", data.file_info.size)?;
@@ -44,6 +45,64 @@ pub struct DomainDataInfo {
     pub ty: DomainTypeRaw,
     pub panic_count: usize,
     pub file_info: DomainFileInfo,
+    pub state: DomainState,
+}
+
+/// The lifecycle state of a domain instance.
+///
+/// The elf file of a domain is registered before any instance is created, it is
+/// recorded in [DomainInfo::ty_list].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DomainState {
+    /// The domain has been created from its elf file
+    Created,
+    /// The `init` of the domain returned successfully
+    Initialized,
+    /// The domain is attached to the kernel and can serve requests
+    Serving,
+    /// The domain is being replaced or reloaded
+    Updating,
+    /// The domain crashed and has not been recovered
+    Crashed,
+    /// The domain has been removed
+    Unloaded,
+}
+
+impl DomainState {
+    /// Whether the domain can move from `self` to `next`
+    pub fn can_transition_to(&self, next: DomainState) -> bool {
+        use DomainState::*;
+        matches!(
+            (self, next),
+            (Created, Initialized)
+                | (Created, Crashed)
+                | (Created, Unloaded)
+                | (Initialized, Serving)
+                | (Initialized, Crashed)
+                | (Initialized, Unloaded)
+                | (Serving, Updating)
+                | (Serving, Crashed)
+                | (Serving, Unloaded)
+                | (Updating, Serving)
+                | (Updating, Crashed)
+                | (Crashed, Updating)
+                | (Crashed, Unloaded)
+        )
+    }
+}
+
+impl Display for DomainState {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let state = match self {
+            DomainState::Created => "created",
+            DomainState::Initialized => "initialized",
+            DomainState::Serving => "serving",
+            DomainState::Updating => "updating",
+            DomainState::Crashed => "crashed",
+            DomainState::Unloaded => "unloaded",
+        };
+        write!(f, "{}", state)
+    }
 }
 
 #[derive(Debug, Clone)]
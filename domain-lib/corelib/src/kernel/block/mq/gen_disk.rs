@@ -62,6 +62,13 @@ impl<T: Operations> GenDisk<T> {
         unsafe { SafePtr::new(self.queue_data as _) }
     }
 
+    /// Borrow the queue data passed to [GenDisk::new_no_alloc] or [GenDisk::try_new]
+    pub fn queue_data(&self) -> <T::QueueData as ForeignOwnable>::Borrowed<'_> {
+        // SAFETY: `queue_data` is created by `into_foreign` and it is only released when the
+        // disk is dropped.
+        unsafe { T::QueueData::borrow(self.queue_data) }
+    }
+
     /// Try to create a new `GenDisk`
     pub fn try_new(tagset: Arc<TagSet<T>>, queue_data: T::QueueData) -> Result<Self> {
         let data = queue_data.into_foreign();
@@ -49,6 +49,11 @@ impl<const ORDER: u32> Pages<ORDER> {
     pub unsafe fn from_raw(ptr: *mut bindings::page) -> Self {
         Self { pages: ptr }
     }
+
+    /// Return the raw `struct page` pointer, the pages are still owned by `self`
+    pub fn as_ptr(&self) -> *mut bindings::page {
+        self.pages
+    }
 }
 
 impl Pages<0> {
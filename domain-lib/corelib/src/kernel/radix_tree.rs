@@ -10,7 +10,7 @@ use core::{marker::PhantomData, pin::Pin};
 use crate::{
     bindings,
     kernel::{
-        error::{to_result, KernelResult as Result},
+        error::{to_result, Error, KernelResult as Result},
         types::{ForeignOwnable, Opaque},
     },
 };
@@ -45,11 +45,19 @@ impl<V: ForeignOwnable> RadixTree<V> {
         })
     }
 
-    /// Try to insert a value into the tree
+    /// Try to insert a value into the tree, `value` is dropped if it can't be inserted
     pub fn try_insert(&mut self, key: Key, value: V) -> Result<()> {
+        self.insert_or_return(key, value).map_err(|(e, _)| e)
+    }
+
+    /// Try to insert a value into the tree, `value` is given back if it can't be inserted
+    pub fn insert_or_return(&mut self, key: Key, value: V) -> core::result::Result<(), (Error, V)> {
+        let ptr = value.into_foreign();
         // SAFETY: `self.tree` points to a valid and initialized `struct radix_tree`
-        let ret = crate::sys_radix_tree_insert(self.tree.get(), key, value.into_foreign() as _);
-        to_result(ret)
+        let ret = crate::sys_radix_tree_insert(self.tree.get(), key, ptr as _);
+        // SAFETY: `ptr` was created by `into_foreign()` above and the tree doesn't keep it
+        // when the insertion fails.
+        to_result(ret).map_err(|e| (e, unsafe { V::from_foreign(ptr) }))
     }
 
     /// Search for `key` in the map. Returns a reference to the associated
@@ -90,6 +98,40 @@ impl<V: ForeignOwnable> RadixTree<V> {
         // because this function takes a `&mut self`.
         Some(unsafe { ForeignOwnable::from_foreign(item.as_ptr()) })
     }
+
+    /// Call `f` with every key and value in the tree, in the order of the keys.
+    pub fn for_each(&self, mut f: impl FnMut(Key, V::Borrowed<'_>)) {
+        let mut iter = bindings::radix_tree_iter {
+            index: 0,
+            next_index: 0,
+            tags: 0,
+            node: core::ptr::null_mut(),
+        };
+
+        // SAFETY: Iter is valid as we allocated it on the stack above
+        let mut slot = crate::sys_radix_tree_iter_init(&mut iter, 0);
+        loop {
+            if slot.is_null() {
+                // SAFETY: Both `self.tree` and `iter` are valid
+                slot = crate::sys_radix_tree_next_chunk(self.tree.get(), &mut iter, 0);
+            }
+
+            if slot.is_null() {
+                break;
+            }
+
+            // SAFETY: `slot` is valid and points to an item created by a call to
+            // `ForeignOwnable::into_foreign()`. The tree is not changed during the borrow
+            // because this function takes a `&self`.
+            let item = unsafe { *slot };
+            f(iter.index, unsafe { V::borrow(item) });
+
+            // SAFETY: `self.tree` is valid and iter is managed by
+            // `radix_tree_next_chunk()` and `radix_tree_next_slot()`. Slot is
+            // not null.
+            slot = crate::sys_radix_tree_next_slot(slot, &mut iter, 0);
+        }
+    }
 }
 
 impl<V: ForeignOwnable> Drop for RadixTree<V> {
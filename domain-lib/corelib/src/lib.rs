@@ -15,6 +15,8 @@ use spin::Once;
 
 pub mod bindings;
 pub mod domain_info;
+// the kernel objects are not available in a host program
+#[cfg(not(feature = "host"))]
 pub mod kernel;
 
 pub type LinuxResult<T> = Result<T, LinuxErrno>;
@@ -27,9 +29,10 @@ pub trait CoreFunction: Send + Sync {
     fn sys_free_pages(&self, domain_id: u64, p: *mut u8, n: usize);
     fn sys_write_console(&self, s: &str);
     fn sys_backtrace(&self, domain_id: u64);
-    /// This func will be deleted
-    fn blk_crash_trick(&self) -> bool;
-    fn sys_get_domain(&self, name: &str) -> Option<DomainType>;
+    /// Take the panic injected into the current call of the caller, see [take_injected_panic]
+    fn sys_take_injected_panic(&self) -> bool;
+    /// Look up the domain `name`, fail with `EPERM` if the caller may not use it
+    fn sys_get_domain(&self, name: &str) -> LinuxResult<DomainType>;
     fn sys_create_domain(
         &self,
         domain_file_name: &str,
@@ -162,6 +165,7 @@ pub trait CoreFunction: Send + Sync {
 }
 
 #[cfg(feature = "core_impl")]
+#[cfg_attr(feature = "host", allow(dead_code))]
 mod core_impl {
     use alloc::sync::Arc;
     use core::any::Any;
@@ -176,10 +180,12 @@ mod core_impl {
 
     static CORE_FUNC: Once<&'static dyn CoreFunction> = Once::new();
 
+    #[cfg(not(feature = "host"))]
     extern "C" {
         fn sbss();
         fn ebss();
     }
+    #[cfg(not(feature = "host"))]
     fn clear_bss() {
         unsafe {
             core::slice::from_raw_parts_mut(
@@ -191,6 +197,8 @@ mod core_impl {
     }
 
     pub fn init(syscall: &'static dyn CoreFunction) {
+        // a host program is loaded by the system, its bss is cleared already
+        #[cfg(not(feature = "host"))]
         clear_bss();
         CORE_FUNC.call_once(|| syscall);
     }
@@ -211,12 +219,13 @@ mod core_impl {
         CORE_FUNC.get_must().sys_backtrace(domain_id);
     }
 
-    // todo!(delete)
-    pub fn blk_crash_trick() -> bool {
-        CORE_FUNC.get_must().blk_crash_trick()
+    /// Whether the tcb injected a panic into the call entering the domain, the call must
+    /// panic if it returns true.
+    pub fn take_injected_panic() -> bool {
+        CORE_FUNC.get_must().sys_take_injected_panic()
     }
 
-    pub fn get_domain(name: &str) -> Option<DomainType> {
+    pub fn get_domain(name: &str) -> LinuxResult<DomainType> {
         CORE_FUNC.get_must().sys_get_domain(name)
     }
 
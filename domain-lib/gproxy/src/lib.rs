@@ -0,0 +1,842 @@
+//! Generate the tcb side proxy of a domain interface.
+//!
+//! ```ignore
+//! #[proxy(EmptyDeviceDomainProxy, PERCPU)]
+//! pub trait EmptyDeviceDomain: Basic + DowncastSync {
+//!     fn init(&self) -> LinuxResult<()>;
+//!     fn read(&self, data: RRefVec<u8>) -> LinuxResult<RRefVec<u8>>;
+//! }
+//! ```
+//!
+//! The attribute keeps the trait as it is and exports a `gen_for_<Trait>!(runtime)` macro.
+//! The macro is expanded in the tcb, where the trait and the types used in its methods must
+//! be in scope. `runtime` is the path of the tcb module providing the loader, the domain
+//! state helpers and the proxy statistics used by the generated code, e.g.
+//! `gen_for_LogDomain!(crate::domain_proxy::runtime)`.
+//!
+//! The second argument selects how the proxy waits for the readers when the domain is
+//! replaced:
+//! - `SRCU`: every call runs inside a srcu read section, `replace` pauses the new callers
+//!   (they sleep, or spin in atomic context) and waits for the srcu grace period.
+//! - `PERCPU`: every call increases a per-cpu counter, `replace` switches the callers to a
+//!   lock path and waits the counter to be zero.
+//!
+//! In both modes the old domain stays published until its readers finish, and the new
+//! domain is only published after that. The writer sleeps while it waits, and the update
+//! fails with `EBUSY` if the readers don't finish in `update_timeout_ms`, the callers then
+//! go back to the old domain and the new one, which was never called, is dropped. The state
+//! of the old domain is handed over to the new one with `export_state`/`import_state` of
+//! `Basic` once no caller is in the old domain.
+//!
+//! `init` is forwarded without any check. The argument of `init` (if any) is saved by
+//! `init_by_box` and used again when the domain is replaced. `replace` initializes the new
+//! domain before the swap and keeps the old one if it fails. When any other call except
+//! `exit` crashes, the domain is marked crashed and reloaded later by the recovery work of
+//! the tcb, since the call may be made in atomic context. The `RRef` values passed by value
+//! are moved to the callee domain and the returned `RRef` is moved back. Every call is
+//! counted in the per-cpu `call_stats` of the proxy with its result, a sample of the calls
+//! is timed. The faults armed in `faults` are injected before the call enters the domain,
+//! an injected panic is raised by the unwind wrapper of the domain, so the domain crashes
+//! and is reloaded like a real panic.
+use proc_macro2::{Ident, Span, TokenStream};
+use quote::{format_ident, quote};
+use syn::{
+    parse::{Parse, ParseStream},
+    parse_macro_input, FnArg, GenericArgument, ItemTrait, Pat, PathArguments, ReturnType,
+    Signature, Token, TraitItem, Type,
+};
+
+#[derive(Clone, Copy, PartialEq)]
+enum SyncMode {
+    Srcu,
+    PerCpu,
+}
+
+struct ProxyAttr {
+    name: Ident,
+    mode: SyncMode,
+}
+
+impl Parse for ProxyAttr {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let name: Ident = input.parse()?;
+        let mode = if input.is_empty() {
+            SyncMode::PerCpu
+        } else {
+            input.parse::<Token![,]>()?;
+            let mode: Ident = input.parse()?;
+            match mode.to_string().as_str() {
+                "SRCU" => SyncMode::Srcu,
+                "PERCPU" => SyncMode::PerCpu,
+                _ => return Err(syn::Error::new(mode.span(), "expected `SRCU` or `PERCPU`")),
+            }
+        };
+        Ok(ProxyAttr { name, mode })
+    }
+}
+
+struct Method {
+    sig: Signature,
+    args: Vec<(Ident, Type)>,
+    /// the `T` of `LinuxResult<T>`
+    ok_ty: Type,
+}
+
+impl Method {
+    fn name(&self) -> &Ident {
+        &self.sig.ident
+    }
+    fn is(&self, name: &str) -> bool {
+        self.sig.ident == name
+    }
+}
+
+#[proc_macro_attribute]
+pub fn proxy(
+    attr: proc_macro::TokenStream,
+    item: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+    let attr = parse_macro_input!(attr as ProxyAttr);
+    let trait_def = parse_macro_input!(item as ItemTrait);
+    let generated = match gen_proxy(&attr, &trait_def) {
+        Ok(code) => code,
+        Err(e) => return e.to_compile_error().into(),
+    };
+    let macro_name = format_ident!("gen_for_{}", trait_def.ident);
+    quote!(
+        #trait_def
+
+        #[macro_export]
+        macro_rules! #macro_name {
+            ($($runtime:tt)+) => {
+                #generated
+            };
+        }
+    )
+    .into()
+}
+
+/// The module of the tcb given to `gen_for_<Trait>!`, the generated code refers to the tcb
+/// only through it.
+fn runtime() -> TokenStream {
+    quote!($($runtime)+)
+}
+
+fn collect_methods(trait_def: &ItemTrait) -> syn::Result<Vec<Method>> {
+    let mut methods = vec![];
+    for item in trait_def.items.iter() {
+        let TraitItem::Fn(func) = item else {
+            continue;
+        };
+        let sig = &func.sig;
+        if !sig.generics.params.is_empty() {
+            return Err(syn::Error::new_spanned(
+                &sig.generics,
+                "generic method is not supported by proxy",
+            ));
+        }
+        let mut inputs = sig.inputs.iter();
+        match inputs.next() {
+            Some(FnArg::Receiver(r)) if r.reference.is_some() && r.mutability.is_none() => {}
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    sig,
+                    "method of domain interface should take `&self`",
+                ))
+            }
+        }
+        let mut args = vec![];
+        for input in inputs {
+            let FnArg::Typed(pat_ty) = input else {
+                unreachable!()
+            };
+            let Pat::Ident(pat) = pat_ty.pat.as_ref() else {
+                return Err(syn::Error::new_spanned(
+                    &pat_ty.pat,
+                    "argument should be an identifier",
+                ));
+            };
+            args.push((pat.ident.clone(), pat_ty.ty.as_ref().clone()));
+        }
+        let ok_ty = linux_result_ok_type(&sig.output).ok_or_else(|| {
+            syn::Error::new_spanned(&sig.output, "method should return `LinuxResult<T>`")
+        })?;
+        methods.push(Method {
+            sig: sig.clone(),
+            args,
+            ok_ty,
+        });
+    }
+    Ok(methods)
+}
+
+fn linux_result_ok_type(output: &ReturnType) -> Option<Type> {
+    let ReturnType::Type(_, ty) = output else {
+        return None;
+    };
+    let Type::Path(path) = ty.as_ref() else {
+        return None;
+    };
+    let last = path.path.segments.last()?;
+    if last.ident != "LinuxResult" {
+        return None;
+    }
+    let PathArguments::AngleBracketed(args) = &last.arguments else {
+        return None;
+    };
+    match args.args.first()? {
+        GenericArgument::Type(ty) => Some(ty.clone()),
+        _ => None,
+    }
+}
+
+/// The types of `rref` owned by a domain, they are moved to the callee when passed by value.
+///
+/// Borrowed views like `RRefSlice` are not owned and never moved.
+const OWNED_RREF_TYPES: &[&str] = &["RRef", "RRefVec", "RRefRing", "RRefString"];
+
+/// Whether the value should be moved between domains, i.e. one of [OWNED_RREF_TYPES].
+fn is_rref(ty: &Type) -> bool {
+    match ty {
+        Type::Path(path) => path
+            .path
+            .segments
+            .last()
+            .map(|s| OWNED_RREF_TYPES.iter().any(|name| s.ident == name))
+            .unwrap_or(false),
+        _ => false,
+    }
+}
+
+fn gen_proxy(attr: &ProxyAttr, trait_def: &ItemTrait) -> syn::Result<TokenStream> {
+    let methods = collect_methods(trait_def)?;
+    let init = methods.iter().find(|m| m.is("init")).ok_or_else(|| {
+        syn::Error::new_spanned(&trait_def.ident, "domain interface should have `init`")
+    })?;
+    if init.args.len() > 1 {
+        return Err(syn::Error::new_spanned(
+            &init.sig,
+            "`init` should take at most one argument",
+        ));
+    }
+    // `init(&self, args: &T)`, T is saved by `init_by_box`
+    let init_arg = match init.args.first() {
+        Some((_, Type::Reference(r))) => Some(r.elem.as_ref().clone()),
+        Some((_, ty)) => {
+            return Err(syn::Error::new_spanned(
+                ty,
+                "the argument of `init` should be a reference",
+            ))
+        }
+        None => None,
+    };
+
+    let proxy = gen_proxy_struct(attr, trait_def, &methods, init_arg.is_some());
+    let builder = gen_proxy_builder(attr, trait_def, init_arg.as_ref());
+    let impls = gen_proxy_impl(attr, trait_def, &methods);
+    let replace = gen_replace(attr, trait_def, init_arg.as_ref());
+    let empty = gen_empty_impl(trait_def, &methods);
+    Ok(quote!(
+        #proxy
+        #builder
+        #impls
+        #replace
+        #empty
+    ))
+}
+
+fn gen_proxy_struct(
+    attr: &ProxyAttr,
+    trait_def: &ItemTrait,
+    methods: &[Method],
+    has_args: bool,
+) -> TokenStream {
+    let proxy_name = &attr.name;
+    let trait_name = &trait_def.ident;
+    let rt = runtime();
+    // the index of a method in `call_stats` and `faults` is its position in the trait
+    let method_names = methods
+        .iter()
+        .map(|m| m.name().to_string())
+        .collect::<Vec<_>>();
+    let (resource, resource_init) = if has_args {
+        (
+            quote!(resource: spin::Once<alloc::boxed::Box<dyn core::any::Any + Send + Sync>>,),
+            quote!(resource: spin::Once::new(),),
+        )
+    } else {
+        (quote!(), quote!())
+    };
+    let (fields, fields_init) = match attr.mode {
+        SyncMode::Srcu => (quote!(), quote!()),
+        SyncMode::PerCpu => (
+            quote!(
+                lock: core::pin::Pin<alloc::boxed::Box<kernel::sync::Mutex<()>>>,
+                flag: core::sync::atomic::AtomicBool,
+                counter: kernel::sync::LongLongPerCpu,
+            ),
+            quote!(
+                lock: alloc::boxed::Box::pin_init(new_mutex!(())).unwrap(),
+                flag: core::sync::atomic::AtomicBool::new(false),
+                counter: kernel::sync::LongLongPerCpu::new(),
+            ),
+        ),
+    };
+    quote!(
+        #[derive(Debug)]
+        pub struct #proxy_name {
+            domain: kernel::sync::SRcuData<alloc::boxed::Box<dyn #trait_name>>,
+            domain_loader: core::pin::Pin<
+                alloc::boxed::Box<kernel::sync::Mutex<#rt::DomainLoader>>,
+            >,
+            recovering: core::sync::atomic::AtomicBool,
+            recover_attempts: core::sync::atomic::AtomicUsize,
+            update_stats: ksync::Mutex<#rt::UpdateStats>,
+            call_stats: #rt::ProxyStats,
+            faults: #rt::FaultInjector,
+            #resource
+            #fields
+        }
+
+        impl #proxy_name {
+            pub fn new(
+                domain: alloc::boxed::Box<dyn #trait_name>,
+                domain_loader: #rt::DomainLoader,
+            ) -> Self {
+                use kernel::init::InPlaceInit;
+                #proxy_name {
+                    domain: kernel::sync::SRcuData::new(domain),
+                    domain_loader: alloc::boxed::Box::pin_init(new_mutex!(domain_loader)).unwrap(),
+                    recovering: core::sync::atomic::AtomicBool::new(false),
+                    recover_attempts: core::sync::atomic::AtomicUsize::new(0),
+                    update_stats: ksync::Mutex::new(#rt::UpdateStats::new()),
+                    call_stats: #rt::ProxyStats::new(&[#(#method_names),*]),
+                    faults: #rt::FaultInjector::new(&[#(#method_names),*]),
+                    #resource_init
+                    #fields_init
+                }
+            }
+
+            pub fn domain_loader(&self) -> #rt::DomainLoader {
+                self.domain_loader.lock().clone()
+            }
+
+            pub fn call_stats(&self) -> &#rt::ProxyStats {
+                &self.call_stats
+            }
+
+            pub fn faults(&self) -> &#rt::FaultInjector {
+                &self.faults
+            }
+        }
+    )
+}
+
+fn gen_proxy_builder(
+    attr: &ProxyAttr,
+    trait_def: &ItemTrait,
+    init_arg: Option<&Type>,
+) -> TokenStream {
+    let proxy_name = &attr.name;
+    let trait_name = &trait_def.ident;
+    let rt = runtime();
+    let empty_name = format_ident!("{}EmptyImpl", trait_name);
+    let init_by_box = match init_arg {
+        Some(ty) => quote!(
+            let args = argv
+                .downcast_ref::<#ty>()
+                .ok_or(corelib::LinuxError::EINVAL)?;
+            self.init(args)?;
+            self.resource.call_once(|| argv);
+        ),
+        None => quote!(
+            let _ = argv;
+            self.init()?;
+        ),
+    };
+    quote!(
+        impl #rt::ProxyBuilder for #proxy_name {
+            type T = alloc::boxed::Box<dyn #trait_name>;
+
+            fn build(
+                domain: Self::T,
+                domain_loader: #rt::DomainLoader,
+            ) -> Self {
+                Self::new(domain, domain_loader)
+            }
+
+            fn build_empty(domain_loader: #rt::DomainLoader) -> Self {
+                let empty = #empty_name::new(#rt::alloc_domain_id());
+                Self::new(alloc::boxed::Box::new(empty), domain_loader)
+            }
+
+            fn build_empty_no_proxy(domain_id: u64) -> Self::T {
+                alloc::boxed::Box::new(#empty_name::new(domain_id))
+            }
+
+            fn init_by_box(
+                &self,
+                argv: alloc::boxed::Box<dyn core::any::Any + Send + Sync>,
+            ) -> corelib::LinuxResult<()> {
+                #init_by_box
+                Ok(())
+            }
+        }
+    )
+}
+
+fn gen_proxy_impl(attr: &ProxyAttr, trait_def: &ItemTrait, methods: &[Method]) -> TokenStream {
+    let proxy_name = &attr.name;
+    let trait_name = &trait_def.ident;
+    let rt = runtime();
+    let call = match attr.mode {
+        SyncMode::Srcu => quote!(self.domain.read(f)),
+        SyncMode::PerCpu => quote!(if self.flag.load(core::sync::atomic::Ordering::Relaxed) {
+            let lock = self.lock.lock();
+            let r = self.domain.read_directly(f);
+            drop(lock);
+            r
+        } else {
+            self.counter.get_with(|counter| {
+                *counter += 1;
+            });
+            let r = self.domain.read_directly(f);
+            self.counter.get_with(|counter| {
+                *counter -= 1;
+            });
+            r
+        }),
+    };
+    let init_call = match attr.mode {
+        SyncMode::Srcu => quote!(read),
+        SyncMode::PerCpu => quote!(read_directly),
+    };
+
+    let domain_name = trait_name.to_string();
+    let trait_methods = methods.iter().enumerate().map(|(idx, m)| {
+        let sig = &m.sig;
+        let name = m.name();
+        let arg_names = m.args.iter().map(|(name, _)| name).collect::<Vec<_>>();
+        if m.is("init") {
+            return quote!(
+                #sig {
+                    let start = self.call_stats.start(#idx);
+                    let r = match self.faults.inject(#idx, #domain_name) {
+                        Ok(inject_panic) => self.domain.#init_call(|domain| {
+                            #rt::enter_domain(
+                                inject_panic,
+                                || interface::Basic::domain_id(&**domain),
+                                || domain.init(#(#arg_names),*),
+                            )
+                        }),
+                        Err(e) => Err(e),
+                    };
+                    self.call_stats.record(#idx, start, &r);
+                    r
+                }
+            );
+        }
+        let moved = m
+            .args
+            .iter()
+            .filter(|(_, ty)| is_rref(ty))
+            .map(|(name, _)| name)
+            .collect::<Vec<_>>();
+        let call = if moved.is_empty() {
+            quote!(self.call(|domain| {
+                #rt::enter_domain(
+                    inject_panic,
+                    || interface::Basic::domain_id(&**domain),
+                    || domain.#name(#(#arg_names),*),
+                )
+            }))
+        } else {
+            let (first, rest) = (moved[0], &moved[1..]);
+            let move_back = if is_rref(&m.ok_ty) {
+                quote!(res.map(|r| {
+                    rref::SharedData::move_to(&r, old_id);
+                    r
+                }))
+            } else {
+                quote!(res)
+            };
+            quote!({
+                let (res, old_id) = self.call(|domain| {
+                    let id = interface::Basic::domain_id(&**domain);
+                    let old_id = rref::SharedData::move_to(&#first, id);
+                    #(rref::SharedData::move_to(&#rest, id);)*
+                    let res = #rt::enter_domain(
+                        inject_panic,
+                        || id,
+                        || domain.#name(#(#arg_names),*),
+                    );
+                    (res, old_id)
+                });
+                #move_back
+            })
+        };
+        let check = if m.is("exit") {
+            quote!(r)
+        } else {
+            quote!(self.check_crash(r))
+        };
+        quote!(
+            #sig {
+                let start = self.call_stats.start(#idx);
+                let r = match self.faults.inject(#idx, #domain_name) {
+                    Ok(inject_panic) => #call,
+                    Err(e) => Err(e),
+                };
+                self.call_stats.record(#idx, start, &r);
+                #check
+            }
+        )
+    });
+
+    quote!(
+        impl interface::Basic for #proxy_name {
+            fn domain_id(&self) -> u64 {
+                self.call(|domain| interface::Basic::domain_id(&**domain))
+            }
+        }
+
+        impl #trait_name for #proxy_name {
+            #(#trait_methods)*
+        }
+
+        impl #proxy_name {
+            #[inline]
+            fn call<R>(
+                &self,
+                f: impl FnOnce(&alloc::boxed::Box<dyn #trait_name>) -> R,
+            ) -> R {
+                #call
+            }
+
+            /// Re-create the domain from its elf file and replace the current instance.
+            pub fn reload(&self) -> corelib::LinuxResult<()> {
+                let old_id = interface::Basic::domain_id(self);
+                let prev = #rt::set_domain_state(
+                    old_id,
+                    corelib::domain_info::DomainState::Updating,
+                )?;
+                let res = #rt::recreate_domain::<dyn #trait_name>(
+                    interface::DomainTypeRaw::#trait_name,
+                    &self.domain_loader(),
+                    old_id,
+                )
+                .and_then(|(new_id, new_domain, loader)| {
+                    let file_info = loader.domain_file_info();
+                    // the crashed domain can't export its state
+                    self.replace_domain(new_domain, loader, false)?;
+                    Ok((new_id, file_info))
+                });
+                let (new_id, file_info) = match res {
+                    Ok(res) => res,
+                    Err(e) => {
+                        // the old domain is kept
+                        #rt::set_domain_state(old_id, prev)?;
+                        return Err(e);
+                    }
+                };
+                #rt::reload_domain_info(old_id, new_id, file_info);
+                println!("Reload {}: {} -> {}", #domain_name, old_id, new_id);
+                Ok(())
+            }
+
+            /// Reload the domain after a crash, it's called by the recovery work.
+            ///
+            /// A failed reload leaves the domain crashed and is retried, up to
+            /// `MAX_RECOVERY_ATTEMPTS` times; after that the next crash queues it again.
+            pub fn recover(&self) {
+                use core::sync::atomic::Ordering;
+                let res = self.reload();
+                if let Err(e) = res {
+                    let attempts = self.recover_attempts.fetch_add(1, Ordering::Relaxed) + 1;
+                    pr_err!(
+                        "Failed to reload {} ({}/{}): {:?}",
+                        #domain_name,
+                        attempts,
+                        #rt::MAX_RECOVERY_ATTEMPTS,
+                        e
+                    );
+                    if attempts < #rt::MAX_RECOVERY_ATTEMPTS {
+                        #rt::retry_recovery();
+                        return;
+                    }
+                }
+                self.recover_attempts.store(0, Ordering::Relaxed);
+                self.recovering.store(false, Ordering::Release);
+            }
+
+            /// Mark the domain crashed if the call crashed and queue its reload.
+            ///
+            /// The call may be made in atomic context, so the reload runs on the workqueue.
+            /// Every crash is counted, only the first crashed caller queues the reload, until
+            /// the domain is recovered.
+            fn check_crash<R>(&self, r: corelib::LinuxResult<R>) -> corelib::LinuxResult<R> {
+                if let Err(corelib::LinuxError::DOMAINCRASH) = r {
+                    match #rt::crash_domain(interface::Basic::domain_id(self)) {
+                        Ok(()) => {
+                            if self
+                                .recovering
+                                .compare_exchange(
+                                    false,
+                                    true,
+                                    core::sync::atomic::Ordering::Acquire,
+                                    core::sync::atomic::Ordering::Relaxed,
+                                )
+                                .is_ok()
+                            {
+                                #rt::schedule_recovery();
+                            }
+                        }
+                        Err(e) => pr_err!("Failed to mark {} crashed: {:?}", #domain_name, e),
+                    }
+                }
+                r
+            }
+        }
+    )
+}
+
+fn gen_replace(attr: &ProxyAttr, trait_def: &ItemTrait, init_arg: Option<&Type>) -> TokenStream {
+    let proxy_name = &attr.name;
+    let trait_name = &trait_def.ident;
+    let rt = runtime();
+    let init_new = match init_arg {
+        Some(ty) => quote!(
+            self.resource
+                .get()
+                .and_then(|resource| resource.downcast_ref::<#ty>())
+                .ok_or(corelib::LinuxError::EINVAL)
+                .and_then(|args| new_domain.init(args))
+        ),
+        None => quote!(new_domain.init()),
+    };
+    // the old domain is still in place if the update fails with `e`, drop the new one
+    let abort = |restore: TokenStream| {
+        quote!({
+            #restore
+            pr_err!(
+                "Update to domain {} failed: {:?}, keep domain {}",
+                new_domain_id,
+                e,
+                old_id
+            );
+            // the new domain lives in its own heap, which is freed with its resources
+            core::mem::forget(new_domain);
+            #rt::discard_new_domain(old_id, new_domain_id);
+            drop(domain_loader);
+            self.update_stats.lock().record_abort(e);
+            return Err(e);
+        })
+    };
+    // refuse the update before anything is changed if the state can't be handed over
+    let check_state = |abort: &TokenStream| {
+        quote!(
+            let version = self
+                .domain
+                .read_directly(|old| interface::Basic::state_version(&**old));
+            if transfer_state && !interface::Basic::can_import_state(&*new_domain, version) {
+                pr_err!(
+                    "Domain {} can't import the state of version {}",
+                    new_domain_id,
+                    version
+                );
+                let e = corelib::LinuxError::EINVAL;
+                #abort
+            }
+        )
+    };
+    let transfer = |abort: &TokenStream| {
+        quote!(
+            if transfer_state {
+                let res = self
+                    .domain
+                    .read_directly(|old| interface::Basic::export_state(&**old))
+                    .and_then(|state| match state {
+                        Some(state) => {
+                            rref::SharedData::move_to(&state.data, new_domain_id);
+                            let res = interface::Basic::import_state(&*new_domain, &state);
+                            // give the state back, it must not be freed with the new domain
+                            if res.is_err() {
+                                rref::SharedData::move_to(&state.data, old_id);
+                            }
+                            res
+                        }
+                        None => Ok(()),
+                    });
+                if let Err(e) = res #abort
+            }
+        )
+    };
+    let body = match attr.mode {
+        SyncMode::Srcu => {
+            let abort_unpaused = abort(quote!());
+            // the callers go back to the old domain, the new one is never called
+            let abort = abort(quote!(self.domain.resume();));
+            let check_state = check_state(&abort_unpaused);
+            let transfer = transfer(&abort);
+            quote!(
+                let start = #rt::now_ns();
+                let mut loader_guard = self.domain_loader.lock();
+                let old_id = interface::Basic::domain_id(self);
+                let new_domain_id = interface::Basic::domain_id(&*new_domain);
+                #check_state
+                // init new domain
+                if let Err(e) = #init_new #abort_unpaused
+                // stop the new callers and sleep until all readers in the old domain finish,
+                // the old domain stays published until then
+                let wait_start = #rt::now_ns();
+                if !self.domain.try_pause(#rt::update_timeout_ms()) {
+                    let e = corelib::LinuxError::EBUSY;
+                    #abort_unpaused
+                }
+                let wait = #rt::now_ns() - wait_start;
+                #transfer
+                // swap domain, the paused callers continue in the new domain
+                let old_domain = self.domain.update_paused(new_domain);
+                // free old domain
+                let real_domain = alloc::boxed::Box::into_inner(old_domain);
+                core::mem::forget(real_domain);
+                #rt::free_domain_resource(
+                    old_id,
+                    #rt::FreeShared::Free,
+                );
+                *loader_guard = domain_loader;
+                self.update_stats
+                    .lock()
+                    .record(wait, #rt::now_ns() - start);
+            )
+        }
+        SyncMode::PerCpu => {
+            let abort_unlocked = abort(quote!());
+            // disable the lock path, the callers go back to the old domain
+            let abort = abort(quote!(
+                self.flag.store(false, core::sync::atomic::Ordering::Relaxed);
+                drop(w_lock);
+            ));
+            let check_state = check_state(&abort_unlocked);
+            let transfer = transfer(&abort);
+            quote!(
+                let start = #rt::now_ns();
+                let mut loader_guard = self.domain_loader.lock();
+                let old_id = interface::Basic::domain_id(self);
+                let new_domain_id = interface::Basic::domain_id(&*new_domain);
+                #check_state
+                // init the new domain while the callers still use the old one
+                if let Err(e) = #init_new #abort_unlocked
+                // The writer lock before enable the lock path
+                let w_lock = self.lock.lock();
+                // enable lock path
+                self.flag.store(true, core::sync::atomic::Ordering::Relaxed);
+
+                // sleep until all readers finish, the new callers wait on the lock path
+                let wait = match #rt::wait_quiescent(|| self.counter.sum() == 0) {
+                    Ok(wait) => wait,
+                    Err(e) => #abort,
+                };
+
+                #transfer
+
+                // swap the domain and change to normal state
+                let old_domain = self.domain.update_directly(new_domain);
+
+                // disable lock path
+                self.flag.store(false, core::sync::atomic::Ordering::Relaxed);
+                // recycle all resources
+                let real_domain = alloc::boxed::Box::into_inner(old_domain);
+                // forget the old domain, it will be dropped by the `free_domain_resource`
+                core::mem::forget(real_domain);
+
+                // We should not free the shared data here, because the shared data will be used
+                // in new domain.
+                #rt::free_domain_resource(
+                    old_id,
+                    #rt::FreeShared::NotFree(new_domain_id),
+                );
+                *loader_guard = domain_loader;
+                drop(w_lock);
+                drop(loader_guard);
+                self.update_stats
+                    .lock()
+                    .record(wait, #rt::now_ns() - start);
+            )
+        }
+    };
+    quote!(
+        impl #proxy_name {
+            /// Replace the domain with `new_domain`, the state of the old domain is handed over.
+            pub fn replace(
+                &self,
+                new_domain: alloc::boxed::Box<dyn #trait_name>,
+                domain_loader: #rt::DomainLoader,
+            ) -> corelib::LinuxResult<()> {
+                self.replace_domain(new_domain, domain_loader, true)
+            }
+
+            fn replace_domain(
+                &self,
+                new_domain: alloc::boxed::Box<dyn #trait_name>,
+                domain_loader: #rt::DomainLoader,
+                transfer_state: bool,
+            ) -> corelib::LinuxResult<()> {
+                #body
+                Ok(())
+            }
+
+            pub fn update_stats(&self) -> #rt::UpdateStats {
+                *self.update_stats.lock()
+            }
+        }
+    )
+}
+
+fn gen_empty_impl(trait_def: &ItemTrait, methods: &[Method]) -> TokenStream {
+    let trait_name = &trait_def.ident;
+    let empty_name = format_ident!("{}EmptyImpl", trait_name);
+    let empty_methods = methods.iter().map(|m| {
+        let mut sig = m.sig.clone();
+        // unused arguments
+        for input in sig.inputs.iter_mut() {
+            if let FnArg::Typed(pat_ty) = input {
+                *pat_ty.pat = Pat::Wild(syn::PatWild {
+                    attrs: vec![],
+                    underscore_token: Token![_](Span::call_site()),
+                });
+            }
+        }
+        let unit = matches!(&m.ok_ty, Type::Tuple(t) if t.elems.is_empty());
+        if (m.is("init") || m.is("exit")) && unit {
+            quote!(#sig { Ok(()) })
+        } else {
+            quote!(#sig { Err(corelib::LinuxError::ENOSYS) })
+        }
+    });
+    quote!(
+        /// The placeholder of a domain which is not loaded yet.
+        ///
+        /// It has its own domain id, so its state is tracked like the state of a real domain.
+        #[derive(Debug)]
+        pub struct #empty_name {
+            domain_id: u64,
+        }
+
+        impl #empty_name {
+            pub fn new(domain_id: u64) -> Self {
+                #empty_name { domain_id }
+            }
+        }
+
+        impl interface::Basic for #empty_name {
+            fn domain_id(&self) -> u64 {
+                self.domain_id
+            }
+        }
+
+        impl #trait_name for #empty_name {
+            #(#empty_methods)*
+        }
+    )
+}
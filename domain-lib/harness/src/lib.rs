@@ -0,0 +1,59 @@
+//! Run the domains in a host program to test them with `cargo test`.
+//!
+//! A domain crate (e.g. `null`, not the `gnull` wrapper) is linked natively into the test and
+//! its `main` is called by [load_domain] to get the domain. The libraries of the domain are
+//! initialized with [HostSyscall], [HostSharedHeap] and [HostStorage] like the TCB does, and
+//! the panics unwind by std, so `basic::catch_unwind` turns them into `DOMAINCRASH`.
+//!
+//! All domains linked into one program share the statics of `corelib`, `rref` and `storage`,
+//! so they run as one domain with the id [HOST_DOMAIN_ID]. Use [SharedData::move_to] to give
+//! a value to another domain id, e.g. to test what is reclaimed when that domain crashes.
+//!
+//! `kbind` is built with its `host` feature, so no kernel build is needed. The kernel types
+//! are opaque and the [HostSyscall] methods using them are unsupported.
+//!
+//! [SharedData::move_to]: rref::SharedData::move_to
+#![feature(allocator_api)]
+mod sheap;
+mod storage_heap;
+mod syscall;
+
+use std::sync::{LazyLock, Once};
+
+pub use sheap::HostSharedHeap;
+pub use storage_heap::{HostDataHeap, HostStorage};
+pub use syscall::HostSyscall;
+
+/// The domain id of the domains in the host program
+pub const HOST_DOMAIN_ID: u64 = 1;
+
+static HOST_SYSCALL: LazyLock<HostSyscall> = LazyLock::new(HostSyscall::new);
+static SHARED_HEAP: HostSharedHeap = HostSharedHeap::new();
+static INIT: Once = Once::new();
+
+pub fn syscall() -> &'static HostSyscall {
+    &HOST_SYSCALL
+}
+
+pub fn shared_heap() -> &'static HostSharedHeap {
+    &SHARED_HEAP
+}
+
+/// Initialize the libraries of the domains like the `main` of the domain wrapper does, it's
+/// done once per program.
+pub fn init() {
+    INIT.call_once(|| {
+        corelib::init(&*HOST_SYSCALL);
+        rref::init(&SHARED_HEAP, HOST_DOMAIN_ID);
+        storage::init_database(Box::new(HostStorage::new()));
+        storage::init_data_allocator(&HostDataHeap);
+    });
+}
+
+/// Call `main` of a domain crate linked into the program, e.g. `load_domain(null::main)`.
+///
+/// The domain is not initialized, call its `init` like the TCB does.
+pub fn load_domain<D: ?Sized>(main: fn() -> Box<D>) -> Box<D> {
+    init();
+    main()
+}
@@ -0,0 +1,147 @@
+//! The shared heap of the host program.
+//!
+//! Every allocation is kept with its owner, so a test can check what a domain leaves on the
+//! heap and reclaim it like the TCB does when the domain crashes.
+use std::{
+    alloc::Layout,
+    any::TypeId,
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+use rref::{SharedHeapAlloc, SharedHeapAllocation};
+
+struct HostAllocation {
+    allocation: SharedHeapAllocation,
+    /// The layout of the memory, it's never zero-sized
+    layout: Layout,
+    type_name: &'static str,
+}
+
+pub struct HostSharedHeap {
+    live: Mutex<BTreeMap<usize, HostAllocation>>,
+    bad_frees: AtomicUsize,
+}
+
+impl HostSharedHeap {
+    pub const fn new() -> Self {
+        Self {
+            live: Mutex::new(BTreeMap::new()),
+            bad_frees: AtomicUsize::new(0),
+        }
+    }
+
+    /// The number and the total size of the live allocations owned by `domain_id`, or by
+    /// all domains if it's `None`.
+    pub fn usage(&self, domain_id: Option<u64>) -> (usize, usize) {
+        let live = self.live.lock().unwrap();
+        live.values()
+            .filter(|value| domain_id.map_or(true, |id| value.allocation.domain_id() == id))
+            .fold((0, 0), |(count, size), value| {
+                (count + 1, size + value.allocation.layout.size())
+            })
+    }
+
+    /// The type names of the live allocations owned by `domain_id`
+    pub fn live_types(&self, domain_id: u64) -> Vec<&'static str> {
+        let live = self.live.lock().unwrap();
+        live.values()
+            .filter(|value| value.allocation.domain_id() == domain_id)
+            .map(|value| value.type_name)
+            .collect()
+    }
+
+    /// The number of frees of the pointers which are not allocated
+    pub fn bad_frees(&self) -> usize {
+        self.bad_frees.load(Ordering::Relaxed)
+    }
+
+    /// Drop and free the values owned by `domain_id` like the TCB does when the domain is
+    /// unloaded or crashes, return the number of values freed.
+    pub fn free_domain_shared_data(&self, domain_id: u64) -> usize {
+        let ptrs = self
+            .live
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, value)| value.allocation.domain_id() == domain_id)
+            .map(|(ptr, _)| *ptr)
+            .collect::<Vec<_>>();
+        let mut freed = 0;
+        for ptr in ptrs {
+            // it may be freed by the drop of the values before it, and the memory may be
+            // allocated again for another domain
+            let allocation = self
+                .live
+                .lock()
+                .unwrap()
+                .get(&ptr)
+                .map(|v| v.allocation)
+                .filter(|allocation| allocation.domain_id() == domain_id);
+            let Some(allocation) = allocation else {
+                continue;
+            };
+            allocation.drop_fn();
+            unsafe { self.dealloc(ptr as *mut u8) };
+            freed += 1;
+        }
+        freed
+    }
+}
+
+impl Default for HostSharedHeap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SharedHeapAlloc for HostSharedHeap {
+    unsafe fn alloc(
+        &self,
+        layout: Layout,
+        type_id: TypeId,
+        type_name: &'static str,
+        drop_fn: fn(TypeId, *mut u8),
+        domain_id: u64,
+    ) -> Option<SharedHeapAllocation> {
+        let mem_layout = Layout::from_size_align(layout.size().max(1), layout.align()).ok()?;
+        let value_pointer = std::alloc::alloc_zeroed(mem_layout);
+        if value_pointer.is_null() {
+            return None;
+        }
+        let allocation = SharedHeapAllocation {
+            value_pointer,
+            domain_id_pointer: Box::into_raw(Box::new(domain_id)),
+            layout,
+            type_id,
+            drop_fn,
+        };
+        self.live.lock().unwrap().insert(
+            value_pointer as usize,
+            HostAllocation {
+                allocation,
+                layout: mem_layout,
+                type_name,
+            },
+        );
+        Some(allocation)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8) {
+        let value = self.live.lock().unwrap().remove(&(ptr as usize));
+        match value {
+            Some(value) => {
+                drop(Box::from_raw(value.allocation.domain_id_pointer));
+                std::alloc::dealloc(ptr, value.layout);
+            }
+            None => {
+                // a panic here may abort the test if it's unwinding already
+                println!("<HostSharedHeap> free {:p} which is not allocated", ptr);
+                self.bad_frees.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
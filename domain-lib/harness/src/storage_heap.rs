@@ -0,0 +1,53 @@
+//! The storage of the domain data in the host program.
+use std::{
+    alloc::{AllocError, Allocator, Global, Layout},
+    any::Any,
+    collections::BTreeMap,
+    ptr::NonNull,
+    sync::{Arc, Mutex},
+};
+
+use storage::{DataStorageHeap, DomainDataStorage, SendAllocator};
+
+type ArcValueType = Arc<dyn Any + Send + Sync, DataStorageHeap>;
+
+#[derive(Default)]
+pub struct HostStorage {
+    data: Mutex<BTreeMap<String, ArcValueType>>,
+}
+
+impl HostStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl DomainDataStorage for HostStorage {
+    fn insert(&self, key: &str, value: ArcValueType) -> Option<ArcValueType> {
+        self.data.lock().unwrap().insert(key.to_string(), value)
+    }
+
+    fn get(&self, key: &str) -> Option<ArcValueType> {
+        self.data.lock().unwrap().get(key).cloned()
+    }
+
+    fn remove(&self, key: &str) -> Option<ArcValueType> {
+        self.data.lock().unwrap().remove(key)
+    }
+}
+
+/// The allocator of the domain data, it's the allocator of the host program.
+#[derive(Debug, Clone)]
+pub struct HostDataHeap;
+
+unsafe impl Allocator for HostDataHeap {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        Global.allocate(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        Global.deallocate(ptr, layout)
+    }
+}
+
+impl SendAllocator for HostDataHeap {}
@@ -0,0 +1,432 @@
+//! The [CoreFunction] of the host program.
+//!
+//! The console and the pages are backed by std, the domains are looked up in the domains
+//! registered by [HostSyscall::register_domain]. The kernel functions are not available and
+//! panic when a domain calls them.
+use std::{
+    alloc::Layout,
+    any::Any,
+    collections::{BTreeMap, HashMap},
+    io::Write,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, ThreadId},
+};
+
+use basic::DomainInfoSet;
+use corelib::{bindings::*, domain_info::DomainInfo, CoreFunction, LinuxError, LinuxResult};
+use interface::{DomainType, DomainTypeRaw};
+
+const PAGE_SIZE: usize = 4096;
+
+fn unsupported(func: &str) -> ! {
+    panic!("`{}` is not available on the host", func)
+}
+
+pub struct HostSyscall {
+    domains: Mutex<BTreeMap<String, DomainType>>,
+    console: Mutex<String>,
+    /// The pages given to the domains, address -> number of pages
+    pages: Mutex<BTreeMap<usize, usize>>,
+    panics: AtomicUsize,
+    /// The panics injected into the next calls entering a domain, by the thread injecting them
+    injected_panics: Mutex<HashMap<ThreadId, usize>>,
+    info: Arc<DomainInfoSet>,
+}
+
+impl HostSyscall {
+    pub fn new() -> Self {
+        Self {
+            domains: Mutex::new(BTreeMap::new()),
+            console: Mutex::new(String::new()),
+            pages: Mutex::new(BTreeMap::new()),
+            panics: AtomicUsize::new(0),
+            injected_panics: Mutex::new(HashMap::new()),
+            info: Arc::new(DomainInfoSet::new(DomainInfo::new())),
+        }
+    }
+
+    /// Make `domain` visible to `get_domain` as `name`, return the domain it replaces.
+    pub fn register_domain(&self, name: &str, domain: DomainType) -> Option<DomainType> {
+        self.domains
+            .lock()
+            .unwrap()
+            .insert(name.to_string(), domain)
+    }
+
+    /// Take the text written to the console since the last call
+    pub fn take_console(&self) -> String {
+        core::mem::take(&mut *self.console.lock().unwrap())
+    }
+
+    /// The number of panics reported by the domains
+    pub fn panic_count(&self) -> usize {
+        self.panics.load(Ordering::Relaxed)
+    }
+
+    /// Make the next call of this thread entering a domain through its unwind wrapper panic
+    pub fn inject_panic(&self) {
+        let mut injected = self.injected_panics.lock().unwrap();
+        *injected.entry(thread::current().id()).or_default() += 1;
+    }
+
+    /// The number of pages the domains hold
+    pub fn page_count(&self) -> usize {
+        self.pages.lock().unwrap().values().sum()
+    }
+}
+
+impl Default for HostSyscall {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CoreFunction for HostSyscall {
+    fn sys_alloc_pages(&self, _domain_id: u64, n: usize) -> *mut u8 {
+        let n = n.next_power_of_two();
+        let Ok(layout) = Layout::from_size_align(n * PAGE_SIZE, PAGE_SIZE) else {
+            return core::ptr::null_mut();
+        };
+        let page = unsafe { std::alloc::alloc_zeroed(layout) };
+        if !page.is_null() {
+            self.pages.lock().unwrap().insert(page as usize, n);
+        }
+        page
+    }
+
+    fn sys_free_pages(&self, domain_id: u64, p: *mut u8, n: usize) {
+        let n = n.next_power_of_two();
+        match self.pages.lock().unwrap().remove(&(p as usize)) {
+            Some(pages) if pages == n => unsafe {
+                std::alloc::dealloc(
+                    p,
+                    Layout::from_size_align_unchecked(n * PAGE_SIZE, PAGE_SIZE),
+                )
+            },
+            res => panic!(
+                "[Domain: {}] free {} pages at {:p}, but it holds {:?} pages there",
+                domain_id, n, p, res
+            ),
+        }
+    }
+
+    fn sys_write_console(&self, s: &str) {
+        print!("{}", s);
+        let _ = std::io::stdout().flush();
+        self.console.lock().unwrap().push_str(s);
+    }
+
+    fn sys_backtrace(&self, domain_id: u64) {
+        // std prints the backtrace of the panic if RUST_BACKTRACE is set
+        println!("[Domain: {}] panic", domain_id);
+        self.panics.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn sys_take_injected_panic(&self) -> bool {
+        let mut injected = self.injected_panics.lock().unwrap();
+        let id = thread::current().id();
+        match injected.get_mut(&id) {
+            Some(1) => injected.remove(&id).is_some(),
+            Some(n) => {
+                *n -= 1;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn sys_get_domain(&self, name: &str) -> LinuxResult<DomainType> {
+        let domains = self.domains.lock().unwrap();
+        domains.get(name).cloned().ok_or(LinuxError::ENOENT)
+    }
+
+    fn sys_create_domain(
+        &self,
+        _domain_file_name: &str,
+        _identifier: &mut [u8],
+    ) -> LinuxResult<DomainType> {
+        Err(LinuxError::ENOSYS)
+    }
+
+    fn sys_register_domain(
+        &self,
+        _ident: &str,
+        _ty: DomainTypeRaw,
+        _data: &[u8],
+    ) -> LinuxResult<()> {
+        Err(LinuxError::ENOSYS)
+    }
+
+    fn sys_update_domain(
+        &self,
+        _old_domain_name: &str,
+        _new_domain_name: &str,
+        _ty: DomainTypeRaw,
+    ) -> LinuxResult<()> {
+        Err(LinuxError::ENOSYS)
+    }
+
+    fn sys_reload_domain(&self, _domain_name: &str) -> LinuxResult<()> {
+        Err(LinuxError::ENOSYS)
+    }
+
+    fn checkout_shared_data(&self) -> LinuxResult<()> {
+        let (count, size) = crate::shared_heap().usage(None);
+        println!(
+            "<checkout_shared_data> {} allocations, {} bytes",
+            count, size
+        );
+        Ok(())
+    }
+
+    fn domain_info(&self) -> LinuxResult<Arc<dyn Any + Send + Sync>> {
+        Ok(self.info.clone())
+    }
+
+    // linux kernel func list
+    fn sys_err_ptr(&self, err: core::ffi::c_long) -> *mut core::ffi::c_void {
+        err as *mut core::ffi::c_void
+    }
+
+    fn sys_is_err(&self, ptr: *const core::ffi::c_void) -> bool {
+        // the errors are the last page of the address space, like IS_ERR
+        ptr as usize >= -4095isize as usize
+    }
+
+    fn sys_ptr_err(&self, ptr: *const core::ffi::c_void) -> core::ffi::c_long {
+        ptr as core::ffi::c_long
+    }
+
+    fn sys_errno_to_blk_status(&self, _errno: core::ffi::c_int) -> blk_status_t {
+        unsupported("sys_errno_to_blk_status")
+    }
+
+    fn sys_bio_advance_iter_single(
+        &self,
+        _bio: *const bio,
+        _iter: *mut bvec_iter,
+        _bytes: core::ffi::c_uint,
+    ) {
+        unsupported("sys_bio_advance_iter_single")
+    }
+
+    fn sys_kmap(&self, _page: *mut page) -> *mut core::ffi::c_void {
+        unsupported("sys_kmap")
+    }
+
+    fn sys_kunmap(&self, _page: *mut page) {
+        unsupported("sys_kunmap")
+    }
+
+    fn sys_kmap_atomic(&self, _page: *mut page) -> *mut core::ffi::c_void {
+        unsupported("sys_kmap_atomic")
+    }
+
+    fn sys_kunmap_atomic(&self, _address: *mut core::ffi::c_void) {
+        unsupported("sys_kunmap_atomic")
+    }
+
+    fn sys__alloc_pages(&self, _gfp: gfp_t, _order: core::ffi::c_uint) -> *mut page {
+        unsupported("sys__alloc_pages")
+    }
+
+    fn sys__free_pages(&self, _page: *mut page, _order: core::ffi::c_uint) {
+        unsupported("sys__free_pages")
+    }
+
+    fn sys__blk_mq_alloc_disk(
+        &self,
+        _set: *mut blk_mq_tag_set,
+        _queuedata: *mut core::ffi::c_void,
+        _lkclass: *mut lock_class_key,
+    ) -> *mut gendisk {
+        unsupported("sys__blk_mq_alloc_disk")
+    }
+
+    fn sys_device_add_disk(
+        &self,
+        _parent: *mut device,
+        _disk: *mut gendisk,
+        _groups: *mut *const attribute_group,
+    ) -> core::ffi::c_int {
+        unsupported("sys_device_add_disk")
+    }
+
+    fn sys_set_capacity(&self, _disk: *mut gendisk, _size: sector_t) {
+        unsupported("sys_set_capacity")
+    }
+
+    fn sys_blk_queue_logical_block_size(
+        &self,
+        _arg1: *mut request_queue,
+        _arg2: core::ffi::c_uint,
+    ) {
+        unsupported("sys_blk_queue_logical_block_size")
+    }
+
+    fn sys_blk_queue_physical_block_size(
+        &self,
+        _arg1: *mut request_queue,
+        _arg2: core::ffi::c_uint,
+    ) {
+        unsupported("sys_blk_queue_physical_block_size")
+    }
+
+    fn sys_blk_queue_flag_set(&self, _flag: core::ffi::c_uint, _q: *mut request_queue) {
+        unsupported("sys_blk_queue_flag_set")
+    }
+
+    fn sys_blk_queue_flag_clear(&self, _flag: core::ffi::c_uint, _q: *mut request_queue) {
+        unsupported("sys_blk_queue_flag_clear")
+    }
+
+    fn sys_del_gendisk(&self, _disk: *mut gendisk) {
+        unsupported("sys_del_gendisk")
+    }
+
+    fn sys_blk_mq_rq_to_pdu(&self, _rq: *mut request) -> *mut core::ffi::c_void {
+        unsupported("sys_blk_mq_rq_to_pdu")
+    }
+
+    fn sys_blk_mq_start_request(&self, _rq: *mut request) {
+        unsupported("sys_blk_mq_start_request")
+    }
+
+    fn sys_blk_mq_end_request(&self, _rq: *mut request, _status: blk_status_t) {
+        unsupported("sys_blk_mq_end_request")
+    }
+
+    fn sys_blk_mq_complete_request_remote(&self, _rq: *mut request) -> bool {
+        unsupported("sys_blk_mq_complete_request_remote")
+    }
+
+    fn sys_blk_mq_rq_from_pdu(&self, _pdu: *mut core::ffi::c_void) -> *mut request {
+        unsupported("sys_blk_mq_rq_from_pdu")
+    }
+
+    fn sys_blk_mq_alloc_tag_set(&self, _set: *mut blk_mq_tag_set) -> core::ffi::c_int {
+        unsupported("sys_blk_mq_alloc_tag_set")
+    }
+
+    fn sys_blk_mq_free_tag_set(&self, _set: *mut blk_mq_tag_set) {
+        unsupported("sys_blk_mq_free_tag_set")
+    }
+
+    fn sys__mutex_init(
+        &self,
+        _ptr: *mut mutex,
+        _name: *const core::ffi::c_char,
+        _key: *mut lock_class_key,
+    ) {
+        unsupported("sys__mutex_init")
+    }
+
+    fn sys_mutex_lock(&self, _ptr: *mut mutex) {
+        unsupported("sys_mutex_lock")
+    }
+
+    fn sys_mutex_unlock(&self, _ptr: *mut mutex) {
+        unsupported("sys_mutex_unlock")
+    }
+
+    fn sys_spin_lock_init(
+        &self,
+        _ptr: *mut spinlock_t,
+        _name: *const core::ffi::c_char,
+        _key: *mut lock_class_key,
+    ) {
+        unsupported("sys_spin_lock_init")
+    }
+
+    fn sys_spin_lock(&self, _ptr: *mut spinlock_t) {
+        unsupported("sys_spin_lock")
+    }
+
+    fn sys_spin_unlock(&self, _ptr: *mut spinlock_t) {
+        unsupported("sys_spin_unlock")
+    }
+
+    fn sys_spin_lock_irqsave(&self, _lock: *mut spinlock_t) -> core::ffi::c_ulong {
+        unsupported("sys_spin_lock_irqsave")
+    }
+
+    fn sys_spin_unlock_irqrestore(&self, _lock: *mut spinlock_t, _flags: core::ffi::c_ulong) {
+        unsupported("sys_spin_unlock_irqrestore")
+    }
+
+    fn sys_init_radix_tree(&self, _tree: *mut xarray, _gfp_mask: gfp_t) {
+        unsupported("sys_init_radix_tree")
+    }
+
+    fn sys_radix_tree_insert(
+        &self,
+        _arg1: *mut xarray,
+        _index: core::ffi::c_ulong,
+        _arg2: *mut core::ffi::c_void,
+    ) -> core::ffi::c_int {
+        unsupported("sys_radix_tree_insert")
+    }
+
+    fn sys_radix_tree_lookup(
+        &self,
+        _arg1: *const xarray,
+        _arg2: core::ffi::c_ulong,
+    ) -> *mut core::ffi::c_void {
+        unsupported("sys_radix_tree_lookup")
+    }
+
+    fn sys_radix_tree_delete(
+        &self,
+        _arg1: *mut xarray,
+        _arg2: core::ffi::c_ulong,
+    ) -> *mut core::ffi::c_void {
+        unsupported("sys_radix_tree_delete")
+    }
+
+    fn sys_radix_tree_iter_init(
+        &self,
+        _iter: *mut radix_tree_iter,
+        _start: core::ffi::c_ulong,
+    ) -> *mut *mut core::ffi::c_void {
+        unsupported("sys_radix_tree_iter_init")
+    }
+
+    fn sys_radix_tree_next_chunk(
+        &self,
+        _arg1: *const xarray,
+        _iter: *mut radix_tree_iter,
+        _flags: core::ffi::c_uint,
+    ) -> *mut *mut core::ffi::c_void {
+        unsupported("sys_radix_tree_next_chunk")
+    }
+
+    fn sys_radix_tree_next_slot(
+        &self,
+        _slot: *mut *mut core::ffi::c_void,
+        _iter: *mut radix_tree_iter,
+        _flags: core::ffi::c_uint,
+    ) -> *mut *mut core::ffi::c_void {
+        unsupported("sys_radix_tree_next_slot")
+    }
+
+    fn sys_hrtimer_init(&self, _timer: *mut hrtimer, _which_clock: clockid_t, _mode: hrtimer_mode) {
+        unsupported("sys_hrtimer_init")
+    }
+
+    fn sys_hrtimer_cancel(&self, _timer: *mut hrtimer) -> core::ffi::c_int {
+        unsupported("sys_hrtimer_cancel")
+    }
+
+    fn sys_hrtimer_start_range_ns(
+        &self,
+        _timer: *mut hrtimer,
+        _tim: ktime_t,
+        _range_ns: u64_,
+        _mode: hrtimer_mode,
+    ) {
+        unsupported("sys_hrtimer_start_range_ns")
+    }
+}
@@ -0,0 +1,103 @@
+use std::sync::{Arc, LazyLock, Mutex};
+
+use corelib::LinuxResult;
+use interface::{
+    logger::{Level, LogDomain, LogRecord},
+    DomainType, LinuxErrno,
+};
+use rref::{RRefRing, RRefString, RRefVec, SharedData};
+
+/// The logger sets the global logger in `init`, so it's loaded once for all tests.
+static LOGGER: LazyLock<Arc<dyn LogDomain>> = LazyLock::new(|| {
+    let logger: Arc<dyn LogDomain> = harness::load_domain(logger::main).into();
+    logger.init().unwrap();
+    harness::syscall().register_domain("logger", DomainType::LogDomain(logger.clone()));
+    logger
+});
+
+/// The panic count is shared by all tests, the tests checking it run one by one.
+static UNWIND: Mutex<()> = Mutex::new(());
+
+#[test]
+fn null_device() {
+    let _guard = UNWIND.lock().unwrap();
+    let null = harness::load_domain(null::main);
+    null.init().unwrap();
+    let data = null.read(RRefVec::new(0, 16)).unwrap();
+    assert!(data.iter().all(|b| *b == 1));
+    assert_eq!(null.write(&RRefVec::from_slice(b"hello")), Ok(5));
+}
+
+#[test]
+fn logger() {
+    LOGGER
+        .log(Level::Info, &RRefString::from("single message"))
+        .unwrap();
+    let ring = RRefRing::new(4);
+    for i in 0..3 {
+        let record = LogRecord::new(Level::Warn, &format!("batch message {}", i));
+        ring.push(record).unwrap();
+    }
+    let ring = LOGGER.log_batch(ring).unwrap();
+    assert!(ring.is_empty());
+    let console = harness::syscall().take_console();
+    assert!(console.contains("single message"));
+    assert!(console.contains("batch message 2"));
+}
+
+#[test]
+fn get_domain() {
+    LazyLock::force(&LOGGER);
+    assert!(matches!(
+        basic::get_domain("logger"),
+        Ok(DomainType::LogDomain(_))
+    ));
+    assert!(matches!(
+        basic::get_domain("missing"),
+        Err(LinuxErrno::ENOENT)
+    ));
+}
+
+#[test]
+fn catch_panic() {
+    let _guard = UNWIND.lock().unwrap();
+    harness::init();
+    let panics = harness::syscall().panic_count();
+    let res: LinuxResult<()> = basic::catch_unwind(|| panic!("domain panic"));
+    assert_eq!(res, Err(LinuxErrno::DOMAINCRASH));
+    assert_eq!(harness::syscall().panic_count(), panics + 1);
+}
+
+#[test]
+fn domain_call_panic() {
+    let _guard = UNWIND.lock().unwrap();
+    let null = harness::load_domain(null::main);
+    null.init().unwrap();
+    let panics = harness::syscall().panic_count();
+    harness::syscall().inject_panic();
+    assert_eq!(
+        null.read(RRefVec::new(0, 16)).err(),
+        Some(LinuxErrno::DOMAINCRASH)
+    );
+    assert_eq!(harness::syscall().panic_count(), panics + 1);
+    // the panic is taken by the crashed call, the next call runs the domain
+    assert_eq!(null.write(&RRefVec::from_slice(b"hello")), Ok(5));
+    assert_eq!(harness::syscall().panic_count(), panics + 1);
+}
+
+#[test]
+fn reclaim_crashed_domain() {
+    const CRASHED: u64 = 100;
+    harness::init();
+    let heap = harness::shared_heap();
+    let vec = RRefVec::from_slice(b"left by the crashed domain");
+    let ring: RRefRing<u64> = RRefRing::new(8);
+    vec.move_to(CRASHED);
+    ring.move_to(CRASHED);
+    // the domain crashes without dropping them
+    core::mem::forget((vec, ring));
+    assert_eq!(heap.usage(Some(CRASHED)).0, 2);
+    assert_eq!(heap.free_domain_shared_data(CRASHED), 2);
+    assert_eq!(heap.usage(Some(CRASHED)), (0, 0));
+    assert_eq!(heap.bad_frees(), 0);
+}
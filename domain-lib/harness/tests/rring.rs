@@ -0,0 +1,94 @@
+use std::{collections::BTreeSet, sync::Mutex, thread};
+
+use rref::RRefRing;
+
+#[test]
+fn full_and_empty() {
+    harness::init();
+    let ring: RRefRing<u64> = RRefRing::new(3);
+    assert_eq!(ring.capacity(), 4);
+    assert!(ring.is_empty());
+    assert_eq!(ring.pop(), None);
+    for i in 0..4 {
+        ring.push(i).unwrap();
+    }
+    assert!(ring.is_full());
+    assert_eq!(ring.push(4), Err(4));
+    assert_eq!(ring.len(), 4);
+    assert_eq!(ring.drain().collect::<Vec<_>>(), [0, 1, 2, 3]);
+    assert!(ring.is_empty());
+    assert_eq!(ring.pop(), None);
+    assert_eq!(ring.push_slice(&[5, 6, 7, 8, 9]), 4);
+    let mut buf = [0; 8];
+    assert_eq!(ring.pop_slice(&mut buf), 4);
+    assert_eq!(buf[..4], [5, 6, 7, 8]);
+}
+
+#[test]
+fn wrap_around() {
+    harness::init();
+    let ring: RRefRing<u64> = RRefRing::new(4);
+    let mut next_pop = 0;
+    // the positions pass the capacity many times with 1 to 4 values in the ring
+    for i in 0..1000u64 {
+        ring.push(i).unwrap();
+        if ring.is_full() || i % 3 == 0 {
+            while let Some(value) = ring.pop() {
+                assert_eq!(value, next_pop);
+                next_pop += 1;
+            }
+        }
+    }
+    assert_eq!(ring.drain().count() as u64, 1000 - next_pop);
+}
+
+#[test]
+fn concurrent_push_pop() {
+    const PRODUCERS: u64 = 4;
+    const CONSUMERS: usize = 4;
+    const VALUES: u64 = 10000;
+    harness::init();
+    let ring: RRefRing<u64> = RRefRing::new(8);
+    let popped = Mutex::new(Vec::new());
+    thread::scope(|s| {
+        for p in 0..PRODUCERS {
+            let ring = &ring;
+            s.spawn(move || {
+                for i in 0..VALUES {
+                    let mut value = p << 32 | i;
+                    while let Err(v) = ring.push(value) {
+                        value = v;
+                        thread::yield_now();
+                    }
+                }
+            });
+        }
+        for _ in 0..CONSUMERS {
+            let (ring, popped) = (&ring, &popped);
+            s.spawn(move || {
+                let mut values = Vec::new();
+                // the values of a producer are popped in the order they are pushed
+                let mut last = [None; PRODUCERS as usize];
+                while (popped.lock().unwrap().len() + values.len()) < (PRODUCERS * VALUES) as usize
+                {
+                    match ring.pop() {
+                        Some(value) => {
+                            let (p, i) = ((value >> 32) as usize, value & u32::MAX as u64);
+                            assert!(last[p].map_or(true, |last| last < i));
+                            last[p] = Some(i);
+                            values.push(value);
+                        }
+                        None if !values.is_empty() => popped.lock().unwrap().append(&mut values),
+                        None => thread::yield_now(),
+                    }
+                }
+                popped.lock().unwrap().append(&mut values);
+            });
+        }
+    });
+    let popped = popped.into_inner().unwrap();
+    assert_eq!(popped.len() as u64, PRODUCERS * VALUES);
+    let unique = popped.iter().collect::<BTreeSet<_>>();
+    assert_eq!(unique.len(), popped.len());
+    assert!(ring.is_empty());
+}
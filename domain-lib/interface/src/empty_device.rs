@@ -1,10 +1,11 @@
 use downcast_rs::{impl_downcast, DowncastSync};
+use gproxy::proxy;
 use rref::RRefVec;
 
 use super::LinuxResult;
 use crate::Basic;
 
-// #[proxy(EmptyDeviceDomainProxy, SRCU)]
+#[proxy(EmptyDeviceDomainProxy, PERCPU)]
 pub trait EmptyDeviceDomain: Basic + DowncastSync {
     fn init(&self) -> LinuxResult<()>;
     fn read(&self, data: RRefVec<u8>) -> LinuxResult<RRefVec<u8>>;
@@ -10,6 +10,7 @@ use alloc::sync::Arc;
 use core::{any::Any, fmt::Debug};
 
 pub use pconst::LinuxErrno;
+use rref::RRefVec;
 
 use crate::{empty_device::EmptyDeviceDomain, logger::LogDomain, null_block::BlockDeviceDomain};
 
@@ -17,6 +18,45 @@ type LinuxResult<T> = Result<T, LinuxErrno>;
 
 pub trait Basic: Send + Sync + Debug + Any {
     fn domain_id(&self) -> u64;
+
+    /// The version of the state this domain exports, `0` means it has no state to hand over.
+    fn state_version(&self) -> u32 {
+        0
+    }
+
+    /// Whether the domain can import the state of `version` exported by its old version.
+    ///
+    /// The update is refused before the swap if it returns false.
+    fn can_import_state(&self, version: u32) -> bool {
+        version == 0 || version == self.state_version()
+    }
+
+    /// Export the state which is handed over to the new version of the domain.
+    ///
+    /// The old domain is dropped without running its destructors after the update, so the
+    /// exported resources must not be released here. The update is aborted if the new domain
+    /// fails to import them.
+    fn export_state(&self) -> LinuxResult<Option<ExportedState>> {
+        Ok(None)
+    }
+
+    /// Import the state exported by the old version of the domain, it's called after `init`.
+    ///
+    /// `state.data` belongs to this domain during the call, it's given back to the old domain
+    /// if the import fails, so the resources taken over must be released before returning
+    /// an error.
+    fn import_state(&self, _state: &ExportedState) -> LinuxResult<()> {
+        Ok(())
+    }
+}
+
+/// The state handed over from a domain to its new version during an update.
+///
+/// `data` is encoded by the domain, its layout is identified by `version`.
+#[derive(Debug)]
+pub struct ExportedState {
+    pub version: u32,
+    pub data: RRefVec<u8>,
 }
 
 #[derive(Clone, Debug)]
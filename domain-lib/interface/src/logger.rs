@@ -1,16 +1,65 @@
+use core::fmt::{Debug, Formatter};
+
 use downcast_rs::{impl_downcast, DowncastSync};
-use rref::RRefVec;
+use gproxy::proxy;
+use rref::{RRefRing, RRefString};
 
 use crate::{Basic, LinuxResult};
 
+#[proxy(LogDomainProxy, SRCU)]
 pub trait LogDomain: Basic + DowncastSync {
     fn init(&self) -> LinuxResult<()>;
-    fn log(&self, level: Level, msg: &RRefVec<u8>) -> LinuxResult<()>;
+    fn log(&self, level: Level, msg: &RRefString) -> LinuxResult<()>;
+    /// Log the records of `records` until it's empty, then hand the ring back.
+    ///
+    /// The caller keeps the returned ring for the next batch instead of allocating a buffer
+    /// per message.
+    fn log_batch(&self, records: RRefRing<LogRecord>) -> LinuxResult<RRefRing<LogRecord>>;
     fn set_max_level(&self, level: LevelFilter) -> LinuxResult<()>;
 }
 
 impl_downcast!(sync LogDomain);
 
+/// The longest message a [LogRecord] keeps, longer messages are truncated.
+pub const LOG_RECORD_MSG_SIZE: usize = 240;
+
+/// A message passed to [LogDomain::log_batch]
+#[derive(Clone, Copy)]
+pub struct LogRecord {
+    pub level: Level,
+    len: usize,
+    msg: [u8; LOG_RECORD_MSG_SIZE],
+}
+
+impl LogRecord {
+    pub fn new(level: Level, msg: &str) -> Self {
+        let mut len = msg.len().min(LOG_RECORD_MSG_SIZE);
+        while !msg.is_char_boundary(len) {
+            len -= 1;
+        }
+        let mut buf = [0; LOG_RECORD_MSG_SIZE];
+        buf[..len].copy_from_slice(&msg.as_bytes()[..len]);
+        Self {
+            level,
+            len,
+            msg: buf,
+        }
+    }
+
+    pub fn msg(&self) -> &str {
+        core::str::from_utf8(&self.msg[..self.len]).unwrap_or("")
+    }
+}
+
+impl Debug for LogRecord {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("LogRecord")
+            .field("level", &self.level)
+            .field("msg", &self.msg())
+            .finish()
+    }
+}
+
 #[repr(usize)]
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
 pub enum Level {
@@ -1,8 +1,12 @@
+use core::str::FromStr;
+
 use downcast_rs::{impl_downcast, DowncastSync};
+use gproxy::proxy;
 use kbind::safe_ptr::SafePtr;
 
-use crate::{Basic, LinuxResult};
+use crate::{Basic, LinuxErrno, LinuxResult};
 
+#[proxy(BlockDeviceDomainProxy, PERCPU)]
 pub trait BlockDeviceDomain: Basic + DowncastSync {
     fn init(&self, args: &BlockArgs) -> LinuxResult<()>;
     fn tag_set_with_queue_data(&self) -> LinuxResult<(SafePtr, SafePtr)>;
@@ -63,3 +67,55 @@ impl Default for BlockArgs {
         }
     }
 }
+
+impl BlockArgs {
+    /// Check that the arguments describe a device which can be created
+    pub fn validate(&self) -> LinuxResult<()> {
+        if self.param_irq_mode > 2 {
+            return Err(LinuxErrno::EINVAL);
+        }
+        // the capacity is converted to sectors
+        if self.param_capacity_mib == 0 || self.param_capacity_mib > u64::MAX >> 11 {
+            return Err(LinuxErrno::EINVAL);
+        }
+        // the completion time is only used in timer mode
+        if self.param_irq_mode != 2 && self.param_completion_time_nsec != 0 {
+            return Err(LinuxErrno::EINVAL);
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for BlockArgs {
+    type Err = LinuxErrno;
+
+    /// Parse the `key=value` pairs separated by `,` or whitespace, the keys are the names
+    /// of the fields and the missing ones keep the default value.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        fn parse<T: FromStr>(value: &str) -> LinuxResult<T> {
+            value.parse().map_err(|_| LinuxErrno::EINVAL)
+        }
+        let mut args = BlockArgs::default();
+        for pair in s
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|pair| !pair.is_empty())
+        {
+            let (key, value) = pair.split_once('=').ok_or(LinuxErrno::EINVAL)?;
+            match key {
+                "param_memory_backed" => {
+                    args.param_memory_backed = match value {
+                        "1" | "true" => true,
+                        "0" | "false" => false,
+                        _ => return Err(LinuxErrno::EINVAL),
+                    }
+                }
+                "param_irq_mode" => args.param_irq_mode = parse(value)?,
+                "param_capacity_mib" => args.param_capacity_mib = parse(value)?,
+                "param_completion_time_nsec" => args.param_completion_time_nsec = parse(value)?,
+                _ => return Err(LinuxErrno::EINVAL),
+            }
+        }
+        args.validate()?;
+        Ok(args)
+    }
+}
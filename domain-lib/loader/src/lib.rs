@@ -21,10 +21,14 @@ use corelib::domain_info::DomainFileInfo;
 use log::{debug, trace};
 use memory_addr::VirtAddr;
 use storage::StorageArg;
-pub use vm::{DomainArea, DomainVmOps};
-use xmas_elf::{program::Type, sections::SectionData, ElfFile};
+pub use vm::{DomainArea, DomainMappingFlags, DomainVmOps};
+use xmas_elf::{
+    program::Type,
+    sections::SectionData,
+    symbol_table::{Binding, Entry},
+    ElfFile,
+};
 
-use crate::vm::DomainMappingFlags;
 const FRAME_SIZE: usize = 4096;
 type Result<T> = core::result::Result<T, &'static str>;
 
@@ -34,17 +38,27 @@ pub struct DomainLoader<V: DomainVmOps> {
     virt_start: usize,
     module_area: Option<Box<dyn DomainArea>>,
     ident: String,
-    text_section: Range<usize>,
+    segments: Vec<DomainSegment>,
+    /// The relocated functions in `.init_array`
+    init_array: Vec<usize>,
     _phantom: core::marker::PhantomData<V>,
 }
 
+/// A loaded `PT_LOAD` segment.
+#[derive(Debug, Clone)]
+pub struct DomainSegment {
+    /// The virtual address range of the segment, not aligned
+    pub range: Range<usize>,
+    pub flags: DomainMappingFlags,
+}
+
 impl<V: DomainVmOps> Debug for DomainLoader<V> {
     fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         f.debug_struct("DomainLoader")
             .field("entry", &self.entry_point)
             .field("phy_start", &self.virt_start)
             .field("ident", &self.ident)
-            .field("text_section", &self.text_section)
+            .field("segments", &self.segments)
             .finish()
     }
 }
@@ -57,7 +71,8 @@ impl<V: DomainVmOps> Clone for DomainLoader<V> {
             virt_start: 0,
             ident: self.ident.to_string(),
             module_area: None,
-            text_section: self.text_section.clone(),
+            segments: self.segments.clone(),
+            init_array: vec![],
             _phantom: core::marker::PhantomData,
         }
     }
@@ -71,7 +86,8 @@ impl<V: DomainVmOps> DomainLoader<V> {
             virt_start: 0,
             ident: ident.to_string(),
             module_area: None,
-            text_section: 0..0,
+            segments: vec![],
+            init_array: vec![],
             _phantom: core::marker::PhantomData,
         }
     }
@@ -91,6 +107,21 @@ impl<V: DomainVmOps> DomainLoader<V> {
         self.entry_point
     }
 
+    /// The page aligned range of the executable segments
+    pub fn text_section(&self) -> Range<usize> {
+        self.segments
+            .iter()
+            .filter(|seg| seg.flags.contains(DomainMappingFlags::EXECUTE))
+            .map(|seg| seg.range.clone())
+            .reduce(|a, b| a.start.min(b.start)..a.end.max(b.end))
+            .map(|r| align_down(r.start)..align_up(r.end))
+            .unwrap_or(0..0)
+    }
+
+    pub fn segments(&self) -> &[DomainSegment] {
+        &self.segments
+    }
+
     pub fn call<T: ?Sized, F>(&self, id: u64, use_old_id: Option<u64>, callback: F) -> Box<T>
     where
         F: FnOnce(
@@ -110,63 +141,185 @@ impl<V: DomainVmOps> DomainLoader<V> {
         let main =
             unsafe { core::mem::transmute::<*const (), F<T>>(self.entry_point() as *const ()) };
         let (syscall, heap, storage_arg) = callback(use_old_id);
+        for &func in self.init_array.iter() {
+            trace!("call init_array function: {:#x}", func);
+            let func = unsafe { core::mem::transmute::<usize, extern "C" fn()>(func) };
+            func();
+        }
         main(syscall, id, heap, storage_arg)
     }
 
     fn load_program(&mut self, elf: &ElfFile) -> Result<()> {
-        elf.program_iter()
+        let module_area = self
+            .module_area
+            .as_ref()
+            .ok_or("domain area is not mapped")?;
+        let module_slice = module_area.as_mut_slice();
+        for ph in elf
+            .program_iter()
             .filter(|ph| ph.get_type() == Ok(Type::Load))
-            .for_each(|ph| {
-                let start_vaddr = ph.virtual_addr() as usize + self.virt_start;
-                let end_vaddr = start_vaddr + ph.mem_size() as usize;
-                let mut permission = DomainMappingFlags::empty();
-                let ph_flags = ph.flags();
-                if ph_flags.is_read() {
-                    permission |= DomainMappingFlags::READ;
-                }
-                if ph_flags.is_write() {
-                    permission |= DomainMappingFlags::WRITE;
-                }
-                if ph_flags.is_execute() {
-                    permission |= DomainMappingFlags::EXECUTE;
-                }
-                let vaddr = VirtAddr::from(start_vaddr).align_down_4k().as_usize();
-                let end_vaddr = VirtAddr::from(end_vaddr).align_up_4k().as_usize();
-                trace!(
-                    "map range: [{:#x}-{:#x}], memsize:{}, perm:{:?}",
-                    vaddr,
-                    end_vaddr,
-                    ph.mem_size(),
-                    permission
-                );
-                let data =
-                    &elf.input[ph.offset() as usize..(ph.offset() + ph.file_size()) as usize];
-                let data_len = data.len();
-                // direct copy data to kernel space
-                let module_area = self.module_area.as_ref().unwrap();
-                let module_slice = module_area.as_mut_slice();
-                let copy_start = start_vaddr - self.virt_start;
-                module_slice[copy_start..copy_start + data_len].copy_from_slice(data);
-                info!(
-                    "copy data to {:#x}-{:#x}",
-                    copy_start,
-                    copy_start + data_len
-                );
-                if permission.contains(DomainMappingFlags::EXECUTE) {
-                    self.text_section = vaddr..end_vaddr;
-                }
+        {
+            let start_vaddr = (ph.virtual_addr() as usize)
+                .checked_add(self.virt_start)
+                .ok_or("segment is out of the domain area")?;
+            let end_vaddr = start_vaddr
+                .checked_add(ph.mem_size() as usize)
+                .ok_or("segment is out of the domain area")?;
+            let mut permission = DomainMappingFlags::empty();
+            let ph_flags = ph.flags();
+            if ph_flags.is_read() {
+                permission |= DomainMappingFlags::READ;
+            }
+            if ph_flags.is_write() {
+                permission |= DomainMappingFlags::WRITE;
+            }
+            if ph_flags.is_execute() {
+                permission |= DomainMappingFlags::EXECUTE;
+            }
+            trace!(
+                "map range: [{:#x}-{:#x}], memsize:{}, perm:{:?}",
+                start_vaddr,
+                end_vaddr,
+                ph.mem_size(),
+                permission
+            );
+            if ph.file_size() > ph.mem_size() {
+                return Err("segment file size is larger than memory size");
+            }
+            let offset = ph.offset() as usize;
+            let data_end = offset
+                .checked_add(ph.file_size() as usize)
+                .ok_or("segment is out of the elf file")?;
+            let data = elf
+                .input
+                .get(offset..data_end)
+                .ok_or("segment is out of the elf file")?;
+            let data_len = data.len();
+            // direct copy data to kernel space
+            let copy_start = start_vaddr - self.virt_start;
+            let copy_end = copy_start
+                .checked_add(data_len)
+                .ok_or("segment is out of the domain area")?;
+            module_slice
+                .get_mut(copy_start..copy_end)
+                .ok_or("segment is out of the domain area")?
+                .copy_from_slice(data);
+            info!("copy data to {:#x}-{:#x}", copy_start, copy_end);
+            self.segments.push(DomainSegment {
+                range: start_vaddr..end_vaddr,
+                flags: permission,
             });
+        }
         Ok(())
     }
+
     fn relocate_dyn(&self, elf: &ElfFile) -> Result<()> {
-        if let Ok(res) = relocate_dyn(elf, self.virt_start) {
-            trace!("Relocate_dyn {} entries", res.len());
-            res.into_iter().for_each(|kv| {
-                trace!("relocate: {:#x} -> {:#x}", kv.0, kv.1);
-                let addr = kv.0;
-                unsafe { (addr as *mut usize).write(kv.1) }
-            });
-            trace!("Relocate_dyn done");
+        let res = relocate_dyn(elf, self.virt_start)?;
+        trace!("Relocate_dyn {} entries", res.len());
+        let module_area = self
+            .module_area
+            .as_ref()
+            .ok_or("domain area is not mapped")?;
+        let module_slice = module_area.as_mut_slice();
+        for (addr, value) in res {
+            trace!("relocate: {:#x} -> {:#x}", addr, value);
+            let offset = addr
+                .checked_sub(self.virt_start)
+                .ok_or("relocation is out of the domain area")?;
+            let end = offset
+                .checked_add(core::mem::size_of::<usize>())
+                .ok_or("relocation is out of the domain area")?;
+            module_slice
+                .get_mut(offset..end)
+                .ok_or("relocation is out of the domain area")?
+                .copy_from_slice(&value.to_ne_bytes());
+        }
+        trace!("Relocate_dyn done");
+        Ok(())
+    }
+
+    /// Collect the functions in `.init_array`, they have been relocated.
+    fn collect_init_array(&mut self, elf: &ElfFile) -> Result<()> {
+        let Some(section) = elf.find_section_by_name(".init_array") else {
+            return Ok(());
+        };
+        let module_slice = self
+            .module_area
+            .as_ref()
+            .ok_or("domain area is not mapped")?
+            .as_slice();
+        let start = section.address() as usize;
+        let end = start
+            .checked_add(section.size() as usize)
+            .ok_or(".init_array is out of the domain area")?;
+        let data = module_slice
+            .get(start..end)
+            .ok_or(".init_array is out of the domain area")?;
+        self.init_array = data
+            .chunks_exact(core::mem::size_of::<usize>())
+            .map(|f| usize::from_ne_bytes(f.try_into().unwrap()))
+            // 0 and -1 are used as the placeholder
+            .filter(|&f| f != 0 && f != usize::MAX)
+            .collect();
+        debug!("init_array: {:#x?}", self.init_array);
+        Ok(())
+    }
+
+    /// Compute the permission of each page in the domain area.
+    ///
+    /// A page shared by several segments gets the union of their permissions,
+    /// a page which would be both writable and executable is rejected.
+    fn page_permissions(&self) -> Result<Vec<DomainMappingFlags>> {
+        let module_area = self
+            .module_area
+            .as_ref()
+            .ok_or("domain area is not mapped")?;
+        let pages = module_area.as_slice().len() / FRAME_SIZE;
+        let mut perms = vec![DomainMappingFlags::empty(); pages];
+        for seg in self.segments.iter() {
+            let start = (align_down(seg.range.start) - self.virt_start) / FRAME_SIZE;
+            let end = (align_up(seg.range.end) - self.virt_start) / FRAME_SIZE;
+            for perm in perms[start..end].iter_mut() {
+                *perm |= seg.flags;
+                if perm.contains(DomainMappingFlags::WRITE | DomainMappingFlags::EXECUTE) {
+                    error!("W^X violation in segment {:#x?}", seg.range);
+                    return Err("page is both writable and executable");
+                }
+            }
+        }
+        Ok(perms)
+    }
+
+    /// Apply the permission of each segment to the pages of the domain area.
+    ///
+    /// Code becomes read-only and executable, rodata read-only and not executable,
+    /// data stays writable but not executable.
+    fn protect_segments(&self) -> Result<()> {
+        let perms = self.page_permissions()?;
+        let mut index = 0;
+        while index < perms.len() {
+            let perm = perms[index];
+            let count = perms[index..].iter().take_while(|&&p| p == perm).count();
+            let start = self.virt_start + index * FRAME_SIZE;
+            index += count;
+            if perm.is_empty() {
+                // not covered by any segment
+                continue;
+            }
+            if !perm.contains(DomainMappingFlags::WRITE) {
+                V::set_memory_ro(start, count)?;
+            }
+            if perm.contains(DomainMappingFlags::EXECUTE) {
+                V::set_memory_x(start, count)?;
+            } else {
+                V::set_memory_nx(start, count)?;
+            }
+            info!(
+                "protect range: {:#x}-{:#x}, perm: {:?}",
+                start,
+                start + count * FRAME_SIZE,
+                perm
+            );
         }
         Ok(())
     }
@@ -175,18 +328,23 @@ impl<V: DomainVmOps> DomainLoader<V> {
         let data = self.data.clone();
         let elf_binary = data.as_slice();
         const ELF_MAGIC: [u8; 4] = [0x7f, b'E', b'L', b'F'];
-        if elf_binary[0..4] != ELF_MAGIC {
+        if elf_binary.get(0..4) != Some(&ELF_MAGIC) {
             return Err("not a elf file");
         }
         debug!("Domain address:{:p}", elf_binary.as_ptr());
         let elf = ElfFile::new(elf_binary)?;
         debug!("Domain type:{:?}", elf.header.pt2.type_().as_type());
-        let end_paddr = elf
+        let mut end_paddr = None;
+        for ph in elf
             .program_iter()
             .filter(|ph| ph.get_type() == Ok(Type::Load))
-            .last()
-            .map(|x| x.virtual_addr() as usize + x.mem_size() as usize)
-            .unwrap();
+        {
+            let end = (ph.virtual_addr() as usize)
+                .checked_add(ph.mem_size() as usize)
+                .ok_or("segment is too large")?;
+            end_paddr = end_paddr.max(Some(end));
+        }
+        let end_paddr = end_paddr.ok_or("no loadable segment")?;
         let end_paddr = VirtAddr::from(end_paddr).align_up(FRAME_SIZE);
         // alloc free page to map elf
         let module_area = V::map_domain_area(end_paddr.as_usize());
@@ -200,13 +358,9 @@ impl<V: DomainVmOps> DomainLoader<V> {
         self.module_area = Some(module_area);
         self.load_program(&elf)?;
         self.relocate_dyn(&elf)?;
-        // update text section permission
-        let text_pages = (self.text_section.end - self.text_section.start) / FRAME_SIZE;
-        V::set_memory_x(self.text_section.start, text_pages)?;
-        info!(
-            "set_memory_x range: {:#x}-{:#x}",
-            self.text_section.start, self.text_section.end
-        );
+        self.collect_init_array(&elf)?;
+        // update segment permission, the image can't be modified after this
+        self.protect_segments()?;
         let entry = elf.header.pt2.entry_point() as usize + region_start;
         info!("entry: {:#x}", entry);
         self.entry_point = entry;
@@ -223,36 +377,106 @@ impl<V: DomainVmOps> Drop for DomainLoader<V> {
     }
 }
 
+fn align_down(addr: usize) -> usize {
+    VirtAddr::from(addr).align_down_4k().as_usize()
+}
+
+fn align_up(addr: usize) -> usize {
+    VirtAddr::from(addr).align_up_4k().as_usize()
+}
+
+/// Compute the `(address, value)` of the relocations in `.rela.dyn` and `.rela.plt`.
 fn relocate_dyn(elf: &ElfFile, region_start: usize) -> Result<Vec<(usize, usize)>> {
-    let data = elf
-        .find_section_by_name(".rela.dyn")
-        .map(|h| h.get_data(elf).unwrap())
-        .ok_or("corrupted .rela.dyn")?;
-    let entries = match data {
-        SectionData::Rela64(entries) => entries,
-        _ => return Err("bad .rela.dyn"),
+    let dynsym = match elf.find_section_by_name(".dynsym") {
+        Some(section) => match section.get_data(elf)? {
+            SectionData::DynSymbolTable64(entries) => entries,
+            _ => return Err("bad .dynsym"),
+        },
+        None => &[],
     };
-    let mut res = vec![];
-    for entry in entries.iter() {
-        match entry.get_type() {
-            RELATIVE => {
-                let value = region_start + entry.get_addend() as usize;
-                let addr = region_start + entry.get_offset() as usize;
-                res.push((addr, value))
+    // the address of the symbol
+    let symbol = |index: u32| -> Result<usize> {
+        let sym = dynsym.get(index as usize).ok_or("invalid symbol index")?;
+        if sym.shndx() == 0 {
+            // the domain can't link to other module, only the weak symbol can be undefined
+            if sym.get_binding()? == Binding::Weak {
+                return Ok(0);
             }
-            t => unimplemented!("unknown type: {}", t),
+            error!(
+                "undefined symbol: {}",
+                sym.get_name(elf).unwrap_or("unknown")
+            );
+            return Err("undefined symbol");
+        }
+        Ok(region_start + sym.value() as usize)
+    };
+    let mut res = vec![];
+    for name in [".rela.dyn", ".rela.plt"] {
+        let Some(section) = elf.find_section_by_name(name) else {
+            continue;
+        };
+        let entries = match section.get_data(elf)? {
+            SectionData::Rela64(entries) => entries,
+            _ => return Err("bad relocation section"),
+        };
+        for entry in entries.iter() {
+            let addr = region_start + entry.get_offset() as usize;
+            let addend = entry.get_addend() as usize;
+            let value = match entry.get_type() {
+                NONE => continue,
+                RELATIVE => region_start.wrapping_add(addend),
+                ABS64 => symbol(entry.get_symbol_table_index())?.wrapping_add(addend),
+                GLOB_DAT | JUMP_SLOT => symbol(entry.get_symbol_table_index())?,
+                t => {
+                    error!("unsupported relocation type: {}", t);
+                    return Err("unsupported relocation type");
+                }
+            };
+            res.push((addr, value))
         }
     }
     Ok(res)
 }
 
+#[cfg(target_arch = "riscv64")]
+const R_RISCV_NONE: u32 = 0;
+#[cfg(target_arch = "riscv64")]
+const R_RISCV_64: u32 = 2;
 #[cfg(target_arch = "riscv64")]
 const R_RISCV_RELATIVE: u32 = 3;
+#[cfg(target_arch = "riscv64")]
+const R_RISCV_JUMP_SLOT: u32 = 5;
+
+#[cfg(target_arch = "x86_64")]
+const R_X86_64_NONE: u32 = 0;
+#[cfg(target_arch = "x86_64")]
+const R_X86_64_64: u32 = 1;
+#[cfg(target_arch = "x86_64")]
+const R_X86_64_GLOB_DAT: u32 = 6;
+#[cfg(target_arch = "x86_64")]
+const R_X86_64_JUMP_SLOT: u32 = 7;
 #[cfg(target_arch = "x86_64")]
 const R_X86_64_RELATIVE: u32 = 8;
 
+#[cfg(target_arch = "riscv64")]
+const NONE: u32 = R_RISCV_NONE;
+#[cfg(target_arch = "riscv64")]
+const ABS64: u32 = R_RISCV_64;
 #[cfg(target_arch = "riscv64")]
 const RELATIVE: u32 = R_RISCV_RELATIVE;
+// riscv has no GLOB_DAT, `R_RISCV_64` is used for GOT entries
+#[cfg(target_arch = "riscv64")]
+const GLOB_DAT: u32 = u32::MAX;
+#[cfg(target_arch = "riscv64")]
+const JUMP_SLOT: u32 = R_RISCV_JUMP_SLOT;
 
+#[cfg(target_arch = "x86_64")]
+const NONE: u32 = R_X86_64_NONE;
+#[cfg(target_arch = "x86_64")]
+const ABS64: u32 = R_X86_64_64;
 #[cfg(target_arch = "x86_64")]
 const RELATIVE: u32 = R_X86_64_RELATIVE;
+#[cfg(target_arch = "x86_64")]
+const GLOB_DAT: u32 = R_X86_64_GLOB_DAT;
+#[cfg(target_arch = "x86_64")]
+const JUMP_SLOT: u32 = R_X86_64_JUMP_SLOT;
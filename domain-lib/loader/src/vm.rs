@@ -6,7 +6,7 @@ use memory_addr::VirtAddr;
 bitflags::bitflags! {
     /// Generic page table entry flags that indicate the corresponding mapped
     /// memory region permissions and attributes.
-    #[derive(Debug,Copy, Clone)]
+    #[derive(Debug,Copy, Clone, PartialEq, Eq)]
     pub struct DomainMappingFlags: usize {
         /// The memory is readable.
         const READ          = 1 << 0;
@@ -28,4 +28,6 @@ pub trait DomainVmOps {
     fn map_domain_area(size: usize) -> Box<dyn DomainArea>;
     fn unmap_domain_area(area: Box<dyn DomainArea>);
     fn set_memory_x(start: usize, pages: usize) -> Result<(), &'static str>;
+    fn set_memory_ro(start: usize, pages: usize) -> Result<(), &'static str>;
+    fn set_memory_nx(start: usize, pages: usize) -> Result<(), &'static str>;
 }
@@ -33,18 +33,14 @@ unsafe impl GlobalAlloc for HeapAllocator {
             let need_pages = (need_pages * 2).next_power_of_two();
             // we alloc two times of the pages we need
             let new_pages = corelib::alloc_raw_pages(need_pages, domain_id());
-            assert!(!new_pages.is_null());
+            // out of memory or the domain exceeds its page quota
+            if new_pages.is_null() {
+                return new_pages;
+            }
             self.allocator
                 .lock()
                 .add_to_heap(new_pages as usize, need_pages * 4096 + new_pages as usize);
-            let ptr = self.allocator.alloc(layout);
-            assert!(
-                !ptr.is_null(),
-                "need pages: {}, layout:{:#x?}",
-                need_pages,
-                layout
-            );
-            ptr
+            self.allocator.alloc(layout)
         } else {
             ptr
         }
@@ -4,6 +4,8 @@
 #![allow(incomplete_features)]
 #![no_std]
 mod rref;
+mod rring;
+mod rstring;
 mod rvec;
 
 extern crate alloc;
@@ -13,7 +15,9 @@ use core::{
 };
 
 pub use rref::RRef;
-pub use rvec::RRefVec;
+pub use rring::RRefRing;
+pub use rstring::RRefString;
+pub use rvec::{RRefSlice, RRefVec};
 use spin::Once;
 /// A trait for types that can be shared between domains.
 ///
@@ -29,12 +33,17 @@ impl<T> !RRefable for [T] {}
 
 pub trait TypeIdentifiable {
     fn type_id() -> TypeId;
+    /// The name of the type, only for diagnostics
+    fn type_name() -> &'static str;
 }
 
 impl<T: 'static> TypeIdentifiable for T {
     fn type_id() -> TypeId {
         TypeId::of::<T>()
     }
+    fn type_name() -> &'static str {
+        core::any::type_name::<T>()
+    }
 }
 
 pub trait CustomDrop {
@@ -129,6 +138,9 @@ unsafe impl Send for SharedHeapAllocation {}
 pub trait SharedHeapAlloc: Send + Sync {
     /// Allocates a new heap allocation with the given layout, type_id, and drop function.
     ///
+    /// The allocation is charged to `domain_id`, it fails if the domain exceeds its quota.
+    /// `type_name` is only used to diagnose the leaks, the allocator copies it if needed.
+    ///
     /// # Safety
     ///
     /// The caller must ensure that the layout is valid and that the drop function is correct.
@@ -136,7 +148,9 @@ pub trait SharedHeapAlloc: Send + Sync {
         &self,
         layout: Layout,
         type_id: TypeId,
+        type_name: &'static str,
         drop_fn: fn(TypeId, *mut u8),
+        domain_id: u64,
     ) -> Option<SharedHeapAllocation>;
     /// Deallocates the heap allocation at the given pointer.
     ///
@@ -158,9 +172,25 @@ pub fn init(allocator: &'static dyn SharedHeapAlloc, domain_id: u64) {
 pub fn share_heap_alloc(
     layout: Layout,
     type_id: TypeId,
+    type_name: &'static str,
     drop_fn: fn(TypeId, *mut u8),
 ) -> Option<SharedHeapAllocation> {
-    unsafe { SHARED_HEAP.get_unchecked().alloc(layout, type_id, drop_fn) }
+    share_heap_alloc_to(layout, type_id, type_name, drop_fn, domain_id())
+}
+
+/// Like [share_heap_alloc], but charge the allocation to `domain_id` rather than this domain.
+pub(crate) fn share_heap_alloc_to(
+    layout: Layout,
+    type_id: TypeId,
+    type_name: &'static str,
+    drop_fn: fn(TypeId, *mut u8),
+    domain_id: u64,
+) -> Option<SharedHeapAllocation> {
+    unsafe {
+        SHARED_HEAP
+            .get_unchecked()
+            .alloc(layout, type_id, type_name, drop_fn, domain_id)
+    }
 }
 
 pub(crate) fn share_heap_dealloc(ptr: *mut u8) {
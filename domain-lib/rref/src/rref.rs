@@ -1,7 +1,6 @@
 //! RRef is a reference counted reference type that is used to share data between domains.
 //!
 //! Reference: https://std-dev-guide.rust-lang.org/policy/specialization.html
-use alloc::collections::BTreeMap;
 use core::{
     alloc::Layout,
     any::TypeId,
@@ -9,8 +8,6 @@ use core::{
     ops::{Deref, DerefMut},
 };
 
-use spin::Mutex;
-
 use super::{CustomDrop, RRefable, SharedData, TypeIdentifiable};
 
 #[repr(C)]
@@ -27,44 +24,67 @@ unsafe impl<T: RRefable> RRefable for RRef<T> {}
 unsafe impl<T: RRefable> Send for RRef<T> where T: Send {}
 unsafe impl<T: RRefable> Sync for RRef<T> where T: Sync {}
 
-pub fn drop_no_type<T: CustomDrop>(ptr: *mut u8) {
+/// Drop the value of type `T` left on the shared heap, e.g. when its domain is gone.
+///
+/// It is instantiated for every type, so no table of the drop functions is needed.
+pub fn drop_domain_share_data<T: CustomDrop>(_id: TypeId, ptr: *mut u8) {
     let ptr = ptr as *mut T;
     unsafe { &mut *ptr }.custom_drop();
 }
 
-type DropFn = fn(ptr: *mut u8);
-static DROP: Mutex<BTreeMap<TypeId, DropFn>> = Mutex::new(BTreeMap::new());
-
-pub fn drop_domain_share_data(id: TypeId, ptr: *mut u8) {
-    let drop = DROP.lock();
-    let drop_fn = drop.get(&id).unwrap();
-    drop_fn(ptr);
-}
-
 impl<T: RRefable> RRef<T>
 where
     T: TypeIdentifiable,
 {
     pub(crate) unsafe fn new_with_layout(value: T, layout: Layout, init: bool) -> RRef<T> {
-        let type_id = T::type_id();
-        let mut drop_guard = DROP.lock();
-        drop_guard.entry(type_id).or_insert(drop_no_type::<T>);
-        drop(drop_guard);
-
-        let allocation = match crate::share_heap_alloc(layout, type_id, drop_domain_share_data) {
-            Some(allocation) => allocation,
+        match Self::try_new_with_layout(value, layout, init) {
+            Some(rref) => rref,
             None => panic!("Shared heap allocation failed"),
+        }
+    }
+
+    /// Return `None` if the shared heap is exhausted or the domain exceeds its quota.
+    pub(crate) unsafe fn try_new_with_layout(
+        value: T,
+        layout: Layout,
+        init: bool,
+    ) -> Option<RRef<T>> {
+        let Some(rref) = Self::try_new_uninit_with_layout(layout, crate::domain_id()) else {
+            if !init {
+                // the value is not written to the heap and may be uninitialized
+                core::mem::forget(value);
+            }
+            return None;
         };
-        let value_pointer = allocation.value_pointer as *mut T;
-        *allocation.domain_id_pointer = crate::domain_id();
         if init {
-            core::ptr::write(value_pointer, value);
+            core::ptr::write(rref.value_pointer, value);
         }
-        RRef {
+        Some(rref)
+    }
+
+    /// Allocate `layout` without writing a value, the space is owned by and charged to
+    /// `domain_id`.
+    ///
+    /// # Safety
+    ///
+    /// The value is uninitialized, the caller must write it before reading or dropping it.
+    pub(crate) unsafe fn try_new_uninit_with_layout(
+        layout: Layout,
+        domain_id: u64,
+    ) -> Option<RRef<T>> {
+        let allocation = crate::share_heap_alloc_to(
+            layout,
+            T::type_id(),
+            T::type_name(),
+            drop_domain_share_data::<T>,
+            domain_id,
+        )?;
+        *allocation.domain_id_pointer = domain_id;
+        Some(RRef {
             domain_id_pointer: allocation.domain_id_pointer,
-            value_pointer,
+            value_pointer: allocation.value_pointer as *mut T,
             exist: false,
-        }
+        })
     }
 
     pub fn new(value: T) -> RRef<T> {
@@ -72,6 +92,12 @@ where
         unsafe { Self::new_with_layout(value, layout, true) }
     }
 
+    /// Like [RRef::new], but return `None` instead of panicking if the allocation fails.
+    pub fn try_new(value: T) -> Option<RRef<T>> {
+        let layout = Layout::new::<T>();
+        unsafe { Self::try_new_with_layout(value, layout, true) }
+    }
+
     pub fn new_aligned(value: T, align: usize) -> RRef<T> {
         let size = core::mem::size_of::<T>();
         let layout = unsafe { Layout::from_size_align_unchecked(size, align) };
@@ -80,24 +106,18 @@ where
 
     pub fn new_uninit() -> RRef<T> {
         let layout = Layout::new::<T>();
-        unsafe {
-            Self::new_with_layout(
-                core::mem::MaybeUninit::uninit().assume_init(),
-                layout,
-                false,
-            )
+        match unsafe { Self::try_new_uninit_with_layout(layout, crate::domain_id()) } {
+            Some(rref) => rref,
+            None => panic!("Shared heap allocation failed"),
         }
     }
 
     pub fn new_uninit_aligned(align: usize) -> RRef<T> {
         let size = core::mem::size_of::<T>();
         let layout = unsafe { Layout::from_size_align_unchecked(size, align) };
-        unsafe {
-            Self::new_with_layout(
-                core::mem::MaybeUninit::uninit().assume_init(),
-                layout,
-                false,
-            )
+        match unsafe { Self::try_new_uninit_with_layout(layout, crate::domain_id()) } {
+            Some(rref) => rref,
+            None => panic!("Shared heap allocation failed"),
         }
     }
 
@@ -124,7 +144,7 @@ impl<T: RRefable> Drop for RRef<T> {
         if self.exist {
             return;
         }
-        log::warn!("<drop> for RRef {:#x}", self.value_pointer as usize);
+        log::trace!("<drop> for RRef {:#x}", self.value_pointer as usize);
         self.custom_drop();
     }
 }
@@ -134,7 +154,7 @@ impl<T: RRefable> CustomDrop for RRef<T> {
         if self.exist {
             return;
         }
-        log::warn!("<custom_drop> for RRef {:#x}", self.value_pointer as usize);
+        log::trace!("<custom_drop> for RRef {:#x}", self.value_pointer as usize);
         let value = unsafe { &mut *self.value_pointer };
         value.custom_drop();
         crate::share_heap_dealloc(self.value_pointer as *mut u8);
@@ -0,0 +1,231 @@
+//! A bounded ring buffer on the shared heap, used to stream values between domains.
+//!
+//! The header and the slots live in one allocation of the shared heap, so the ring is owned
+//! by one domain like any [RRef] and is reclaimed by the TCB with its owner. Passing it by
+//! value to a domain interface moves it to the callee, which hands it back when it returns,
+//! so the caller can keep refilling the same ring instead of allocating a buffer per call.
+//!
+//! The slots carry a sequence number (Vyukov's bounded queue), any number of producers and
+//! consumers may share the ring.
+use core::{
+    alloc::Layout,
+    cell::UnsafeCell,
+    fmt::{Debug, Formatter},
+    marker::PhantomData,
+    mem::MaybeUninit,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use super::{CustomDrop, RRef, RRefable, SharedData, TypeIdentifiable};
+
+#[repr(C)]
+struct Slot<T> {
+    seq: AtomicUsize,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+/// The header of the ring, the slots follow it at `offset`
+#[repr(C)]
+struct RingHeader<T> {
+    head: AtomicUsize,
+    tail: AtomicUsize,
+    capacity: usize,
+    offset: usize,
+    _marker: PhantomData<T>,
+}
+
+impl<T: RRefable + Copy> CustomDrop for RingHeader<T> {
+    fn custom_drop(&mut self) {
+        // the slots hold `Copy` values, nothing to drop
+    }
+}
+
+pub struct RRefRing<T>
+where
+    T: 'static + RRefable + Copy + TypeIdentifiable,
+{
+    data: RRef<RingHeader<T>>,
+}
+
+unsafe impl<T> RRefable for RRefRing<T> where T: 'static + RRefable + Copy + TypeIdentifiable {}
+unsafe impl<T> Send for RRefRing<T> where T: 'static + RRefable + Copy + TypeIdentifiable + Send {}
+unsafe impl<T> Sync for RRefRing<T> where T: 'static + RRefable + Copy + TypeIdentifiable + Send {}
+
+impl<T> RRefRing<T>
+where
+    T: 'static + RRefable + Copy + TypeIdentifiable,
+{
+    /// Create a ring holding at least `capacity` values, the capacity is rounded up to a
+    /// power of two.
+    pub fn new(capacity: usize) -> Self {
+        match Self::try_new(capacity) {
+            Some(ring) => ring,
+            None => panic!("Shared heap allocation failed"),
+        }
+    }
+
+    /// Like [RRefRing::new], but return `None` instead of panicking if the allocation fails.
+    pub fn try_new(capacity: usize) -> Option<Self> {
+        let capacity = capacity.max(1).checked_next_power_of_two()?;
+        let (layout, offset) = Layout::new::<RingHeader<T>>()
+            .extend(Layout::array::<Slot<T>>(capacity).ok()?)
+            .ok()?;
+        let header = RingHeader {
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            capacity,
+            offset,
+            _marker: PhantomData,
+        };
+        let data = unsafe { RRef::try_new_with_layout(header, layout.pad_to_align(), true)? };
+        let ring = Self { data };
+        for idx in 0..capacity {
+            unsafe { ring.slot(idx).seq.store(idx, Ordering::Relaxed) };
+        }
+        Some(ring)
+    }
+
+    /// # Safety
+    /// `idx` must be less than the capacity.
+    unsafe fn slot(&self, idx: usize) -> &Slot<T> {
+        let header = &*self.data;
+        let slots = (self.data.value_pointer as *mut u8).add(header.offset) as *const Slot<T>;
+        &*slots.add(idx)
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.data.capacity
+    }
+
+    /// The number of values in the ring, it may be stale if other domains are using it.
+    pub fn len(&self) -> usize {
+        let tail = self.data.tail.load(Ordering::Acquire);
+        let head = self.data.head.load(Ordering::Acquire);
+        tail.wrapping_sub(head).min(self.capacity())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len() == self.capacity()
+    }
+
+    pub fn domain_id(&self) -> u64 {
+        self.data.domain_id()
+    }
+
+    /// Push `value` to the ring, return it back if the ring is full.
+    pub fn push(&self, value: T) -> Result<(), T> {
+        let mask = self.capacity() - 1;
+        let mut pos = self.data.tail.load(Ordering::Relaxed);
+        loop {
+            let slot = unsafe { self.slot(pos & mask) };
+            let seq = slot.seq.load(Ordering::Acquire);
+            let diff = seq as isize - pos as isize;
+            if diff == 0 {
+                match self.data.tail.compare_exchange_weak(
+                    pos,
+                    pos.wrapping_add(1),
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        unsafe { (*slot.value.get()).write(value) };
+                        slot.seq.store(pos.wrapping_add(1), Ordering::Release);
+                        return Ok(());
+                    }
+                    Err(cur) => pos = cur,
+                }
+            } else if diff < 0 {
+                return Err(value);
+            } else {
+                pos = self.data.tail.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Pop the oldest value of the ring, return `None` if the ring is empty.
+    pub fn pop(&self) -> Option<T> {
+        let mask = self.capacity() - 1;
+        let mut pos = self.data.head.load(Ordering::Relaxed);
+        loop {
+            let slot = unsafe { self.slot(pos & mask) };
+            let seq = slot.seq.load(Ordering::Acquire);
+            let diff = seq as isize - pos.wrapping_add(1) as isize;
+            if diff == 0 {
+                match self.data.head.compare_exchange_weak(
+                    pos,
+                    pos.wrapping_add(1),
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        let value = unsafe { (*slot.value.get()).assume_init_read() };
+                        slot.seq
+                            .store(pos.wrapping_add(mask + 1), Ordering::Release);
+                        return Some(value);
+                    }
+                    Err(cur) => pos = cur,
+                }
+            } else if diff < 0 {
+                return None;
+            } else {
+                pos = self.data.head.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Push the values of `values` in order until the ring is full, return the number pushed.
+    pub fn push_slice(&self, values: &[T]) -> usize {
+        values
+            .iter()
+            .take_while(|value| self.push(**value).is_ok())
+            .count()
+    }
+
+    /// Pop values into `buf` until it's full or the ring is empty, return the number popped.
+    pub fn pop_slice(&self, buf: &mut [T]) -> usize {
+        let mut count = 0;
+        for slot in buf.iter_mut() {
+            match self.pop() {
+                Some(value) => *slot = value,
+                None => break,
+            }
+            count += 1;
+        }
+        count
+    }
+
+    /// Pop the values of the ring until it's empty.
+    pub fn drain(&self) -> impl Iterator<Item = T> + '_ {
+        core::iter::from_fn(move || self.pop())
+    }
+}
+
+impl<T> Debug for RRefRing<T>
+where
+    T: 'static + RRefable + Copy + TypeIdentifiable,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("RRefRing")
+            .field("capacity", &self.capacity())
+            .field("len", &self.len())
+            .field("domain_id", &self.domain_id())
+            .finish()
+    }
+}
+
+impl<T: RRefable + Copy + TypeIdentifiable> CustomDrop for RRefRing<T> {
+    fn custom_drop(&mut self) {
+        log::trace!("<custom_drop> for RRefRing");
+        self.data.custom_drop();
+    }
+}
+
+impl<T: RRefable + Copy + TypeIdentifiable> SharedData for RRefRing<T> {
+    fn move_to(&self, new_domain_id: u64) -> u64 {
+        self.data.move_to(new_domain_id)
+    }
+}
@@ -0,0 +1,140 @@
+use core::{
+    fmt::{Debug, Display, Formatter},
+    ops::Deref,
+    str::Utf8Error,
+};
+
+use super::{CustomDrop, RRefVec, RRefable, SharedData};
+
+/// A string on the shared heap, it's always valid UTF-8 so the receiver doesn't check it.
+pub struct RRefString {
+    vec: RRefVec<u8>,
+}
+
+unsafe impl RRefable for RRefString {}
+unsafe impl Send for RRefString {}
+
+impl RRefString {
+    pub fn new() -> Self {
+        Self {
+            vec: RRefVec::with_capacity(0),
+        }
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            vec: RRefVec::with_capacity(capacity),
+        }
+    }
+
+    /// Like [RRefString::with_capacity], but return `None` if the allocation fails.
+    pub fn try_with_capacity(capacity: usize) -> Option<Self> {
+        Some(Self {
+            vec: RRefVec::try_with_capacity(capacity)?,
+        })
+    }
+
+    /// Check the bytes of `vec` and convert it to a string without copying.
+    pub fn from_utf8(vec: RRefVec<u8>) -> Result<Self, Utf8Error> {
+        core::str::from_utf8(vec.as_slice())?;
+        Ok(Self { vec })
+    }
+
+    pub fn as_str(&self) -> &str {
+        // the bytes are checked or come from a `str`
+        unsafe { core::str::from_utf8_unchecked(self.vec.as_slice()) }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        self.vec.as_slice()
+    }
+
+    pub fn into_bytes(self) -> RRefVec<u8> {
+        self.vec
+    }
+
+    pub fn len(&self) -> usize {
+        self.vec.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vec.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.vec.capacity()
+    }
+
+    pub fn domain_id(&self) -> u64 {
+        self.vec.domain_id()
+    }
+
+    pub fn push_str(&mut self, s: &str) {
+        self.vec.extend_from_slice(s.as_bytes());
+    }
+
+    pub fn push(&mut self, ch: char) {
+        self.push_str(ch.encode_utf8(&mut [0; 4]));
+    }
+
+    /// Shorten the string to `len` bytes, it has no effect if `len` is not less than the
+    /// length.
+    ///
+    /// # Panics
+    /// Panics if `len` is not on a char boundary.
+    pub fn truncate(&mut self, len: usize) {
+        if len < self.len() {
+            assert!(self.as_str().is_char_boundary(len));
+            self.vec.truncate(len);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.vec.clear();
+    }
+}
+
+impl Default for RRefString {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl From<&str> for RRefString {
+    fn from(s: &str) -> Self {
+        let mut string = Self::with_capacity(s.len());
+        string.push_str(s);
+        string
+    }
+}
+
+impl Deref for RRefString {
+    type Target = str;
+    fn deref(&self) -> &Self::Target {
+        self.as_str()
+    }
+}
+
+impl Display for RRefString {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        Display::fmt(self.as_str(), f)
+    }
+}
+
+impl Debug for RRefString {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl CustomDrop for RRefString {
+    fn custom_drop(&mut self) {
+        self.vec.custom_drop();
+    }
+}
+
+impl SharedData for RRefString {
+    fn move_to(&self, new_domain_id: u64) -> u64 {
+        self.vec.move_to(new_domain_id)
+    }
+}
@@ -2,18 +2,21 @@ use alloc::boxed::Box;
 use core::{
     alloc::Layout,
     fmt::{Debug, Formatter},
-    mem::MaybeUninit,
-    ops::{Deref, DerefMut, Index, IndexMut},
+    ops::{Deref, DerefMut, Index, IndexMut, RangeBounds},
 };
 
 use super::{CustomDrop, RRef, RRefable, SharedData, TypeIdentifiable};
 
+/// The least capacity a vector grows to
+const MIN_CAPACITY: usize = 8;
+
 pub struct RRefVec<T>
 where
     T: 'static + RRefable + Copy + TypeIdentifiable,
 {
     data: RRef<T>,
     size: usize,
+    capacity: usize,
     exist: bool,
 }
 unsafe impl<T> RRefable for RRefVec<T> where T: 'static + RRefable + Copy + TypeIdentifiable {}
@@ -29,37 +32,70 @@ where
         let mut vec = Self {
             data,
             size,
+            capacity: size,
             exist: false,
         };
         vec.as_mut_slice().fill(initial_value);
         vec
     }
 
+    /// Like [RRefVec::new], but return `None` instead of panicking if the allocation fails.
+    pub fn try_new(initial_value: T, size: usize) -> Option<Self> {
+        let layout = Layout::array::<T>(size).ok()?;
+        let data = unsafe { RRef::try_new_with_layout(initial_value, layout, false)? };
+        let mut vec = Self {
+            data,
+            size,
+            capacity: size,
+            exist: false,
+        };
+        vec.as_mut_slice().fill(initial_value);
+        Some(vec)
+    }
+
     pub fn new_uninit(size: usize) -> Self {
-        let layout = Layout::array::<T>(size).unwrap();
-        let data =
-            unsafe { RRef::new_with_layout(MaybeUninit::uninit().assume_init(), layout, false) };
+        let data = match Self::alloc_data(size, crate::domain_id()) {
+            Some(data) => data,
+            None => panic!("Shared heap allocation failed"),
+        };
         Self {
             data,
             size,
+            capacity: size,
             exist: false,
         }
     }
 
-    #[allow(clippy::uninit_assumed_init)]
     pub fn from_slice(slice: &[T]) -> Self {
-        let size = slice.len();
-        let layout = Layout::array::<T>(size).unwrap();
-        let data =
-            unsafe { RRef::new_with_layout(MaybeUninit::uninit().assume_init(), layout, false) };
-        let mut vec = Self {
-            data,
-            size,
-            exist: false,
-        };
-        vec.as_mut_slice().copy_from_slice(slice);
+        let mut vec = Self::with_capacity(slice.len());
+        vec.extend_from_slice(slice);
         vec
     }
+    /// Create an empty vector with space for at least `capacity` values.
+    pub fn with_capacity(capacity: usize) -> Self {
+        match Self::try_with_capacity(capacity) {
+            Some(vec) => vec,
+            None => panic!("Shared heap allocation failed"),
+        }
+    }
+
+    /// Like [RRefVec::with_capacity], but return `None` if the allocation fails.
+    pub fn try_with_capacity(capacity: usize) -> Option<Self> {
+        let capacity = capacity.max(1);
+        Some(Self {
+            data: Self::alloc_data(capacity, crate::domain_id())?,
+            size: 0,
+            capacity,
+            exist: false,
+        })
+    }
+
+    /// Allocate uninitialized space for `capacity` values, owned by and charged to `domain_id`.
+    fn alloc_data(capacity: usize, domain_id: u64) -> Option<RRef<T>> {
+        let layout = Layout::array::<T>(capacity).ok()?;
+        unsafe { RRef::try_new_uninit_with_layout(layout, domain_id) }
+    }
+
     pub fn as_slice(&self) -> &[T] {
         unsafe { core::slice::from_raw_parts(&*self.data, self.size) }
     }
@@ -77,6 +113,123 @@ where
         self.size == 0
     }
 
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn domain_id(&self) -> u64 {
+        self.data.domain_id()
+    }
+
+    /// Move the values to a new space of at least `min_capacity` values.
+    ///
+    /// The new space belongs to and is charged to the owner of the vector rather than the domain
+    /// growing it, the old space is freed unless it's borrowed from another vector.
+    fn grow(&mut self, min_capacity: usize) -> bool {
+        let capacity = min_capacity.max(self.capacity * 2).max(MIN_CAPACITY);
+        let Some(data) = Self::alloc_data(capacity, self.data.domain_id()) else {
+            return false;
+        };
+        unsafe {
+            core::ptr::copy_nonoverlapping(self.data.value_pointer, data.value_pointer, self.size)
+        };
+        let old = core::mem::replace(&mut self.data, data);
+        let borrowed_id = old.exist.then_some(old.domain_id_pointer);
+        drop(old);
+        if let Some(id) = borrowed_id {
+            let _d = unsafe { Box::from_raw(id) };
+            self.exist = false;
+        }
+        self.capacity = capacity;
+        true
+    }
+
+    /// Reserve space for at least `additional` more values.
+    pub fn reserve(&mut self, additional: usize) {
+        if !self.try_reserve(additional) {
+            panic!("Shared heap allocation failed");
+        }
+    }
+
+    /// Like [RRefVec::reserve], but return false if the allocation fails.
+    pub fn try_reserve(&mut self, additional: usize) -> bool {
+        let Some(needed) = self.size.checked_add(additional) else {
+            return false;
+        };
+        needed <= self.capacity || self.grow(needed)
+    }
+
+    pub fn push(&mut self, value: T) {
+        self.reserve(1);
+        unsafe { self.data.value_pointer.add(self.size).write(value) };
+        self.size += 1;
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        if self.size == 0 {
+            return None;
+        }
+        self.size -= 1;
+        Some(unsafe { self.data.value_pointer.add(self.size).read() })
+    }
+
+    pub fn extend_from_slice(&mut self, slice: &[T]) {
+        self.reserve(slice.len());
+        unsafe {
+            let end = self.data.value_pointer.add(self.size);
+            core::ptr::copy_nonoverlapping(slice.as_ptr(), end, slice.len());
+        }
+        self.size += slice.len();
+    }
+
+    /// Shorten the vector to `len` values, it has no effect if `len` is not less than the
+    /// length. The capacity is kept.
+    pub fn truncate(&mut self, len: usize) {
+        self.size = self.size.min(len);
+    }
+
+    pub fn clear(&mut self) {
+        self.size = 0;
+    }
+
+    /// Split the vector at `at`, return the values `[at, len)` in a new vector which is owned
+    /// by and charged to the owner of this one.
+    ///
+    /// # Panics
+    /// Panics if `at > len`.
+    pub fn split_off(&mut self, at: usize) -> Self {
+        assert!(
+            at <= self.size,
+            "`at` split index (is {at}) should be <= len"
+        );
+        let capacity = (self.size - at).max(1);
+        let data = match Self::alloc_data(capacity, self.data.domain_id()) {
+            Some(data) => data,
+            None => panic!("Shared heap allocation failed"),
+        };
+        let mut tail = Self {
+            data,
+            size: 0,
+            capacity,
+            exist: false,
+        };
+        tail.extend_from_slice(&self.as_slice()[at..]);
+        self.size = at;
+        tail
+    }
+
+    /// Borrow the values in `range` without copying them.
+    ///
+    /// # Panics
+    /// Panics if the range is out of bounds.
+    pub fn slice(&self, range: impl RangeBounds<usize>) -> RRefSlice<'_, T> {
+        let bounds = (range.start_bound().cloned(), range.end_bound().cloned());
+        RRefSlice {
+            data: &self.as_slice()[bounds],
+            domain_id: self.data.domain_id(),
+        }
+    }
+
     /// # WARNING
     /// This is a super dangerous function, it will return a slice of the data without checking the domain id
     pub fn from_other_rvec_slice(slice: &[T]) -> Self {
@@ -90,6 +243,7 @@ where
         Self {
             data: rref,
             size: slice.len(),
+            capacity: slice.len(),
             exist: true,
         }
     }
@@ -116,6 +270,7 @@ where
         f.debug_struct("RRefVec")
             .field("data", &self.data)
             .field("size", &self.size)
+            .field("capacity", &self.capacity)
             .finish()
     }
 }
@@ -129,7 +284,7 @@ impl<T: RRefable + Copy + TypeIdentifiable> Drop for RRefVec<T> {
                 return;
             }
         }
-        log::warn!("<drop> for RRefVec");
+        log::trace!("<drop> for RRefVec");
     }
 }
 
@@ -138,7 +293,7 @@ impl<T: RRefable + Copy + TypeIdentifiable> CustomDrop for RRefVec<T> {
         if self.exist {
             return;
         }
-        log::warn!("<custom_drop> for RRefVec");
+        log::trace!("<custom_drop> for RRefVec");
         self.data.custom_drop();
     }
 }
@@ -161,3 +316,64 @@ impl<T: RRefable + Copy + TypeIdentifiable> DerefMut for RRefVec<T> {
         self.as_mut_slice()
     }
 }
+
+/// A borrowed part of a [RRefVec], the values stay on the shared heap and are not copied.
+///
+/// It can't outlive the vector, so a domain receives it by reference and copies the values
+/// with [RRefSlice::to_vec] if it needs to keep them.
+pub struct RRefSlice<'a, T>
+where
+    T: 'static + RRefable + Copy + TypeIdentifiable,
+{
+    data: &'a [T],
+    domain_id: u64,
+}
+
+impl<'a, T> RRefSlice<'a, T>
+where
+    T: 'static + RRefable + Copy + TypeIdentifiable,
+{
+    pub fn as_slice(&self) -> &'a [T] {
+        self.data
+    }
+
+    /// The owner of the vector when the slice was taken
+    pub fn domain_id(&self) -> u64 {
+        self.domain_id
+    }
+
+    /// Borrow the values in `range` of this slice.
+    pub fn slice(&self, range: impl RangeBounds<usize>) -> RRefSlice<'a, T> {
+        let bounds = (range.start_bound().cloned(), range.end_bound().cloned());
+        RRefSlice {
+            data: &self.data[bounds],
+            domain_id: self.domain_id,
+        }
+    }
+
+    /// Copy the values to a new vector owned by the current domain.
+    pub fn to_vec(&self) -> RRefVec<T> {
+        let mut vec = RRefVec::with_capacity(self.data.len());
+        vec.extend_from_slice(self.data);
+        vec
+    }
+}
+
+impl<T: RRefable + Copy + TypeIdentifiable> Deref for RRefSlice<'_, T> {
+    type Target = [T];
+    fn deref(&self) -> &Self::Target {
+        self.data
+    }
+}
+
+impl<T> Debug for RRefSlice<'_, T>
+where
+    T: 'static + RRefable + Copy + TypeIdentifiable + Debug,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("RRefSlice")
+            .field("data", &self.data)
+            .field("domain_id", &self.domain_id)
+            .finish()
+    }
+}
@@ -5,11 +5,11 @@ use alloc::boxed::Box;
 
 use basic::{println, LinuxResult};
 use interface::{
-    logger::{Level, LevelFilter, LogDomain},
+    logger::{Level, LevelFilter, LogDomain, LogRecord},
     Basic,
 };
 use log::{Log, Metadata, Record};
-use rref::RRefVec;
+use rref::{RRefRing, RRefString};
 
 #[derive(Debug)]
 pub struct Logger;
@@ -29,19 +29,18 @@ impl LogDomain for Logger {
         Ok(())
     }
 
-    fn log(&self, level: Level, msg: &RRefVec<u8>) -> LinuxResult<()> {
-        let msg = core::str::from_utf8(msg.as_slice()).unwrap();
-        let level = match level {
-            Level::Error => log::Level::Error,
-            Level::Warn => log::Level::Warn,
-            Level::Info => log::Level::Info,
-            Level::Debug => log::Level::Debug,
-            Level::Trace => log::Level::Trace,
-        };
-        log::log!(level, "{}", msg);
+    fn log(&self, level: Level, msg: &RRefString) -> LinuxResult<()> {
+        log::log!(to_log_level(level), "{}", msg);
         Ok(())
     }
 
+    fn log_batch(&self, records: RRefRing<LogRecord>) -> LinuxResult<RRefRing<LogRecord>> {
+        for record in records.drain() {
+            log::log!(to_log_level(record.level), "{}", record.msg());
+        }
+        Ok(records)
+    }
+
     fn set_max_level(&self, level: LevelFilter) -> LinuxResult<()> {
         log::set_max_level(match level {
             LevelFilter::Error => log::LevelFilter::Error,
@@ -56,6 +55,16 @@ impl LogDomain for Logger {
     }
 }
 
+fn to_log_level(level: Level) -> log::Level {
+    match level {
+        Level::Error => log::Level::Error,
+        Level::Warn => log::Level::Warn,
+        Level::Info => log::Level::Info,
+        Level::Debug => log::Level::Debug,
+        Level::Trace => log::Level::Trace,
+    }
+}
+
 struct SimpleLogger;
 
 impl Log for SimpleLogger {
@@ -94,9 +103,12 @@ impl LogDomain for UnwindWrap {
     fn init(&self) -> LinuxResult<()> {
         self.0.init()
     }
-    fn log(&self, level: Level, msg: &RRefVec<u8>) -> LinuxResult<()> {
+    fn log(&self, level: Level, msg: &RRefString) -> LinuxResult<()> {
         basic::catch_unwind(|| self.0.log(level, msg))
     }
+    fn log_batch(&self, records: RRefRing<LogRecord>) -> LinuxResult<RRefRing<LogRecord>> {
+        basic::catch_unwind(|| self.0.log_batch(records))
+    }
     fn set_max_level(&self, level: LevelFilter) -> LinuxResult<()> {
         basic::catch_unwind(|| self.0.set_max_level(level))
     }
@@ -3,10 +3,8 @@
 extern crate alloc;
 
 use alloc::boxed::Box;
-use alloc::string::String;
 use core::fmt::Debug;
-use core::sync::atomic::AtomicBool;
-use basic::{println, LinuxResult};
+use basic::LinuxResult;
 use interface::{empty_device::EmptyDeviceDomain, Basic};
 use rref::RRefVec;
 
@@ -29,12 +27,6 @@ impl EmptyDeviceDomain for NullDeviceDomainImpl {
         Ok(data)
     }
     fn write(&self, data: &RRefVec<u8>) -> LinuxResult<usize> {
-        static FLAG: AtomicBool = AtomicBool::new(true);
-        if FLAG.load(core::sync::atomic::Ordering::Relaxed) {
-            println!("NullDeviceDomainImpl::read: panic test");
-            FLAG.store(false, core::sync::atomic::Ordering::Relaxed);
-            bar();
-        }
         Ok(data.len())
     }
 }
@@ -67,25 +59,3 @@ pub fn main() -> Box<dyn EmptyDeviceDomain> {
     Box::new(UnwindWrap::new(NullDeviceDomainImpl))
 }
 
-
-#[derive(Debug)]
-struct PrintOnDrop(String);
-
-impl Drop for PrintOnDrop {
-    fn drop(&mut self) {
-        println!("dropped: {:?}", self.0);
-    }
-}
-
-fn foo() {
-    panic!("panic at foo\n");
-}
-
-#[inline(never)]
-fn bar() {
-    use alloc::string::String;
-    let p1 = PrintOnDrop(String::from("PrintOnDrop1"));
-    let p2 = PrintOnDrop(String::from("PrintOnDrop2"));
-    println!("p1: {:?}, p2: {:?}", p1, p2);
-    foo()
-}
@@ -1,4 +1,4 @@
-use alloc::{boxed::Box, sync::Arc};
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
 use core::{fmt::Debug, pin::Pin};
 
 use basic::{impl_has_timer, kernel::{
@@ -81,6 +81,75 @@ impl NullBlkDomain {
     }
 }
 
+/// The version of the state handed over to the next version of the domain.
+///
+/// The state is a list of `(index, struct page)` pairs of the memory backed disk, both are
+/// encoded as little endian `u64`. Change it if the encoding changes.
+pub const STATE_VERSION: u32 = 1;
+
+const STATE_ENTRY_SIZE: usize = 16;
+
+impl NullBlkDomain {
+    /// Encode the stored pages, they are still owned by this domain.
+    pub fn export_state(&self) -> Vec<u8> {
+        let disk = self.disk.lock();
+        let tree = disk.queue_data().tree.lock_irqsave();
+        let mut data = Vec::new();
+        tree.for_each(|idx, page| {
+            data.extend_from_slice(&idx.to_le_bytes());
+            data.extend_from_slice(&(page.as_ptr() as u64).to_le_bytes());
+        });
+        println!("Export {} pages", data.len() / STATE_ENTRY_SIZE);
+        data
+    }
+
+    /// Take over the pages exported by the old version of the domain.
+    ///
+    /// The pages still belong to the old domain if this fails, so the pages inserted so
+    /// far are taken out of the tree again without being freed.
+    pub fn import_state(&self, data: &[u8]) -> KernelResult<()> {
+        if data.len() % STATE_ENTRY_SIZE != 0 {
+            return Err(error::linux_err::EINVAL);
+        }
+        let disk = self.disk.lock();
+        let mut tree = disk.queue_data().tree.lock_irqsave();
+        let mut imported = 0;
+        let res = data
+            .chunks_exact(STATE_ENTRY_SIZE)
+            .try_for_each(|entry| -> KernelResult<()> {
+                let (idx, page) = decode_state_entry(entry);
+                // allocate the box first, the page must not be freed if the allocation fails
+                let page = Box::write(Box::try_new_uninit()?, unsafe {
+                    Pages::<0>::from_raw(page as *mut _)
+                });
+                if let Err((e, page)) = tree.insert_or_return(idx, page) {
+                    core::mem::forget(*page);
+                    return Err(e);
+                }
+                imported += 1;
+                Ok(())
+            });
+        if let Err(e) = res {
+            for entry in data.chunks_exact(STATE_ENTRY_SIZE).take(imported) {
+                let (idx, _) = decode_state_entry(entry);
+                if let Some(page) = tree.remove(idx) {
+                    core::mem::forget(*page);
+                }
+            }
+            println!("Import failed, rolled back {} pages", imported);
+            return Err(e);
+        }
+        println!("Import {} pages", imported);
+        Ok(())
+    }
+}
+
+fn decode_state_entry(entry: &[u8]) -> (u64, u64) {
+    let idx = u64::from_le_bytes(entry[..8].try_into().unwrap());
+    let page = u64::from_le_bytes(entry[8..].try_into().unwrap());
+    (idx, page)
+}
+
 impl Drop for NullBlkDomain {
     fn drop(&mut self) {
         println!("Dropping NullBlkDomain");
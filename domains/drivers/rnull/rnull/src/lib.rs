@@ -10,11 +10,12 @@ use core::fmt::Debug;
 use basic::{kernel::block::mq::OperationsConverter, println, LinuxError, LinuxResult, SafePtr};
 use interface::{
     null_block::{BlockArgs, BlockDeviceDomain},
-    Basic,
+    Basic, ExportedState,
 };
+use rref::RRefVec;
 use spin::Mutex;
 
-use crate::block_domain::{NullBlkDevice, NullBlkDomain};
+use crate::block_domain::{NullBlkDevice, NullBlkDomain, STATE_VERSION};
 
 #[derive(Debug)]
 struct NullDeviceDomainImpl {
@@ -33,6 +34,34 @@ impl Basic for NullDeviceDomainImpl {
     fn domain_id(&self) -> u64 {
         rref::domain_id()
     }
+
+    fn state_version(&self) -> u32 {
+        STATE_VERSION
+    }
+
+    fn export_state(&self) -> LinuxResult<Option<ExportedState>> {
+        let blk = self.block.lock();
+        let Some(blk) = blk.as_ref() else {
+            return Ok(None);
+        };
+        let data = blk.export_state();
+        Ok(Some(ExportedState {
+            version: STATE_VERSION,
+            data: RRefVec::from_slice(&data),
+        }))
+    }
+
+    fn import_state(&self, state: &ExportedState) -> LinuxResult<()> {
+        if state.version != STATE_VERSION {
+            return Err(LinuxError::EINVAL);
+        }
+        let blk = self.block.lock();
+        let blk = blk.as_ref().ok_or(LinuxError::EINVAL)?;
+        blk.import_state(state.data.as_slice()).map_err(|e| {
+            println!("NullBlkModule import_state error: {:?}", e);
+            LinuxError::EINVAL
+        })
+    }
 }
 
 impl BlockDeviceDomain for NullDeviceDomainImpl {
@@ -143,6 +172,18 @@ impl Basic for UnwindWrap {
     fn domain_id(&self) -> u64 {
         self.0.domain_id()
     }
+
+    fn state_version(&self) -> u32 {
+        self.0.state_version()
+    }
+
+    fn export_state(&self) -> LinuxResult<Option<ExportedState>> {
+        basic::catch_unwind(|| self.0.export_state())
+    }
+
+    fn import_state(&self, state: &ExportedState) -> LinuxResult<()> {
+        basic::catch_unwind(|| self.0.import_state(state))
+    }
 }
 
 impl BlockDeviceDomain for UnwindWrap{
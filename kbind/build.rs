@@ -39,6 +39,9 @@ fn main() {
     println!("cargo:rerun-if-env-changed=c_flags");
     println!("cargo:rerun-if-changed=src/bindings_helper.h");
 
+    if env::var_os("CARGO_FEATURE_HOST").is_some() {
+        return;
+    }
     let kernel_dir = env::var("KDIR");
     if kernel_dir.is_err() {
         return;
@@ -21,6 +21,10 @@
 #include <linux/blkdev.h>
 #include <linux/pagemap.h>
 #include <linux/srcu.h>
+#include <linux/delay.h>
+#include <linux/kernel_read_file.h>
+#include <linux/workqueue.h>
+#include <linux/capability.h>
 // Bindgen gets confused at certain things
 //
 const gfp_t BINDINGS_GFP_KERNEL = GFP_KERNEL;
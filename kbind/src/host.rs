@@ -0,0 +1,46 @@
+//! The kernel types named by the interface of the domains, for a host program without a
+//! kernel build. They are opaque, a host program never touches the kernel objects.
+#![allow(non_camel_case_types)]
+
+macro_rules! opaque_types {
+    ($($name:ident),* $(,)?) => {
+        $(
+            #[repr(C)]
+            #[derive(Debug)]
+            pub struct $name {
+                _private: [u8; 0],
+            }
+        )*
+    };
+}
+
+opaque_types!(
+    attribute_group,
+    bio,
+    blk_mq_tag_set,
+    bvec_iter,
+    device,
+    gendisk,
+    hrtimer,
+    lock_class_key,
+    mutex,
+    page,
+    radix_tree_iter,
+    request,
+    request_queue,
+    spinlock_t,
+    xarray,
+);
+
+pub type u64_ = u64;
+pub type gfp_t = core::ffi::c_uint;
+pub type slab_flags_t = core::ffi::c_uint;
+pub type loff_t = core::ffi::c_longlong;
+pub type sector_t = u64;
+pub type blk_status_t = u8;
+pub type clockid_t = core::ffi::c_int;
+pub type ktime_t = i64;
+pub type hrtimer_mode = core::ffi::c_uint;
+
+pub const PAGE_SHIFT: u32 = 12;
+pub const BINDINGS_GFP_KERNEL: gfp_t = 3264;
@@ -9,11 +9,16 @@
     unreachable_pub,
     unsafe_op_in_unsafe_fn
 )]
-
+#[cfg(not(feature = "host"))]
 mod bindings {
     include!("bindings_c.rs");
 }
+#[cfg(not(feature = "host"))]
 pub use bindings::*;
+#[cfg(feature = "host")]
+mod host;
+#[cfg(feature = "host")]
+pub use host::*;
 pub mod safe_ptr;
 
 pub const GFP_KERNEL: gfp_t = BINDINGS_GFP_KERNEL;
@@ -110,7 +110,13 @@ fn build_helper(kernel_args: &Vec<String>, target: &str) {
     builder.compile("helpers");
 }
 
-const INCLUDE_FUNCS: &[&str] = &["module_alloc", "module_memfree", "set_memory_x"];
+const INCLUDE_FUNCS: &[&str] = &[
+    "module_alloc",
+    "module_memfree",
+    "set_memory_x",
+    "set_memory_ro",
+    "set_memory_nx",
+];
 pub fn kallsyms_lookup_name() {
     let mut env_file = OpenOptions::new()
         .write(true)
@@ -121,7 +127,7 @@ pub fn kallsyms_lookup_name() {
         "sh",
         &[
             "-c",
-            "sudo cat /proc/kallsyms | grep -E 'module_alloc|module_memfree|set_memory_x'",
+            "sudo cat /proc/kallsyms | grep -E 'module_alloc|module_memfree|set_memory_x|set_memory_ro|set_memory_nx'",
         ],
     );
     let lines = ret.split("\n");
@@ -58,6 +58,8 @@ extern "C" {
     pub fn put_task_struct(t: *mut task_struct);
     #[link_name = "rust_helper_signal_pending"]
     pub fn signal_pending(t: *mut task_struct) -> core::ffi::c_int;
+    #[link_name = "rust_helper_current_euid"]
+    pub fn current_euid() -> uid_t;
 
     // error
     #[link_name = "rust_helper_IS_ERR"]
@@ -194,6 +196,18 @@ extern "C" {
     pub fn memalloc_nofs_save() -> core::ffi::c_uint;
     #[link_name = "rust_helper_memalloc_nofs_restore"]
     pub fn memalloc_nofs_restore(flags: core::ffi::c_uint);
+
+    // workqueue
+    #[link_name = "rust_helper_init_work"]
+    pub fn init_work(work: *mut work_struct, func: work_func_t);
+    #[link_name = "rust_helper_schedule_work"]
+    pub fn schedule_work(work: *mut work_struct) -> bool_;
+
+    // delay
+    #[link_name = "rust_helper_mdelay"]
+    pub fn mdelay(msecs: core::ffi::c_ulong);
+    #[link_name = "rust_helper_may_sleep"]
+    pub fn may_sleep() -> bool_;
 }
 
 #[repr(C)]
@@ -6,6 +6,7 @@
 //!
 //! C headers: [`include/linux/fs.h`](srctree/include/linux/fs.h)
 
+use alloc::vec::Vec;
 use core::{
     ffi,
     marker::PhantomData,
@@ -445,6 +446,38 @@ pub fn memalloc_nofs<T>(cb: impl FnOnce() -> T) -> T {
     ret
 }
 
+/// Reads the whole file at `path`.
+///
+/// The read is checked by the security hooks of module loading. Fails with `EFBIG` if the
+/// file is larger than `max_size`.
+pub fn read_file_from_path(path: &CStr, max_size: usize) -> Result<Vec<u8>> {
+    let mut buf: *mut ffi::c_void = ptr::null_mut();
+    let mut file_size = 0;
+    // SAFETY: `path` is NUL-terminated, the kernel allocates `buf` and we free it below.
+    let ret = unsafe {
+        bindings::kernel_read_file_from_path(
+            path.as_char_ptr(),
+            0,
+            &mut buf,
+            max_size,
+            &mut file_size,
+            bindings::kernel_read_file_id_READING_MODULE,
+        )
+    };
+    if ret < 0 {
+        return Err(Error::from_errno(ret as ffi::c_int));
+    }
+    let len = ret as usize;
+    let data = Vec::try_with_capacity(len).map(|mut data| {
+        // SAFETY: the kernel read `len` bytes into `buf`.
+        data.extend_from_slice(unsafe { core::slice::from_raw_parts(buf as *const u8, len) });
+        data
+    });
+    // SAFETY: `buf` is allocated by `kernel_read_file_from_path` with `vmalloc`.
+    unsafe { bindings::vfree(buf) };
+    Ok(data?)
+}
+
 /// Kernel module that exposes a single file system implemented by `T`.
 #[pin_data]
 pub struct FsModule<T: FileSystem + ?Sized> {
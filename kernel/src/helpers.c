@@ -21,6 +21,9 @@
 #include <linux/fs.h>
 #include <linux/pagemap.h>
 #include <linux/srcu.h>
+#include <linux/cred.h>
+#include <linux/delay.h>
+#include <linux/preempt.h>
 
 
 void bug_helper(void) { BUG(); }
@@ -281,4 +284,35 @@ void rust_helper_memalloc_nofs_restore(unsigned int flags)
 
 void * rust_helper_srcu_dereference(struct rcudata *p,const struct srcu_struct *ssp) {
     return srcu_dereference(p->a, ssp);
-}
\ No newline at end of file
+}
+// workqueue
+
+void rust_helper_init_work(struct work_struct *work, work_func_t func)
+{
+    INIT_WORK(work, func);
+}
+
+bool rust_helper_schedule_work(struct work_struct *work)
+{
+    return schedule_work(work);
+}
+
+// credentials
+
+uid_t rust_helper_current_euid(void)
+{
+    return from_kuid_munged(current_user_ns(), current_euid());
+}
+
+// delay
+
+void rust_helper_mdelay(unsigned long msecs)
+{
+    mdelay(msecs);
+}
+
+bool rust_helper_may_sleep(void)
+{
+    // preemptible() is always false without CONFIG_PREEMPT_COUNT
+    return preemptible() && !rcu_preempt_depth();
+}
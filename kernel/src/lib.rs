@@ -25,9 +25,10 @@ pub mod random;
 pub mod str;
 pub mod sync;
 pub mod sysctl;
-mod task;
+pub mod task;
 pub mod time;
 pub mod types;
+pub mod workqueue;
 
 use alloc::boxed::Box;
 
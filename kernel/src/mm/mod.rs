@@ -9,11 +9,11 @@ pub mod mem_cache;
 pub mod pages;
 pub mod vm;
 
-type SetMemoryX = extern "C" fn(*mut core::ffi::c_void, core::ffi::c_int) -> core::ffi::c_int;
+type SetMemoryFn = extern "C" fn(*mut core::ffi::c_void, core::ffi::c_int) -> core::ffi::c_int;
 
 pub fn set_memory_x(virt_addr: usize, numpages: usize) -> KernelResult<()> {
     let raw_set_memory_x = env::SET_MEMORY_X_ADDR as *const u8 as *const ();
-    let set_memory_x: SetMemoryX = unsafe { core::mem::transmute(raw_set_memory_x) };
+    let set_memory_x: SetMemoryFn = unsafe { core::mem::transmute(raw_set_memory_x) };
     let ret = set_memory_x(
         virt_addr as *mut core::ffi::c_void,
         numpages as core::ffi::c_int,
@@ -24,3 +24,31 @@ pub fn set_memory_x(virt_addr: usize, numpages: usize) -> KernelResult<()> {
         Err(linux_err::EINVAL)
     }
 }
+
+pub fn set_memory_ro(virt_addr: usize, numpages: usize) -> KernelResult<()> {
+    let raw_set_memory_ro = env::SET_MEMORY_RO_ADDR as *const u8 as *const ();
+    let set_memory_ro: SetMemoryFn = unsafe { core::mem::transmute(raw_set_memory_ro) };
+    let ret = set_memory_ro(
+        virt_addr as *mut core::ffi::c_void,
+        numpages as core::ffi::c_int,
+    );
+    if ret == 0 {
+        Ok(())
+    } else {
+        Err(linux_err::EINVAL)
+    }
+}
+
+pub fn set_memory_nx(virt_addr: usize, numpages: usize) -> KernelResult<()> {
+    let raw_set_memory_nx = env::SET_MEMORY_NX_ADDR as *const u8 as *const ();
+    let set_memory_nx: SetMemoryFn = unsafe { core::mem::transmute(raw_set_memory_nx) };
+    let ret = set_memory_nx(
+        virt_addr as *mut core::ffi::c_void,
+        numpages as core::ffi::c_int,
+    );
+    if ret == 0 {
+        Ok(())
+    } else {
+        Err(linux_err::EINVAL)
+    }
+}
@@ -1,13 +1,22 @@
 use alloc::boxed::Box;
+use core::sync::atomic::{AtomicBool, Ordering};
 
 use kbind::srcu_struct;
 
-use crate::{bindings, bindings::CRcuData, pr_warn};
+use crate::{
+    bindings,
+    bindings::CRcuData,
+    pr_warn,
+    task::may_sleep,
+    time::{ktime_ms_delta, msleep, Ktime},
+};
 
 #[derive(Debug)]
 pub struct SRcuData<T> {
     crcu_data: CRcuData,
     ssp: *mut srcu_struct,
+    /// The new readers wait while it's set, see [SRcuData::try_pause]
+    paused: AtomicBool,
     _marker: core::marker::PhantomData<T>,
 }
 unsafe impl<T> Sync for SRcuData<T> {}
@@ -25,19 +34,41 @@ impl<T> SRcuData<T> {
                 data_ptr: v as *mut core::ffi::c_void,
             },
             ssp,
+            paused: AtomicBool::new(false),
             _marker: core::marker::PhantomData,
         }
     }
 
+    /// Call `f` with the data inside a read section.
+    ///
+    /// If the data is paused by [SRcuData::try_pause], the reader waits outside of the read
+    /// section until it's resumed. It sleeps if it can, only a reader in atomic context spins,
+    /// so it can be called in any context.
     pub fn read<R>(&self, f: impl FnOnce(&T) -> R) -> R {
-        let idx = unsafe { bindings::__srcu_read_lock(self.ssp) };
-        let ptr = srcu_defererence::<T>(&self.crcu_data, self.ssp);
-        let v = unsafe { &*ptr };
-        let r = f(v);
-        unsafe {
-            bindings::__srcu_read_unlock(self.ssp, idx);
+        loop {
+            let idx = unsafe { bindings::__srcu_read_lock(self.ssp) };
+            // checked inside the read section, so the writer either waits for this reader
+            // or this reader sees the flag
+            if !self.paused.load(Ordering::Acquire) {
+                let ptr = srcu_defererence::<T>(&self.crcu_data, self.ssp);
+                let v = unsafe { &*ptr };
+                let r = f(v);
+                unsafe {
+                    bindings::__srcu_read_unlock(self.ssp, idx);
+                }
+                return r;
+            }
+            unsafe {
+                bindings::__srcu_read_unlock(self.ssp, idx);
+            }
+            while self.paused.load(Ordering::Acquire) {
+                if may_sleep() {
+                    msleep(1);
+                } else {
+                    core::hint::spin_loop();
+                }
+            }
         }
-        r
     }
 
     pub fn read_directly<R>(&self, f: impl FnOnce(&T) -> R) -> R {
@@ -64,6 +95,46 @@ impl<T> SRcuData<T> {
         let old_data = unsafe { Box::from_raw(old_ptr as *mut T) };
         old_data
     }
+
+    /// Stop the new readers and wait up to `timeout_ms` milliseconds for the current readers
+    /// to finish. The writer sleeps while it waits.
+    ///
+    /// The data stays published the whole time, the new readers wait in [SRcuData::read]
+    /// until [SRcuData::resume] or [SRcuData::update_paused] is called. Once it returns
+    /// `true` no reader uses the data, so it can be read with [SRcuData::read_directly]
+    /// without racing with the readers.
+    ///
+    /// On timeout the readers are resumed and `false` is returned, the data is not changed.
+    /// The readers which can't sleep spin during the wait, so the timeout should be short.
+    pub fn try_pause(&self, timeout_ms: u32) -> bool {
+        self.paused.store(true, Ordering::SeqCst);
+        let cookie = unsafe { bindings::start_poll_synchronize_srcu(self.ssp) };
+        let start = Ktime::ktime_get();
+        while !unsafe { bindings::poll_state_synchronize_srcu(self.ssp, cookie) } {
+            if ktime_ms_delta(Ktime::ktime_get(), start) >= timeout_ms as i64 {
+                pr_warn!("srcu readers don't finish in {}ms", timeout_ms);
+                self.resume();
+                return false;
+            }
+            msleep(1);
+        }
+        true
+    }
+
+    /// Let the readers paused by [SRcuData::try_pause] continue with the current data.
+    pub fn resume(&self) {
+        self.paused.store(false, Ordering::Release);
+    }
+
+    /// Replace the data paused by [SRcuData::try_pause] and resume the readers.
+    ///
+    /// No reader uses the old data, so it's returned at once.
+    pub fn update_paused(&self, data: T) -> Box<T> {
+        debug_assert!(self.paused.load(Ordering::Relaxed));
+        let old_data = self.update_directly(data);
+        self.resume();
+        old_data
+    }
 }
 
 impl<T> Drop for SRcuData<T> {
@@ -12,6 +12,32 @@ use crate::{
 pub trait SysctlStorage: Sync {
     fn store_value(&self, data: &[u8]) -> (usize, error::KernelResult<()>);
     fn read_value(&self, data: &mut KernelSlicePtrWriter) -> (usize, error::KernelResult<()>);
+
+    /// Read the value from `offset`, the reader continues at the returned length.
+    ///
+    /// The default reads the whole value with [SysctlStorage::read_value] at offset 0 and
+    /// returns EOF at any other offset, values which may not fit in one read override it.
+    fn read_value_at(
+        &self,
+        data: &mut KernelSlicePtrWriter,
+        offset: usize,
+    ) -> (usize, error::KernelResult<()>) {
+        if offset != 0 {
+            return (0, Ok(()));
+        }
+        self.read_value(data)
+    }
+}
+
+/// Write the part of `text` from `offset` that fits in `data`, for [SysctlStorage::read_value_at].
+pub fn read_text_at(
+    data: &mut KernelSlicePtrWriter,
+    text: &[u8],
+    offset: usize,
+) -> (usize, error::KernelResult<()>) {
+    let rest = text.get(offset..).unwrap_or(&[]);
+    let len = rest.len().min(data.len());
+    (len, data.write(&rest[..len]))
 }
 
 fn trim_whitespace(mut data: &[u8]) -> &[u8] {
@@ -39,6 +65,14 @@ where
     fn read_value(&self, data: &mut KernelSlicePtrWriter) -> (usize, error::KernelResult<()>) {
         (*self).read_value(data)
     }
+
+    fn read_value_at(
+        &self,
+        data: &mut KernelSlicePtrWriter,
+        offset: usize,
+    ) -> (usize, error::KernelResult<()>) {
+        (*self).read_value_at(data, offset)
+    }
 }
 
 impl SysctlStorage for atomic::AtomicBool {
@@ -67,6 +101,22 @@ impl SysctlStorage for atomic::AtomicBool {
     }
 }
 
+impl SysctlStorage for atomic::AtomicU32 {
+    fn store_value(&self, data: &[u8]) -> (usize, error::KernelResult<()>) {
+        let result = core::str::from_utf8(trim_whitespace(data))
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .map(|value| self.store(value, atomic::Ordering::Relaxed))
+            .ok_or(error::linux_err::EINVAL);
+        (data.len(), result)
+    }
+
+    fn read_value(&self, data: &mut KernelSlicePtrWriter) -> (usize, error::KernelResult<()>) {
+        let value = alloc::format!("{}\n", self.load(atomic::Ordering::Relaxed));
+        (value.len(), data.write(value.as_bytes()))
+    }
+}
+
 pub struct Sysctl<T: SysctlStorage> {
     inner: Box<T>,
     // Responsible for keeping the ctl_table alive.
@@ -89,13 +139,6 @@ unsafe extern "C" fn proc_handler<T: SysctlStorage>(
     //     "proc_handler: ctl={:p}, write={}, buffer={:p}, len={}, ppos={}",
     //     ctl, write, buffer, *len, *ppos
     // );
-    // If we're reading from some offset other than the beginning of the file,
-    // return an empty read to signal EOF.
-    if *ppos != 0 && write == 0 {
-        *len = 0;
-        return 0;
-    }
-
     let data = match KernelSlicePtr::new(buffer, *len) {
         Ok(ptr) => ptr,
         Err(e) => {
@@ -112,7 +155,7 @@ unsafe extern "C" fn proc_handler<T: SysctlStorage>(
         storage.store_value(&data)
     } else {
         let mut writer = data.writer();
-        storage.read_value(&mut writer)
+        storage.read_value_at(&mut writer, *ppos as usize)
     };
     *len = bytes_processed;
     *ppos += *len as bindings::loff_t;
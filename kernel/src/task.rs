@@ -24,6 +24,27 @@ pub const TASK_UNINTERRUPTIBLE: c_int = bindings::TASK_UNINTERRUPTIBLE as c_int;
 /// uninterruptible sleep.
 pub const TASK_NORMAL: c_uint = bindings::TASK_NORMAL as c_uint;
 
+/// Returns the effective user id of the current task, as seen in its user namespace.
+pub fn current_euid() -> bindings::uid_t {
+    // SAFETY: Just an FFI call with no additional safety requirements.
+    unsafe { bindings::current_euid() }
+}
+
+/// Returns whether the current task has the capability `cap`, e.g. `bindings::CAP_SYS_ADMIN`.
+pub fn capable(cap: c_uint) -> bool {
+    // SAFETY: Just an FFI call with no additional safety requirements.
+    unsafe { bindings::capable(cap as c_int) }
+}
+
+/// Returns whether the current task can sleep.
+///
+/// It's false in atomic context and in a RCU read section. It's always false if the kernel
+/// doesn't track the preemption count, so the caller must have a fallback which doesn't sleep.
+pub fn may_sleep() -> bool {
+    // SAFETY: Just an FFI call with no additional safety requirements.
+    unsafe { bindings::may_sleep() }
+}
+
 /// Returns the currently running task.
 #[macro_export]
 macro_rules! current {
@@ -29,6 +29,24 @@ pub fn msecs_to_jiffies(msecs: Msecs) -> Jiffies {
     unsafe { bindings::__msecs_to_jiffies(msecs) }
 }
 
+/// Sleeps for at least `msecs` milliseconds.
+///
+/// The caller must be in a sleepable context.
+#[inline]
+pub fn msleep(msecs: Msecs) {
+    // SAFETY: `msleep` is safe to call in a sleepable context.
+    unsafe { bindings::msleep(msecs) }
+}
+
+/// Busy waits for `msecs` milliseconds.
+///
+/// It can be called in atomic context, keep `msecs` small.
+#[inline]
+pub fn mdelay(msecs: Msecs) {
+    // SAFETY: `mdelay` is safe to call in any context.
+    unsafe { bindings::mdelay(msecs as _) }
+}
+
 /// A Rust wrapper around a `ktime_t`.
 #[repr(transparent)]
 #[derive(Copy, Clone)]
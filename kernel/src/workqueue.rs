@@ -0,0 +1,59 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Work items run on the system workqueue.
+//!
+//! C header: [`include/linux/workqueue.h`](srctree/include/linux/workqueue.h).
+
+use alloc::boxed::Box;
+use core::pin::Pin;
+
+use crate::{bindings, container_of, types::Opaque};
+
+/// A work item that runs `func` in process context on the system workqueue.
+///
+/// It's used to leave atomic context for work that may sleep. The work is cancelled and
+/// waited for when it's dropped.
+#[repr(C)]
+pub struct Work {
+    work: Opaque<bindings::work_struct>,
+    func: fn(),
+}
+
+// SAFETY: The work_struct is only accessed through the workqueue API, which is thread safe.
+unsafe impl Send for Work {}
+unsafe impl Sync for Work {}
+
+impl Work {
+    /// Create a work item which calls `func` each time it runs.
+    pub fn new(func: fn()) -> Pin<Box<Self>> {
+        let work = Box::pin(Self {
+            work: Opaque::uninit(),
+            func,
+        });
+        // SAFETY: The work is pinned, so the work_struct never moves after it's initialized.
+        unsafe { bindings::init_work(work.work.get(), Some(Self::run)) };
+        work
+    }
+
+    /// Queue the work on the system workqueue, return false if it's already pending.
+    ///
+    /// It can be called from any context.
+    pub fn schedule(&self) -> bool {
+        // SAFETY: The work_struct is initialized in `new`.
+        unsafe { bindings::schedule_work(self.work.get()) }
+    }
+
+    unsafe extern "C" fn run(work: *mut bindings::work_struct) {
+        // SAFETY: The work_struct is the `work` field of a `Work`, which lives until the
+        // work is cancelled in `drop`.
+        let this = unsafe { &*container_of!(work, Self, work) };
+        (this.func)();
+    }
+}
+
+impl Drop for Work {
+    fn drop(&mut self) {
+        // SAFETY: The work_struct is initialized in `new`.
+        unsafe { bindings::cancel_work_sync(self.work.get()) };
+    }
+}
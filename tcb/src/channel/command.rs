@@ -1,178 +1,344 @@
 use alloc::{
+    collections::BTreeMap,
     string::{String, ToString},
     vec::Vec,
 };
 use core::sync::atomic;
 
-use command::{Command, Response};
+use command::{checksum, Command, ErrorCode, Response};
+use corelib::LinuxError;
 use interface::DomainTypeRaw;
 use kernel::{
+    bindings::{pid_t, uid_t, CAP_SYS_ADMIN},
     buf::KernelSlicePtrWriter,
+    current,
     error::{linux_err, KernelResult},
     pr_err, println,
     sysctl::SysctlStorage,
+    task::{capable, current_euid},
+    time::Ktime,
 };
 use spin::Mutex;
 
-use crate::channel::update_domain;
+use crate::{
+    channel::update_domain,
+    domain_loader::path::{register_domain_path, MAX_DOMAIN_FILE_SIZE},
+};
+
+/// The max number of the upload sessions at the same time
+const MAX_UPLOAD_SESSIONS: usize = 16;
+/// The max number of the upload sessions of one user at the same time
+const MAX_USER_SESSIONS: usize = 2;
+/// The upload sessions which don't receive a command in this time are removed
+const SESSION_IDLE_TIMEOUT_NS: u64 = 60 * 1_000_000_000;
+/// The max number of the responses kept for the tasks which don't read them
+const MAX_RESPONSES: usize = 64;
 
 #[derive(Debug)]
 pub struct CommandChannel {
-    id: atomic::AtomicU64,
+    session_id: atomic::AtomicU64,
     inner: Mutex<CommandChannelInner>,
 }
+
 #[derive(Debug)]
 struct CommandChannelInner {
-    id: Option<u64>,
-    domain_type: Option<DomainTypeRaw>,
-    domain_ident: Option<String>,
-    domain_size: Option<usize>,
-    domain_data: Option<Vec<u8>>,
-    response: Option<Response>,
+    sessions: BTreeMap<u64, UploadSession>,
+    /// The response of the last request of each task, it is read by the same task
+    responses: BTreeMap<pid_t, PendingResponse>,
+    /// The order of the responses, the oldest one is dropped when there are too many
+    response_seq: u64,
+}
+
+#[derive(Debug)]
+struct PendingResponse {
+    seq: u64,
+    request_id: u64,
+    response: Response,
+}
+
+#[derive(Debug)]
+struct UploadSession {
+    /// Only the user who starts the session can use it
+    uid: uid_t,
+    domain_type: DomainTypeRaw,
+    domain_ident: String,
+    domain_size: usize,
+    domain_data: Vec<u8>,
+    next_data_id: u32,
+    /// The time of the last command of the session, in nanoseconds
+    last_active: u64,
 }
 
 impl CommandChannel {
     pub fn new() -> Self {
         Self {
-            id: atomic::AtomicU64::new(0),
+            session_id: atomic::AtomicU64::new(0),
             inner: Mutex::new(CommandChannelInner {
-                id: None,
-                domain_type: None,
-                domain_ident: None,
-                domain_size: None,
-                domain_data: None,
-                response: None,
+                sessions: BTreeMap::new(),
+                responses: BTreeMap::new(),
+                response_seq: 0,
             }),
         }
     }
-}
 
-impl SysctlStorage for CommandChannel {
-    fn store_value(&self, data: &[u8]) -> (usize, KernelResult<()>) {
-        let command = Command::parse(data);
-        let mut inner = self.inner.lock();
+    fn handle(&self, command: Command) -> Result<Response, ErrorCode> {
         match command {
-            Some(Command::Start(ref start_command)) => {
-                println!("Command: {:?}", command);
-                inner.id = Some(self.id.fetch_add(1, atomic::Ordering::Relaxed));
-                let ty = DomainTypeRaw::try_from(start_command.domain_type);
-                if ty.is_err() {
-                    pr_err!("Invalid domain type");
-                    return (0, Err(linux_err::EINVAL));
+            Command::Start(start_command) => {
+                require_admin()?;
+                let ty = DomainTypeRaw::try_from(start_command.domain_type)
+                    .map_err(|_| ErrorCode::InvalidDomainType)?;
+                if start_command.domain_size > MAX_DOMAIN_FILE_SIZE {
+                    return Err(ErrorCode::TooLarge);
+                }
+                let mut domain_data = Vec::new();
+                domain_data
+                    .try_reserve_exact(start_command.domain_size)
+                    .map_err(|_| ErrorCode::OutOfMemory)?;
+                let uid = current_euid();
+                let now = Ktime::ktime_get().to_ns() as u64;
+                // the data of the stale sessions is freed out of the lock
+                let expired = self.inner.lock().expire_sessions(now);
+                drop(expired);
+                let mut inner = self.inner.lock();
+                let user_sessions = inner
+                    .sessions
+                    .values()
+                    .filter(|session| session.uid == uid)
+                    .count();
+                if inner.sessions.len() >= MAX_UPLOAD_SESSIONS || user_sessions >= MAX_USER_SESSIONS
+                {
+                    return Err(ErrorCode::TooManySessions);
                 }
-                let ty = ty.unwrap();
-                inner.domain_type = Some(ty);
-                inner.domain_ident = Some(start_command.register_domain_elf_ident.to_string());
-                inner.domain_size = Some(start_command.domain_size);
-                inner.domain_data = Some(Vec::with_capacity(start_command.domain_size));
-                // set res
-                inner.response = Some(Response::Ok(inner.id.unwrap() as usize));
-                (data.len(), Ok(()))
+                let session = self.session_id.fetch_add(1, atomic::Ordering::Relaxed);
+                inner.sessions.insert(
+                    session,
+                    UploadSession {
+                        uid,
+                        domain_type: ty,
+                        domain_ident: start_command.register_domain_elf_ident.to_string(),
+                        domain_size: start_command.domain_size,
+                        domain_data,
+                        next_data_id: 0,
+                        last_active: now,
+                    },
+                );
+                Ok(Response::Ok(session))
             }
-            Some(Command::Send(send_command)) => {
-                if send_command.id != inner.id.unwrap() {
-                    pr_err!("Invalid id");
-                    return (0, Err(linux_err::EINVAL));
+            Command::Send(send_command) => {
+                let mut inner = self.inner.lock();
+                let session = inner.session_mut(send_command.session)?;
+                if send_command.data_id != session.next_data_id {
+                    return Err(ErrorCode::OutOfOrder);
                 }
-                if send_command.bytes != send_command.data.len() {
-                    pr_err!("Invalid data length");
-                    return (0, Err(linux_err::EINVAL));
+                if checksum(send_command.data) != send_command.checksum {
+                    return Err(ErrorCode::ChecksumMismatch);
                 }
-                inner
-                    .domain_data
-                    .as_mut()
-                    .unwrap()
-                    .extend_from_slice(send_command.data);
-                // set res
-                inner.response = Some(Response::Receive(
-                    inner.id.unwrap() as usize,
-                    send_command.data_id,
-                    send_command.bytes,
-                ));
-                (data.len(), Ok(()))
-            }
-            Some(Command::Stop(ref stop_command)) => {
-                println!("Command: {:?}", command);
-                if stop_command.id != inner.id.unwrap() {
-                    pr_err!("Invalid id");
-                    return (0, Err(linux_err::EINVAL));
+                if session.domain_data.len() + send_command.data.len() > session.domain_size {
+                    return Err(ErrorCode::SizeMismatch);
                 }
-                let id = inner.id.take().unwrap();
-                let domain_elf = inner.domain_data.take().unwrap();
-                let ty = inner.domain_type.take().unwrap();
-                let ident = inner.domain_ident.take().unwrap();
-                inner.domain_size = None;
-
-                super::register_domain(ident.as_str(), domain_elf, ty).unwrap();
-
-                // set res
-                inner.response = Some(Response::Ok(id as usize));
-                (data.len(), Ok(()))
+                session.domain_data.extend_from_slice(send_command.data);
+                session.next_data_id += 1;
+                session.last_active = Ktime::ktime_get().to_ns() as u64;
+                Ok(Response::Receive {
+                    session: send_command.session,
+                    data_id: send_command.data_id,
+                    bytes: send_command.data.len() as u32,
+                })
             }
-            Some(Command::Update(ref update_command)) => {
-                println!("Command: {:?}", command);
-                let old_domain_ident = update_command.domain_ident;
-                let new_domain_ident = update_command.register_domain_elf_ident;
-                let domain_type = DomainTypeRaw::try_from(update_command.domain_type);
-                if domain_type.is_err() {
-                    pr_err!("Invalid domain type");
-                    return (0, Err(linux_err::EINVAL));
+            Command::Stop(stop_command) => {
+                let session = self.inner.lock().remove_session(stop_command.session)?;
+                if session.domain_data.len() != session.domain_size {
+                    return Err(ErrorCode::SizeMismatch);
                 }
-                let domain_type = domain_type.unwrap();
-                update_domain(old_domain_ident, new_domain_ident, domain_type).unwrap();
-                inner.response = Some(Response::Ok(0));
-                (data.len(), Ok(()))
+                super::register_domain(
+                    session.domain_ident.as_str(),
+                    session.domain_data,
+                    session.domain_type,
+                    stop_command.signature,
+                )
+                .map_err(|e| match e {
+                    LinuxError::EPERM => ErrorCode::BadSignature,
+                    _ => ErrorCode::RegisterFailed,
+                })?;
+                Ok(Response::Ok(stop_command.session))
             }
-            Some(Command::Load(ref load_command)) => {
-                println!("Command: {:?}", command);
-                let ty = DomainTypeRaw::try_from(load_command.domain_type);
-                if ty.is_err() {
-                    pr_err!("Invalid domain type");
-                    return (0, Err(linux_err::EINVAL));
-                }
-                let ty = ty.unwrap();
-                let res = super::load_domain(
+            Command::Exit(exit_command) => {
+                // the data is freed out of the lock
+                let session = self.inner.lock().remove_session(exit_command.session)?;
+                drop(session);
+                Ok(Response::Ok(exit_command.session))
+            }
+            Command::Update(update_command) => {
+                require_admin()?;
+                let domain_type = DomainTypeRaw::try_from(update_command.domain_type)
+                    .map_err(|_| ErrorCode::InvalidDomainType)?;
+                update_domain(
+                    update_command.domain_ident,
+                    update_command.register_domain_elf_ident,
+                    domain_type,
+                )
+                .map_err(|_| ErrorCode::UpdateFailed)?;
+                Ok(Response::Ok(0))
+            }
+            Command::Load(load_command) => {
+                require_admin()?;
+                let ty = DomainTypeRaw::try_from(load_command.domain_type)
+                    .map_err(|_| ErrorCode::InvalidDomainType)?;
+                let args = super::parse_domain_args(ty, load_command.args)
+                    .map_err(|_| ErrorCode::InvalidArgs)?;
+                let name = super::load_domain(
                     load_command.register_domain_elf_ident,
                     load_command.domain_ident,
                     ty,
-                );
-                if res.is_err() {
-                    return (0, Err(linux_err::EINVAL));
-                }
-                inner.response = Some(Response::Ok(0));
-                (data.len(), Ok(()))
+                    args,
+                    false,
+                )
+                .map_err(|_| ErrorCode::LoadFailed)?;
+                println!("Domain {} loaded", name);
+                // the domain is named `<domain_ident>-<n>`, reply the instance number
+                let instance = name
+                    .rsplit('-')
+                    .next()
+                    .and_then(|n| n.parse().ok())
+                    .unwrap_or(0);
+                Ok(Response::Ok(instance))
             }
-            Some(Command::Unload(ref unload_command)) => {
-                println!("Command: {:?}", command);
-                let res = super::unload_domain(unload_command.domain_ident);
-                if res.is_err() {
-                    return (0, Err(linux_err::EINVAL));
-                }
-                inner.response = Some(Response::Ok(0));
-                (data.len(), Ok(()))
+            Command::Unload(unload_command) => {
+                require_admin()?;
+                super::unload_domain(unload_command.domain_ident)
+                    .map_err(|_| ErrorCode::UnloadFailed)?;
+                Ok(Response::Ok(0))
             }
-            None => {
-                pr_err!("Invalid command format");
-                (0, Err(linux_err::EINVAL))
+            Command::UnloadTree(unload_command) => {
+                require_admin()?;
+                super::unload_domain_tree(unload_command.domain_ident)
+                    .map_err(|_| ErrorCode::UnloadFailed)?;
+                Ok(Response::Ok(0))
             }
-            Some(c) => {
-                pr_err!("Invalid command: {:?}", c);
-                (0, Err(linux_err::EINVAL))
+            Command::RegisterPath(register_command) => {
+                // the file is read with the credentials of the kernel
+                require_admin()?;
+                let ty = DomainTypeRaw::try_from(register_command.domain_type)
+                    .map_err(|_| ErrorCode::InvalidDomainType)?;
+                register_domain_path(
+                    register_command.register_domain_elf_ident,
+                    ty,
+                    register_command.path,
+                )
+                .map_err(|e| match e {
+                    LinuxError::EPERM => ErrorCode::BadSignature,
+                    LinuxError::ENOENT => ErrorCode::FileNotFound,
+                    _ => ErrorCode::RegisterFailed,
+                })?;
+                Ok(Response::Ok(0))
+            }
+        }
+    }
+}
+
+/// The commands changing the domains, or keeping kernel memory, need `CAP_SYS_ADMIN`.
+fn require_admin() -> Result<(), ErrorCode> {
+    if capable(CAP_SYS_ADMIN) {
+        Ok(())
+    } else {
+        Err(ErrorCode::PermissionDenied)
+    }
+}
+
+impl CommandChannelInner {
+    /// Get the upload session `session` of the current user.
+    ///
+    /// The sessions of the other users are reported as invalid, they can't be probed.
+    fn session_mut(&mut self, session: u64) -> Result<&mut UploadSession, ErrorCode> {
+        self.sessions
+            .get_mut(&session)
+            .filter(|session| session.uid == current_euid())
+            .ok_or(ErrorCode::InvalidSession)
+    }
+
+    /// Remove the upload sessions which are idle for [SESSION_IDLE_TIMEOUT_NS].
+    fn expire_sessions(&mut self, now: u64) -> Vec<UploadSession> {
+        let expired = self
+            .sessions
+            .iter()
+            .filter(|(_, session)| {
+                now.saturating_sub(session.last_active) >= SESSION_IDLE_TIMEOUT_NS
+            })
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        expired
+            .into_iter()
+            .filter_map(|id| {
+                let session = self.sessions.remove(&id)?;
+                pr_err!("Upload session {} of user {} expired", id, session.uid);
+                Some(session)
+            })
+            .collect()
+    }
+
+    fn remove_session(&mut self, session: u64) -> Result<UploadSession, ErrorCode> {
+        self.session_mut(session)?;
+        self.sessions
+            .remove(&session)
+            .ok_or(ErrorCode::InvalidSession)
+    }
+
+    /// Keep `response` for the task `pid`, drop the oldest response if there are too many.
+    fn push_response(&mut self, pid: pid_t, request_id: u64, response: Response) {
+        if self.responses.len() >= MAX_RESPONSES && !self.responses.contains_key(&pid) {
+            let oldest = self
+                .responses
+                .iter()
+                .min_by_key(|(_, pending)| pending.seq)
+                .map(|(pid, _)| *pid);
+            if let Some(oldest) = oldest {
+                self.responses.remove(&oldest);
+            }
+        }
+        let seq = self.response_seq;
+        self.response_seq += 1;
+        self.responses.insert(
+            pid,
+            PendingResponse {
+                seq,
+                request_id,
+                response,
+            },
+        );
+    }
+}
+
+impl SysctlStorage for CommandChannel {
+    fn store_value(&self, data: &[u8]) -> (usize, KernelResult<()>) {
+        let (request_id, response) = match Command::decode(data) {
+            Ok((request_id, command)) => {
+                if !matches!(command, Command::Send(_)) {
+                    println!("Command({}): {:?}", request_id, command);
+                }
+                let response = self.handle(command).unwrap_or_else(Response::Error);
+                (request_id, response)
             }
+            Err((request_id, code)) => (request_id, Response::Error(code)),
+        };
+        if let Response::Error(code) = response {
+            pr_err!("Command({}) failed: {}", request_id, code);
         }
+        let pid = current!().pid();
+        self.inner.lock().push_response(pid, request_id, response);
+        (data.len(), Ok(()))
     }
 
     fn read_value(&self, data: &mut KernelSlicePtrWriter) -> (usize, KernelResult<()>) {
+        let pid = current!().pid();
         let mut inner = self.inner.lock();
-        println!("Response: {:?}", inner.response);
-        if inner.response.is_none() {
+        let Some(pending) = inner.responses.get(&pid) else {
             return (0, Err(linux_err::EAGAIN));
-        }
-        let res = inner.response.as_ref().unwrap().to_bytes();
+        };
+        let res = pending.response.encode(pending.request_id);
         if data.len() < res.len() {
             return (0, Err(linux_err::EAGAIN));
         }
-        inner.response = None;
+        inner.responses.remove(&pid);
         (res.len(), data.write(&res))
     }
 }
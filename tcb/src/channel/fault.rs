@@ -0,0 +1,162 @@
+use alloc::{fmt::Write, string::String, vec::Vec};
+
+use corelib::{LinuxError, LinuxResult};
+use interface::DomainType;
+use kernel::{
+    buf::KernelSlicePtrWriter,
+    error::{linux_err, KernelResult},
+    sysctl::{read_text_at, SysctlStorage},
+};
+
+use crate::{
+    domain_helper::{query_domain, DOMAIN_INFO},
+    domain_proxy::{
+        block_device::BlockDeviceDomainProxy, empty_device::EmptyDeviceDomainProxy,
+        logger::LogDomainProxy, FaultAction, FaultInjector, FaultSpec, FaultTrigger,
+        MAX_FAULT_DELAY_MS,
+    },
+};
+
+/// Arm the faults injected into the calls of a domain.
+///
+/// - `<name> <method> <panic|error=<errno>|delay=<ms>> [nth=<n>|prob=<percent>] [times=<n>] [seed=<n>]`
+///   arms a fault on `method`, the default trigger is `nth=1`. The delay busy waits, it's at
+///   most [MAX_FAULT_DELAY_MS] milliseconds.
+/// - `<name> <method> off` disarms the fault of `method`, `<name> off` disarms all faults of
+///   the domain.
+///
+/// Read it to see the armed faults and the calls they hit.
+#[derive(Debug)]
+pub struct FaultChannel;
+
+impl FaultChannel {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+fn with_faults<R>(name: &str, f: impl FnOnce(&FaultInjector) -> R) -> Option<R> {
+    match query_domain(name)? {
+        DomainType::LogDomain(d) => d
+            .downcast_arc::<LogDomainProxy>()
+            .ok()
+            .map(|p| f(p.faults())),
+        DomainType::EmptyDeviceDomain(d) => d
+            .downcast_arc::<EmptyDeviceDomainProxy>()
+            .ok()
+            .map(|p| f(p.faults())),
+        DomainType::BlockDeviceDomain(d) => d
+            .downcast_arc::<BlockDeviceDomainProxy>()
+            .ok()
+            .map(|p| f(p.faults())),
+    }
+}
+
+fn parse_errno(name: &str) -> Option<LinuxError> {
+    let errno = match name {
+        "EPERM" => LinuxError::EPERM,
+        "ENOENT" => LinuxError::ENOENT,
+        "EIO" => LinuxError::EIO,
+        "EAGAIN" => LinuxError::EAGAIN,
+        "ENOMEM" => LinuxError::ENOMEM,
+        "EBUSY" => LinuxError::EBUSY,
+        "EINVAL" => LinuxError::EINVAL,
+        "ENOSYS" => LinuxError::ENOSYS,
+        _ => return None,
+    };
+    Some(errno)
+}
+
+fn parse_spec<'a>(mut parts: impl Iterator<Item = &'a str>) -> Option<FaultSpec> {
+    let action = match parts.next()? {
+        "panic" => FaultAction::Panic,
+        action => match action.split_once('=')? {
+            ("error", errno) => FaultAction::Error(parse_errno(errno)?),
+            ("delay", ms) => {
+                FaultAction::Delay(ms.parse().ok().filter(|ms| *ms <= MAX_FAULT_DELAY_MS)?)
+            }
+            _ => return None,
+        },
+    };
+    let mut spec = FaultSpec {
+        action,
+        trigger: FaultTrigger::Nth(1),
+        times: None,
+        seed: None,
+    };
+    for kv in parts {
+        match kv.split_once('=')? {
+            ("nth", n) => spec.trigger = FaultTrigger::Nth(n.parse().ok().filter(|n| *n > 0)?),
+            ("prob", p) => {
+                spec.trigger = FaultTrigger::Percent(p.parse().ok().filter(|p| *p <= 100)?)
+            }
+            ("times", n) => spec.times = Some(n.parse().ok()?),
+            ("seed", n) => spec.seed = Some(n.parse().ok()?),
+            _ => return None,
+        }
+    }
+    Some(spec)
+}
+
+fn store_fault(data: &[u8]) -> LinuxResult<()> {
+    let command = core::str::from_utf8(data).map_err(|_| LinuxError::EINVAL)?;
+    let mut parts = command.split_whitespace();
+    let name = parts.next().ok_or(LinuxError::EINVAL)?;
+    let method = parts.next().ok_or(LinuxError::EINVAL)?;
+    let mut parts = parts.peekable();
+    let res = if method == "off" {
+        with_faults(name, |faults| faults.disarm(None))
+    } else if parts.peek() == Some(&"off") {
+        with_faults(name, |faults| faults.disarm(Some(method)))
+    } else {
+        let spec = parse_spec(parts).ok_or(LinuxError::EINVAL)?;
+        println!("[FaultChannel] Arm {}::{}: {:?}", name, method, spec);
+        with_faults(name, |faults| faults.arm(method, spec))
+    };
+    res.ok_or(LinuxError::ENOENT)?
+}
+
+fn render_faults() -> Result<String, core::fmt::Error> {
+    let mut out = String::new();
+    let names = DOMAIN_INFO
+        .lock()
+        .domain_list
+        .values()
+        .map(|data| data.name.clone())
+        .collect::<Vec<_>>();
+    for name in names {
+        let Some(faults) = with_faults(&name, |faults| alloc::format!("{}", faults)) else {
+            continue;
+        };
+        if !faults.is_empty() {
+            writeln!(out, "Domain {}:", name)?;
+            out.push_str(&faults);
+        }
+    }
+    Ok(out)
+}
+
+impl SysctlStorage for FaultChannel {
+    fn store_value(&self, data: &[u8]) -> (usize, KernelResult<()>) {
+        let res = store_fault(data).map_err(|e| {
+            println!("[FaultChannel] Invalid command: {:?}", e);
+            linux_err::EINVAL
+        });
+        (data.len(), res)
+    }
+
+    fn read_value(&self, data: &mut KernelSlicePtrWriter) -> (usize, KernelResult<()>) {
+        self.read_value_at(data, 0)
+    }
+
+    fn read_value_at(
+        &self,
+        data: &mut KernelSlicePtrWriter,
+        offset: usize,
+    ) -> (usize, KernelResult<()>) {
+        match render_faults() {
+            Ok(faults) => read_text_at(data, faults.as_bytes(), offset),
+            Err(_) => (0, Err(linux_err::EINVAL)),
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use alloc::{fmt::Write, string::String, vec::Vec};
+
+use interface::DomainType;
+use kernel::{
+    buf::KernelSlicePtrWriter,
+    error::{linux_err, KernelResult},
+    sysctl::{read_text_at, SysctlStorage},
+};
+
+use crate::{
+    domain::with_autoload_results,
+    domain_helper::{
+        domain_dependents, domain_providers, domain_ref_count, domain_shared_heap_usage,
+        query_domain, DOMAIN_INFO,
+    },
+    domain_loader::loader::DomainLoader,
+    domain_proxy::{
+        block_device::BlockDeviceDomainProxy, empty_device::EmptyDeviceDomainProxy,
+        logger::LogDomainProxy, UpdateStats,
+    },
+};
+
+/// Read-only view of the registered domain files and the live domains.
+#[derive(Debug)]
+pub struct InfoChannel;
+
+impl InfoChannel {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+fn domain_proxy_info(domain: DomainType) -> Option<(DomainLoader, UpdateStats)> {
+    match domain {
+        DomainType::LogDomain(d) => d
+            .downcast_arc::<LogDomainProxy>()
+            .ok()
+            .map(|p| (p.domain_loader(), p.update_stats())),
+        DomainType::EmptyDeviceDomain(d) => d
+            .downcast_arc::<EmptyDeviceDomainProxy>()
+            .ok()
+            .map(|p| (p.domain_loader(), p.update_stats())),
+        DomainType::BlockDeviceDomain(d) => d
+            .downcast_arc::<BlockDeviceDomainProxy>()
+            .ok()
+            .map(|p| (p.domain_loader(), p.update_stats())),
+    }
+}
+
+fn render_info() -> Result<String, core::fmt::Error> {
+    let mut out = String::new();
+    let domains = {
+        let info = DOMAIN_INFO.lock();
+        write!(out, "{}", *info)?;
+        info.domain_list
+            .iter()
+            .map(|(id, data)| (*id, data.name.clone()))
+            .collect::<Vec<_>>()
+    };
+    for (id, name) in domains {
+        writeln!(out, "Domain {}: {}", id, name)?;
+        // count the references before we hold a new one
+        if let Some(count) = domain_ref_count(&name) {
+            writeln!(out, "  - Ref count: {}", count)?;
+        }
+        if let Some((loader, stats)) = query_domain(&name).and_then(domain_proxy_info) {
+            let text = loader.text_section();
+            writeln!(out, "  - Text: {:#x}-{:#x}", text.start, text.end)?;
+            writeln!(out, "  - Updates: {}", stats)?;
+        }
+        writeln!(
+            out,
+            "  - Shared heap: {} bytes",
+            domain_shared_heap_usage(id)
+        )?;
+        let providers = domain_providers(&name);
+        if !providers.is_empty() {
+            writeln!(out, "  - Depends on: {}", providers.join(", "))?;
+        }
+        let dependents = domain_dependents(&name);
+        if !dependents.is_empty() {
+            writeln!(out, "  - Used by: {}", dependents.join(", "))?;
+        }
+    }
+    with_autoload_results(|results| {
+        if !results.is_empty() {
+            writeln!(out, "Autoload:")?;
+        }
+        results
+            .iter()
+            .try_for_each(|res| writeln!(out, "  - {}", res))
+    })?;
+    Ok(out)
+}
+
+impl SysctlStorage for InfoChannel {
+    fn store_value(&self, _data: &[u8]) -> (usize, KernelResult<()>) {
+        (0, Err(linux_err::EPERM))
+    }
+
+    fn read_value(&self, data: &mut KernelSlicePtrWriter) -> (usize, KernelResult<()>) {
+        self.read_value_at(data, 0)
+    }
+
+    fn read_value_at(
+        &self,
+        data: &mut KernelSlicePtrWriter,
+        offset: usize,
+    ) -> (usize, KernelResult<()>) {
+        match render_info() {
+            Ok(info) => read_text_at(data, info.as_bytes(), offset),
+            Err(_) => (0, Err(linux_err::EINVAL)),
+        }
+    }
+}
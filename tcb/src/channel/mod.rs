@@ -1,25 +1,32 @@
-use alloc::{
-    boxed::Box,
-    collections::BTreeMap,
-    string::{String, ToString},
-    vec::Vec,
-};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicU32};
 
 use kernel::sysctl::Sysctl;
-use spin::RwLock;
 
 mod command;
+mod fault;
+mod info;
+mod quota;
+mod register;
+mod search_path;
+mod shared_heap;
+mod stats;
 pub use command::CommandChannel;
-use corelib::{LinuxError, LinuxResult};
-use interface::{null_block::BlockArgs, DomainType, DomainTypeRaw};
+use corelib::LinuxResult;
+pub use fault::FaultChannel;
+pub use info::InfoChannel;
+use interface::DomainTypeRaw;
 use kernel::{error::KernelResult, types::Mode};
+pub use quota::QuotaChannel;
+pub use register::RegisterChannel;
+pub use search_path::SearchPathChannel;
+pub use shared_heap::SharedHeapChannel;
+pub use stats::{clear_domain_stats, register_domain_stats, unregister_domain_stats};
 
 use crate::{
-    create_domain,
-    domain_helper::{domain_ref_count, unregister_domain, DOMAIN_SYS},
-    domain_proxy::{block_device::BlockDeviceDomainProxy, ProxyBuilder},
-    kshim::{BlockDeviceShim, KernelShim},
-    register_domain,
+    domain::{load_domain, parse_domain_args, unload_domain, unload_domain_tree},
+    domain_helper::{DOMAIN_SYS, SHARED_HEAP_DEBUG},
+    domain_proxy::UPDATE_TIMEOUT_MS,
 };
 
 pub fn init_domain_channel() -> KernelResult<Sysctl<CommandChannel>> {
@@ -33,74 +40,99 @@ pub fn init_domain_channel() -> KernelResult<Sysctl<CommandChannel>> {
     Ok(command_channel)
 }
 
-fn register_domain(ident: &str, elf: Vec<u8>, ty: DomainTypeRaw) -> LinuxResult<()> {
-    crate::domain_loader::creator::register_domain_elf(ident, elf, ty);
-    println!("Register domain: {} ({:?})", ident, ty);
-    Ok(())
+pub fn init_domain_info() -> KernelResult<Sysctl<InfoChannel>> {
+    let info_channel = Sysctl::register(
+        c_str!("rust/domain"),
+        c_str!("info"),
+        InfoChannel::new(),
+        Mode::from_int(0o444),
+    )?;
+    Ok(info_channel)
 }
 
-pub fn update_domain(old_ident: &str, new_ident: &str, ty: DomainTypeRaw) -> LinuxResult<()> {
-    println!("Update domain: {} -> {} ({:?})", old_ident, new_ident, ty);
-    DOMAIN_SYS.sys_update_domain(old_ident, new_ident, ty)?;
-    Ok(())
+pub fn init_update_timeout() -> KernelResult<Sysctl<&'static AtomicU32>> {
+    let timeout = Sysctl::register(
+        c_str!("rust/domain"),
+        c_str!("update_timeout_ms"),
+        &UPDATE_TIMEOUT_MS,
+        Mode::from_int(0o644),
+    )?;
+    Ok(timeout)
+}
+
+pub fn init_shared_heap() -> KernelResult<Sysctl<SharedHeapChannel>> {
+    let shared_heap_channel = Sysctl::register(
+        c_str!("rust/domain"),
+        c_str!("shared_heap"),
+        SharedHeapChannel::new(),
+        Mode::from_int(0o444),
+    )?;
+    Ok(shared_heap_channel)
+}
+
+pub fn init_shared_heap_debug() -> KernelResult<Sysctl<&'static AtomicBool>> {
+    let debug = Sysctl::register(
+        c_str!("rust/domain"),
+        c_str!("shared_heap_debug"),
+        &SHARED_HEAP_DEBUG,
+        Mode::from_int(0o644),
+    )?;
+    Ok(debug)
 }
 
-static KSHIM_OBJ: RwLock<BTreeMap<String, Box<dyn KernelShim>>> = RwLock::new(BTreeMap::new());
+pub fn init_domain_fault() -> KernelResult<Sysctl<FaultChannel>> {
+    let fault_channel = Sysctl::register(
+        c_str!("rust/domain"),
+        c_str!("fault"),
+        FaultChannel::new(),
+        Mode::from_int(0o644),
+    )?;
+    Ok(fault_channel)
+}
+
+pub fn init_domain_quota() -> KernelResult<Sysctl<QuotaChannel>> {
+    let quota_channel = Sysctl::register(
+        c_str!("rust/domain"),
+        c_str!("quota"),
+        QuotaChannel::new(),
+        Mode::from_int(0o644),
+    )?;
+    Ok(quota_channel)
+}
 
-pub fn load_domain(
-    register_domain_elf_ident: &str,
-    domain_ident: &str,
+pub fn init_domain_register() -> KernelResult<Sysctl<RegisterChannel>> {
+    let register_channel = Sysctl::register(
+        c_str!("rust/domain"),
+        c_str!("register"),
+        RegisterChannel::new(),
+        Mode::from_int(0o200),
+    )?;
+    Ok(register_channel)
+}
+
+pub fn init_domain_search_path() -> KernelResult<Sysctl<SearchPathChannel>> {
+    let search_path_channel = Sysctl::register(
+        c_str!("rust/domain"),
+        c_str!("search_path"),
+        SearchPathChannel::new(),
+        Mode::from_int(0o644),
+    )?;
+    Ok(search_path_channel)
+}
+
+fn register_domain(
+    ident: &str,
+    elf: Vec<u8>,
     ty: DomainTypeRaw,
+    signature: &[u8],
 ) -> LinuxResult<()> {
-    println!(
-        "Load domain: {} ({:?}) -> {} ",
-        register_domain_elf_ident, ty, domain_ident
-    );
-    match ty {
-        DomainTypeRaw::BlockDeviceDomain => {
-            let (block_device, domain_file_info) = create_domain!(
-                BlockDeviceDomainProxy,
-                DomainTypeRaw::BlockDeviceDomain,
-                register_domain_elf_ident
-            )?;
-            let args = BlockArgs::default();
-            block_device.init_by_box(Box::new(args))?;
-            register_domain!(
-                domain_ident,
-                domain_file_info,
-                DomainType::BlockDeviceDomain(block_device.clone()),
-                true
-            );
-            let null_block = BlockDeviceShim::load(block_device).expect("Load block device failed");
-            KSHIM_OBJ
-                .write()
-                .insert(domain_ident.to_string(), Box::new(null_block));
-        }
-        other => {
-            pr_err!("[load_domain] Unsupported domain type: {:?}", other);
-            return Err(LinuxError::EINVAL);
-        }
-    }
+    crate::domain_loader::creator::register_domain_elf(ident, elf, ty, signature)?;
+    println!("Register domain: {} ({:?})", ident, ty);
     Ok(())
 }
 
-pub fn unload_domain(domain_ident: &str) -> LinuxResult<()> {
-    println!("Unload domain: {}", domain_ident);
-    let ref_count = domain_ref_count(domain_ident);
-    if ref_count.is_none() {
-        println!("[unload_domain] Domain {} not found", domain_ident);
-        return Err(LinuxError::ENOENT);
-    }
-    let ref_count = ref_count.unwrap();
-    if ref_count > 2 {
-        println!(
-            "[unload_domain] Domain {} is still in use, it has {} references",
-            domain_ident, ref_count
-        );
-        return Err(LinuxError::EBUSY);
-    }
-    unregister_domain(domain_ident);
-    KSHIM_OBJ.write().remove(domain_ident);
-    println!("Domain {} unloaded", domain_ident);
+pub fn update_domain(old_ident: &str, new_ident: &str, ty: DomainTypeRaw) -> LinuxResult<()> {
+    println!("Update domain: {} -> {} ({:?})", old_ident, new_ident, ty);
+    DOMAIN_SYS.sys_update_domain(old_ident, new_ident, ty)?;
     Ok(())
 }
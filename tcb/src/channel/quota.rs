@@ -0,0 +1,113 @@
+use alloc::{fmt::Write, string::String};
+use core::fmt::Display;
+
+use kernel::{
+    buf::KernelSlicePtrWriter,
+    error::{linux_err, KernelResult},
+    sysctl::{read_text_at, SysctlStorage},
+};
+
+use crate::domain_helper::{domain_quotas, query_domain, set_domain_quota, DOMAIN_INFO};
+
+/// The memory usage and the quota of each domain.
+///
+/// Write `<name> pages=<n|none> heap=<bytes|none>` to change the quota of a domain, the keys
+/// which are not given keep their old value.
+#[derive(Debug)]
+pub struct QuotaChannel;
+
+impl QuotaChannel {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+struct Limit(Option<usize>);
+
+impl Display for Limit {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self.0 {
+            Some(limit) => write!(f, "{}", limit),
+            None => write!(f, "none"),
+        }
+    }
+}
+
+fn render_quota() -> Result<String, core::fmt::Error> {
+    let mut out = String::new();
+    for (id, quota) in domain_quotas() {
+        let name = DOMAIN_INFO
+            .lock()
+            .domain_list
+            .get(&id)
+            .map(|data| data.name.clone())
+            .unwrap_or_default();
+        writeln!(out, "Domain {}: {}", id, name)?;
+        writeln!(
+            out,
+            "  - Pages: {} (peak: {}, limit: {})",
+            quota.pages,
+            quota.pages_peak,
+            Limit(quota.limit.pages)
+        )?;
+        writeln!(
+            out,
+            "  - Shared heap: {} bytes (peak: {}, limit: {})",
+            quota.heap_bytes,
+            quota.heap_peak,
+            Limit(quota.limit.heap_bytes)
+        )?;
+    }
+    Ok(out)
+}
+
+fn parse_limit(value: &str) -> Option<Option<usize>> {
+    match value {
+        "none" => Some(None),
+        value => value.parse().ok().map(Some),
+    }
+}
+
+fn store_quota(data: &[u8]) -> KernelResult<()> {
+    let command = core::str::from_utf8(data).map_err(|_| linux_err::EINVAL)?;
+    let mut parts = command.split_whitespace();
+    let name = parts.next().ok_or(linux_err::EINVAL)?;
+    let domain_id = query_domain(name).ok_or(linux_err::ENOENT)?.domain_id();
+    let mut limit = domain_quotas()
+        .into_iter()
+        .find(|(id, _)| *id == domain_id)
+        .map(|(_, quota)| quota.limit)
+        .unwrap_or_default();
+    for kv in parts {
+        let (target, value) = match kv.split_once('=') {
+            Some(("pages", value)) => (&mut limit.pages, value),
+            Some(("heap", value)) => (&mut limit.heap_bytes, value),
+            _ => return Err(linux_err::EINVAL),
+        };
+        *target = parse_limit(value).ok_or(linux_err::EINVAL)?;
+    }
+    println!("[QuotaChannel] Set quota of domain {}: {:?}", name, limit);
+    set_domain_quota(domain_id, limit);
+    Ok(())
+}
+
+impl SysctlStorage for QuotaChannel {
+    fn store_value(&self, data: &[u8]) -> (usize, KernelResult<()>) {
+        (data.len(), store_quota(data))
+    }
+
+    fn read_value(&self, data: &mut KernelSlicePtrWriter) -> (usize, KernelResult<()>) {
+        self.read_value_at(data, 0)
+    }
+
+    fn read_value_at(
+        &self,
+        data: &mut KernelSlicePtrWriter,
+        offset: usize,
+    ) -> (usize, KernelResult<()>) {
+        match render_quota() {
+            Ok(quota) => read_text_at(data, quota.as_bytes(), offset),
+            Err(_) => (0, Err(linux_err::EINVAL)),
+        }
+    }
+}
@@ -0,0 +1,45 @@
+use kernel::{
+    buf::KernelSlicePtrWriter,
+    error::{linux_err, Error, KernelResult},
+    sysctl::SysctlStorage,
+};
+
+use crate::{domain::parse_domain_type, domain_loader::path::register_domain_path};
+
+/// Register a domain file read by the tcb itself.
+///
+/// Write `<ident> <type> <file>`, the type is the number or the name of the domain type and
+/// the file is an absolute path or a name in the search path.
+#[derive(Debug)]
+pub struct RegisterChannel;
+
+impl RegisterChannel {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+fn store_register(data: &[u8]) -> KernelResult<()> {
+    let command = core::str::from_utf8(data).map_err(|_| linux_err::EINVAL)?;
+    let mut parts = command.split_whitespace();
+    let (Some(ident), Some(ty), Some(file), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(linux_err::EINVAL);
+    };
+    let ty = parse_domain_type(ty).ok_or(linux_err::EINVAL)?;
+    register_domain_path(ident, ty, file).map_err(|e| {
+        println!("[RegisterChannel] Register {} failed: {:?}", ident, e);
+        Error::from_errno(e as i32)
+    })
+}
+
+impl SysctlStorage for RegisterChannel {
+    fn store_value(&self, data: &[u8]) -> (usize, KernelResult<()>) {
+        (data.len(), store_register(data))
+    }
+
+    fn read_value(&self, _data: &mut KernelSlicePtrWriter) -> (usize, KernelResult<()>) {
+        (0, Err(linux_err::EPERM))
+    }
+}
@@ -0,0 +1,41 @@
+use kernel::{
+    buf::KernelSlicePtrWriter,
+    error::{linux_err, KernelResult},
+    sysctl::{read_text_at, SysctlStorage},
+};
+
+use crate::domain_loader::path::{domain_search_path, set_domain_search_path};
+
+/// The directories searched for the domain files registered by name, separated by `:`
+#[derive(Debug)]
+pub struct SearchPathChannel;
+
+impl SearchPathChannel {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl SysctlStorage for SearchPathChannel {
+    fn store_value(&self, data: &[u8]) -> (usize, KernelResult<()>) {
+        let res = core::str::from_utf8(data)
+            .ok()
+            .and_then(|path| set_domain_search_path(path).ok())
+            .ok_or(linux_err::EINVAL);
+        (data.len(), res)
+    }
+
+    fn read_value(&self, data: &mut KernelSlicePtrWriter) -> (usize, KernelResult<()>) {
+        self.read_value_at(data, 0)
+    }
+
+    fn read_value_at(
+        &self,
+        data: &mut KernelSlicePtrWriter,
+        offset: usize,
+    ) -> (usize, KernelResult<()>) {
+        let mut path = domain_search_path();
+        path.push('\n');
+        read_text_at(data, path.as_bytes(), offset)
+    }
+}
@@ -0,0 +1,39 @@
+use kernel::{
+    buf::KernelSlicePtrWriter,
+    error::{linux_err, KernelResult},
+    sysctl::{read_text_at, SysctlStorage},
+};
+
+use crate::domain_helper::SharedHeapReport;
+
+/// Read-only view of the live shared heap of each domain.
+///
+/// The types and the ages of the values are only shown for the values allocated while
+/// `/proc/sys/rust/domain/shared_heap_debug` is on.
+#[derive(Debug)]
+pub struct SharedHeapChannel;
+
+impl SharedHeapChannel {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl SysctlStorage for SharedHeapChannel {
+    fn store_value(&self, _data: &[u8]) -> (usize, KernelResult<()>) {
+        (0, Err(linux_err::EPERM))
+    }
+
+    fn read_value(&self, data: &mut KernelSlicePtrWriter) -> (usize, KernelResult<()>) {
+        self.read_value_at(data, 0)
+    }
+
+    fn read_value_at(
+        &self,
+        data: &mut KernelSlicePtrWriter,
+        offset: usize,
+    ) -> (usize, KernelResult<()>) {
+        let report = alloc::format!("{}", SharedHeapReport::all());
+        read_text_at(data, report.as_bytes(), offset)
+    }
+}
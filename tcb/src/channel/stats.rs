@@ -0,0 +1,122 @@
+use alloc::{collections::BTreeMap, string::String};
+
+use interface::DomainType;
+use kernel::{
+    buf::KernelSlicePtrWriter,
+    error::{linux_err, KernelResult},
+    str::{CStr, CString},
+    sysctl::{read_text_at, Sysctl, SysctlStorage},
+    types::Mode,
+};
+use ksync::Mutex;
+
+use crate::{
+    domain_helper::query_domain,
+    domain_proxy::{
+        block_device::BlockDeviceDomainProxy, empty_device::EmptyDeviceDomainProxy,
+        logger::LogDomainProxy, ProxyStats,
+    },
+};
+
+/// The call statistics of the domain `name`.
+///
+/// Write `0` or `reset` to clear them.
+#[derive(Debug)]
+pub struct StatsChannel {
+    name: String,
+}
+
+impl StatsChannel {
+    pub fn new(name: String) -> Self {
+        Self { name }
+    }
+
+    fn with_stats<R>(&self, f: impl FnOnce(&ProxyStats) -> R) -> Option<R> {
+        match query_domain(&self.name)? {
+            DomainType::LogDomain(d) => d
+                .downcast_arc::<LogDomainProxy>()
+                .ok()
+                .map(|p| f(p.call_stats())),
+            DomainType::EmptyDeviceDomain(d) => d
+                .downcast_arc::<EmptyDeviceDomainProxy>()
+                .ok()
+                .map(|p| f(p.call_stats())),
+            DomainType::BlockDeviceDomain(d) => d
+                .downcast_arc::<BlockDeviceDomainProxy>()
+                .ok()
+                .map(|p| f(p.call_stats())),
+        }
+    }
+}
+
+impl SysctlStorage for StatsChannel {
+    fn store_value(&self, data: &[u8]) -> (usize, KernelResult<()>) {
+        let res = match core::str::from_utf8(data).map(str::trim) {
+            Ok("0") | Ok("reset") => self.with_stats(ProxyStats::reset).ok_or(linux_err::ENOENT),
+            _ => Err(linux_err::EINVAL),
+        };
+        (data.len(), res)
+    }
+
+    fn read_value(&self, data: &mut KernelSlicePtrWriter) -> (usize, KernelResult<()>) {
+        self.read_value_at(data, 0)
+    }
+
+    fn read_value_at(
+        &self,
+        data: &mut KernelSlicePtrWriter,
+        offset: usize,
+    ) -> (usize, KernelResult<()>) {
+        match self.with_stats(|stats| alloc::format!("{}", stats)) {
+            Some(stats) => read_text_at(data, stats.as_bytes(), offset),
+            None => (0, Err(linux_err::ENOENT)),
+        }
+    }
+}
+
+struct DomainStatsFile {
+    // unregister the file before its name is freed
+    _sysctl: Sysctl<StatsChannel>,
+    _name: CString,
+}
+
+// The file is only touched with the lock of `DOMAIN_STATS` held.
+unsafe impl Send for DomainStatsFile {}
+
+static DOMAIN_STATS: Mutex<BTreeMap<String, DomainStatsFile>> = Mutex::new(BTreeMap::new());
+
+/// Create `/proc/sys/rust/domain/stats/<name>` for the loaded domain `name`
+pub fn register_domain_stats(name: &str) -> KernelResult<()> {
+    let file_name = CString::try_from_fmt(fmt!("{}", name))?;
+    // SAFETY: the name lives in the heap until the file is unregistered
+    let static_name = unsafe { &*(&*file_name as *const CStr) };
+    let sysctl = Sysctl::register(
+        c_str!("rust/domain/stats"),
+        static_name,
+        StatsChannel::new(name.into()),
+        Mode::from_int(0o644),
+    )?;
+    let old = DOMAIN_STATS.lock().insert(
+        name.into(),
+        DomainStatsFile {
+            _sysctl: sysctl,
+            _name: file_name,
+        },
+    );
+    drop(old);
+    Ok(())
+}
+
+/// Remove the stats file of the domain `name`.
+///
+/// Unregistering the sysctl table may sleep, so the file is dropped out of the lock.
+pub fn unregister_domain_stats(name: &str) {
+    let file = DOMAIN_STATS.lock().remove(name);
+    drop(file);
+}
+
+/// Remove the stats files of all domains, the module is going away
+pub fn clear_domain_stats() {
+    let files = core::mem::take(&mut *DOMAIN_STATS.lock());
+    drop(files);
+}
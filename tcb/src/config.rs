@@ -5,6 +5,8 @@ use kernel::error::{Error, KernelResult};
 pub const FRAME_SIZE: usize = 0x1000;
 /// 物理页大小的位数
 pub const FRAME_BITS: usize = 12;
+/// The hex encoded ed25519 public key used to verify the domain elf
+pub const DOMAIN_PUBLIC_KEY: Option<&str> = option_env!("DOMAIN_PUBLIC_KEY");
 
 pub fn to_kresult<T>(err: LinuxResult<T>) -> KernelResult<T> {
     match err {
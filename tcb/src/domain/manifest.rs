@@ -0,0 +1,159 @@
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::fmt::{Display, Formatter};
+
+use corelib::{LinuxError, LinuxResult};
+use interface::DomainTypeRaw;
+use ksync::Mutex;
+
+use super::registry::{load_domain, parse_domain_args, parse_domain_type, LoadArgs};
+use crate::{
+    domain_helper::{dependency_order, query_domain},
+    domain_loader::path::{read_search_path_file, register_domain_path},
+};
+
+/// One domain of the manifest, a line `<ident> <type> <file> [args]`.
+///
+/// The domain file is looked up like `register` does and registered as `<ident>`, the domain
+/// is named `<ident>` too, it must not exist yet. `xtask build` writes the manifest of the
+/// `init_members` of `domains/domain-list.toml` next to their files. The `args` are the load
+/// arguments, see [parse_domain_args].
+#[derive(Debug)]
+struct ManifestEntry<'a> {
+    line: usize,
+    ident: &'a str,
+    ty: DomainTypeRaw,
+    file: &'a str,
+    args: &'a str,
+}
+
+/// The result of loading one line of the manifest
+#[derive(Debug)]
+pub struct AutoloadResult {
+    pub line: usize,
+    pub ident: String,
+    pub result: LinuxResult<String>,
+}
+
+impl Display for AutoloadResult {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match &self.result {
+            Ok(name) => write!(f, "line {}: {} loaded as {}", self.line, self.ident, name),
+            Err(e) => write!(f, "line {}: {} failed: {:?}", self.line, self.ident, e),
+        }
+    }
+}
+
+/// The results of the last autoload, shown by `/proc/sys/rust/domain/info`
+static AUTOLOAD_RESULTS: Mutex<Vec<AutoloadResult>> = Mutex::new(Vec::new());
+
+pub fn with_autoload_results<R>(f: impl FnOnce(&[AutoloadResult]) -> R) -> R {
+    f(&AUTOLOAD_RESULTS.lock())
+}
+
+fn parse_line(line: usize, text: &str) -> LinuxResult<ManifestEntry> {
+    let text = text.trim();
+    let mut parts = text.splitn(4, char::is_whitespace);
+    let (Some(ident), Some(ty), Some(file)) = (parts.next(), parts.next(), parts.next()) else {
+        return Err(LinuxError::EINVAL);
+    };
+    let ty = parse_domain_type(ty).ok_or(LinuxError::EINVAL)?;
+    Ok(ManifestEntry {
+        line,
+        ident,
+        ty,
+        file: file.trim(),
+        args: parts.next().unwrap_or("").trim(),
+    })
+}
+
+/// Register the file of the entry and parse its arguments
+fn prepare_entry(entry: &ManifestEntry) -> LinuxResult<LoadArgs> {
+    let args = parse_domain_args(entry.ty, entry.args)?;
+    register_domain_path(entry.ident, entry.ty, entry.file)?;
+    Ok(args)
+}
+
+/// Load the domains listed in the manifest `manifest`.
+///
+/// Empty lines and lines starting with `#` are skipped. Each domain is loaded after the
+/// domains it `requires`, otherwise in the order of the manifest. A failed entry doesn't stop
+/// the others, but the domains requiring it fail too. Return the number of failed entries,
+/// the result of every entry is logged and kept for the info channel.
+pub fn autoload_domains(manifest: &str) -> LinuxResult<usize> {
+    let (path, data) = read_search_path_file(manifest)?;
+    let text = core::str::from_utf8(&data).map_err(|_| LinuxError::EINVAL)?;
+    println!("[autoload] Load domains from {}", path);
+
+    let mut results = Vec::new();
+    let mut pending = BTreeMap::new();
+    let mut requires = BTreeMap::new();
+    let mut order = Vec::new();
+    let lines = text
+        .lines()
+        .enumerate()
+        .map(|(idx, line)| (idx + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'));
+    for (line, text) in lines {
+        let res = parse_line(line, text).and_then(|entry| {
+            // checked before the file is registered, it must not replace the file of a
+            // loaded domain, e.g. the default `logger`
+            if pending.contains_key(entry.ident) || query_domain(entry.ident).is_some() {
+                return Err(LinuxError::EEXIST);
+            }
+            let args = prepare_entry(&entry)?;
+            Ok((entry, args))
+        });
+        match res {
+            Ok((entry, args)) => {
+                requires.insert(entry.ident.to_string(), args.requires().to_vec());
+                order.push(entry.ident.to_string());
+                pending.insert(entry.ident, (entry, args));
+            }
+            Err(e) => results.push(AutoloadResult {
+                line,
+                ident: text.split_whitespace().next().unwrap_or("").to_string(),
+                result: Err(e),
+            }),
+        }
+    }
+
+    // a cycle fails all entries, `dependency_order` reports it
+    let order = dependency_order(&order, &requires).unwrap_or_else(|_| {
+        pending.values().for_each(|(entry, _)| {
+            results.push(AutoloadResult {
+                line: entry.line,
+                ident: entry.ident.to_string(),
+                result: Err(LinuxError::EINVAL),
+            })
+        });
+        Vec::new()
+    });
+    for ident in order {
+        let (entry, args) = pending.remove(ident.as_str()).unwrap();
+        results.push(AutoloadResult {
+            line: entry.line,
+            ident,
+            result: load_domain(entry.ident, entry.ident, entry.ty, args, true),
+        });
+    }
+
+    results.sort_by_key(|res| res.line);
+    let failed = results.iter().filter(|res| res.result.is_err()).count();
+    for res in results.iter() {
+        match res.result {
+            Ok(_) => println!("[autoload] {}", res),
+            Err(_) => pr_err!("[autoload] {}", res),
+        }
+    }
+    println!(
+        "[autoload] {} domains loaded, {} failed",
+        results.len() - failed,
+        failed
+    );
+    *AUTOLOAD_RESULTS.lock() = results;
+    Ok(failed)
+}
@@ -1,15 +1,21 @@
-use alloc::boxed::Box;
+use alloc::{boxed::Box, vec::Vec};
+
+mod manifest;
+mod registry;
 
 use corelib::LinuxResult;
-use interface::{DomainType, DomainTypeRaw};
+use interface::DomainTypeRaw;
 use kernel::env;
+pub use manifest::{autoload_domains, with_autoload_results};
+pub use registry::{
+    load_domain, load_domains, parse_domain_args, parse_domain_type, unload_domain,
+    unload_domain_tree, LoadSpec,
+};
 
 use crate::{
-    create_domain, domain_helper,
+    domain_helper,
     domain_helper::{alloc_domain_id, DOMAIN_DATA_ALLOCATOR, SHARED_HEAP_ALLOCATOR},
     domain_loader::creator::DomainCreateImpl,
-    domain_proxy::{empty_device::EmptyDeviceDomainProxy, logger::LogDomainProxy, ProxyBuilder},
-    register_domain,
 };
 
 pub fn init_domain_system() -> LinuxResult<()> {
@@ -18,30 +24,23 @@ pub fn init_domain_system() -> LinuxResult<()> {
     pr_info!("module_alloc func ptr: {:x?}", env::MODULE_ALLOC_ADDR);
     pr_info!("module_dealloc func ptr: {:x?}", env::MODULE_MEMFREE_ADDR);
 
-    let (logger, domain_file_info) =
-        create_domain!(LogDomainProxy, DomainTypeRaw::LogDomain, "logger")?;
-    logger.init_by_box(Box::new(()))?;
-    register_domain!(
-        "logger",
-        domain_file_info,
-        DomainType::LogDomain(logger),
-        true
-    );
-    println!("Register a empty logger domain");
-
-    let (null_device, domain_file_info) = create_domain!(
-        EmptyDeviceDomainProxy,
-        DomainTypeRaw::EmptyDeviceDomain,
-        "empty_device"
-    )?;
-    null_device.init_by_box(Box::new(()))?;
-    register_domain!(
-        "empty_device",
-        domain_file_info,
-        DomainType::EmptyDeviceDomain(null_device),
-        true
-    );
-    println!("Register a empty device domain");
+    let specs = [
+        ("logger", DomainTypeRaw::LogDomain),
+        ("empty_device", DomainTypeRaw::EmptyDeviceDomain),
+    ]
+    .into_iter()
+    .map(|(ident, ty)| {
+        Ok(LoadSpec {
+            elf_ident: ident.into(),
+            ident: ident.into(),
+            ty,
+            // the tcb starts without them if their elf files are not built in
+            args: parse_domain_args(ty, "")?.with_placeholder(),
+        })
+    })
+    .collect::<LinuxResult<Vec<_>>>()?;
+    let names = load_domains(specs)?;
+    println!("Register the default domains: {:?}", names);
 
     Ok(())
 }
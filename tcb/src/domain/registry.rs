@@ -0,0 +1,387 @@
+use alloc::{boxed::Box, collections::BTreeMap, string::String, sync::Arc, vec::Vec};
+use core::any::Any;
+
+use corelib::{domain_info::DomainState, LinuxError, LinuxResult};
+use interface::{null_block::BlockArgs, DomainType, DomainTypeRaw};
+use ksync::{Lazy, RwLock};
+
+use crate::{
+    channel::{register_domain_stats, unregister_domain_stats},
+    domain_helper::{
+        add_dependency, dependency_order, dependents_closure, domain_dependents, domain_ref_count,
+        init_registered_domain, query_domain, register_domain, serve_domain, set_domain_capability,
+        set_domain_quota, set_domain_state, unregister_domain, Capability, Privilege, QuotaLimit,
+    },
+    domain_loader::creator::{create_domain_registered, create_domain_special},
+    domain_proxy::{
+        block_device::BlockDeviceDomainProxy, empty_device::EmptyDeviceDomainProxy,
+        logger::LogDomainProxy, ProxyBuilder,
+    },
+    kshim::{BlockDeviceShim, KernelShim},
+};
+
+pub type DomainArgs = Box<dyn Any + Send + Sync>;
+
+/// The parsed load arguments, the `quota_*`, `cap_*` and `requires` keys are taken by the tcb
+/// and the others are passed to the domain.
+pub struct LoadArgs {
+    args: DomainArgs,
+    quota: QuotaLimit,
+    capability: Capability,
+    /// The domains which should be loaded before this one
+    requires: Vec<String>,
+    /// Create an empty domain if the elf file is not registered
+    placeholder: bool,
+}
+
+impl LoadArgs {
+    /// The domains which should be loaded before this one
+    pub fn requires(&self) -> &[String] {
+        &self.requires
+    }
+
+    /// Create an empty domain answering `ENOSYS` if the elf file is not registered, it's
+    /// only used for the default domains of the tcb.
+    pub fn with_placeholder(mut self) -> Self {
+        self.placeholder = true;
+        self
+    }
+}
+
+/// How the domains of one type are created, initialized and attached to the kernel.
+struct DomainTypeOps {
+    /// Create a domain from the registered elf file, register it and initialize it with the
+    /// arguments
+    create: fn(&str, &str, bool, LoadArgs) -> LinuxResult<(String, DomainType)>,
+    /// Parse the `key=value` arguments passed to `init` of the domain
+    parse_args: fn(&str) -> LinuxResult<DomainArgs>,
+    /// Attach the domain to the kernel, the domain has no kernel shim if it is `None`
+    shim: Option<fn(DomainType) -> LinuxResult<Box<dyn KernelShim>>>,
+}
+
+static DOMAIN_TYPE_OPS: Lazy<BTreeMap<DomainTypeRaw, DomainTypeOps>> = Lazy::new(|| {
+    let mut ops = BTreeMap::new();
+    ops.insert(
+        DomainTypeRaw::LogDomain,
+        DomainTypeOps {
+            create: |elf_ident, ident, unique, args| {
+                create_with::<LogDomainProxy, _>(
+                    DomainTypeRaw::LogDomain,
+                    elf_ident,
+                    ident,
+                    unique,
+                    args,
+                    |proxy| DomainType::LogDomain(proxy),
+                )
+            },
+            parse_args: no_args,
+            shim: None,
+        },
+    );
+    ops.insert(
+        DomainTypeRaw::EmptyDeviceDomain,
+        DomainTypeOps {
+            create: |elf_ident, ident, unique, args| {
+                create_with::<EmptyDeviceDomainProxy, _>(
+                    DomainTypeRaw::EmptyDeviceDomain,
+                    elf_ident,
+                    ident,
+                    unique,
+                    args,
+                    |proxy| DomainType::EmptyDeviceDomain(proxy),
+                )
+            },
+            parse_args: no_args,
+            shim: None,
+        },
+    );
+    ops.insert(
+        DomainTypeRaw::BlockDeviceDomain,
+        DomainTypeOps {
+            create: |elf_ident, ident, unique, args| {
+                create_with::<BlockDeviceDomainProxy, _>(
+                    DomainTypeRaw::BlockDeviceDomain,
+                    elf_ident,
+                    ident,
+                    unique,
+                    args,
+                    |proxy| DomainType::BlockDeviceDomain(proxy),
+                )
+            },
+            parse_args: |args| Ok(Box::new(args.parse::<BlockArgs>()?)),
+            shim: Some(|domain| match domain {
+                DomainType::BlockDeviceDomain(block_device) => {
+                    let shim = BlockDeviceShim::load(block_device).map_err(|e| {
+                        pr_err!("Load block device failed: {:?}", e);
+                        LinuxError::EINVAL
+                    })?;
+                    Ok(Box::new(shim) as Box<dyn KernelShim>)
+                }
+                _ => Err(LinuxError::EINVAL),
+            }),
+        },
+    );
+    ops
+});
+
+/// The domain takes no arguments
+fn no_args(args: &str) -> LinuxResult<DomainArgs> {
+    if !args.trim().is_empty() {
+        return Err(LinuxError::EINVAL);
+    }
+    Ok(Box::new(()))
+}
+
+/// The kernel shims of the loaded domains, keyed by the domain name
+static KSHIM_OBJ: RwLock<BTreeMap<String, Box<dyn KernelShim>>> = RwLock::new(BTreeMap::new());
+
+fn create_with<P, T: ?Sized>(
+    ty: DomainTypeRaw,
+    elf_ident: &str,
+    ident: &str,
+    unique: bool,
+    args: LoadArgs,
+    wrap: fn(Arc<P>) -> DomainType,
+) -> LinuxResult<(String, DomainType)>
+where
+    P: ProxyBuilder<T = Box<T>>,
+{
+    let (proxy, domain_file_info) = if args.placeholder {
+        create_domain_special::<P, T>(ty, elf_ident, None, None)?
+    } else {
+        create_domain_registered::<P, T>(ty, elf_ident)?
+    };
+    let domain = wrap(proxy.clone());
+    let name = register_domain(ident, domain_file_info, domain.clone(), unique);
+    set_domain_quota(domain.domain_id(), args.quota);
+    set_domain_capability(domain.domain_id(), args.capability);
+    init_registered_domain(&name, || proxy.init_by_box(args.args))?;
+    Ok((name, domain))
+}
+
+/// Parse the value of a `quota_*` key, `none` means unlimited
+fn parse_quota(value: &str) -> LinuxResult<Option<usize>> {
+    match value {
+        "none" => Ok(None),
+        value => value.parse().map(Some).map_err(|_| LinuxError::EINVAL),
+    }
+}
+
+/// Parse the arguments of the domain type `ty`, they are validated by the type.
+///
+/// The memory quota of the domain is set by `quota_pages=<n>` and `quota_heap=<bytes>`.
+/// The capability of the domain is declared by `cap_lookup=<name>:<name>..` (`*` for all
+/// domains) and `cap_syscall=<create|register|update|reload>:..`, the domain has none of them
+/// by default. `requires=<name>:<name>..` declares the domains it depends on, it may look
+/// them up.
+pub fn parse_domain_args(ty: DomainTypeRaw, args: &str) -> LinuxResult<LoadArgs> {
+    let ops = domain_type_ops(ty)?;
+    let mut quota = QuotaLimit::default();
+    let mut capability = Capability::default();
+    let mut requires = Vec::new();
+    let mut domain_args = Vec::new();
+    let res = args
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|kv| !kv.is_empty())
+        .try_for_each(|kv| {
+            match kv.split_once('=') {
+                Some(("quota_pages", value)) => quota.pages = parse_quota(value)?,
+                Some(("quota_heap", value)) => quota.heap_bytes = parse_quota(value)?,
+                Some(("cap_lookup", value)) => capability.lookup.extend(
+                    value
+                        .split(':')
+                        .filter(|name| !name.is_empty())
+                        .map(String::from),
+                ),
+                Some(("requires", value)) => requires.extend(
+                    value
+                        .split(':')
+                        .filter(|name| !name.is_empty())
+                        .map(String::from),
+                ),
+                Some(("cap_syscall", value)) => {
+                    for name in value.split(':').filter(|name| !name.is_empty()) {
+                        capability.privilege |=
+                            Privilege::from_name(name).ok_or(LinuxError::EINVAL)?;
+                    }
+                }
+                _ => domain_args.push(kv),
+            }
+            Ok(())
+        })
+        .and_then(|_| (ops.parse_args)(&domain_args.join(",")));
+    match res {
+        Ok(args) => {
+            capability.lookup.extend(requires.iter().cloned());
+            Ok(LoadArgs {
+                args,
+                quota,
+                capability,
+                requires,
+                placeholder: false,
+            })
+        }
+        Err(e) => {
+            pr_err!(
+                "[parse_domain_args] Invalid arguments for {:?}: {}",
+                ty,
+                args
+            );
+            Err(e)
+        }
+    }
+}
+
+/// Parse the domain type given by its number or its name, e.g. `3` or `BlockDeviceDomain`
+pub fn parse_domain_type(ty: &str) -> Option<DomainTypeRaw> {
+    if let Ok(ty) = ty.parse::<u8>() {
+        return DomainTypeRaw::try_from(ty).ok();
+    }
+    let ty = match ty {
+        "EmptyDeviceDomain" => DomainTypeRaw::EmptyDeviceDomain,
+        "LogDomain" => DomainTypeRaw::LogDomain,
+        "BlockDeviceDomain" => DomainTypeRaw::BlockDeviceDomain,
+        _ => return None,
+    };
+    Some(ty)
+}
+
+fn domain_type_ops(ty: DomainTypeRaw) -> LinuxResult<&'static DomainTypeOps> {
+    DOMAIN_TYPE_OPS.get(&ty).ok_or_else(|| {
+        pr_err!("Unsupported domain type: {:?}", ty);
+        LinuxError::EINVAL
+    })
+}
+
+/// Create a domain of type `ty` from the registered elf `elf_ident`, initialize it and
+/// attach it to the kernel. The `args` come from [parse_domain_args].
+///
+/// If `unique` is false, the domain is named `<ident>-<n>` so that several instances can be
+/// loaded. The domains it requires should be loaded. Return the name of the domain.
+pub fn load_domain(
+    elf_ident: &str,
+    ident: &str,
+    ty: DomainTypeRaw,
+    mut args: LoadArgs,
+    unique: bool,
+) -> LinuxResult<String> {
+    let ops = domain_type_ops(ty)?;
+    let requires = core::mem::take(&mut args.requires);
+    if let Some(missing) = requires.iter().find(|name| query_domain(name).is_none()) {
+        pr_err!("[load_domain] {} requires domain {}", ident, missing);
+        return Err(LinuxError::ENOENT);
+    }
+    let (name, domain) = (ops.create)(elf_ident, ident, unique, args)?;
+    requires
+        .iter()
+        .for_each(|provider| add_dependency(&name, provider));
+    if let Some(shim) = ops.shim {
+        let domain_id = domain.domain_id();
+        match shim(domain) {
+            Ok(shim) => {
+                KSHIM_OBJ.write().insert(name.clone(), shim);
+            }
+            Err(e) => {
+                set_domain_state(domain_id, DomainState::Unloaded)?;
+                unregister_domain(&name);
+                return Err(e);
+            }
+        }
+    }
+    serve_domain(&name)?;
+    // the domain works without its stats file
+    if let Err(e) = register_domain_stats(&name) {
+        pr_err!("Failed to create the stats file of {}: {:?}", name, e);
+    }
+    Ok(name)
+}
+
+/// Detach the domain `ident` from the kernel and remove it.
+///
+/// The domain can't be unloaded if other domains depend on it or someone else still holds it.
+pub fn unload_domain(ident: &str) -> LinuxResult<()> {
+    let Some(domain) = query_domain(ident) else {
+        println!("[unload_domain] Domain {} not found", ident);
+        return Err(LinuxError::ENOENT);
+    };
+    let dependents = domain_dependents(ident);
+    if !dependents.is_empty() {
+        println!(
+            "[unload_domain] Domain {} is used by {:?}",
+            ident, dependents
+        );
+        return Err(LinuxError::EBUSY);
+    }
+    let domain_id = domain.domain_id();
+    drop(domain);
+    // the container and the kernel shim hold the domain
+    let shim_count = KSHIM_OBJ.read().contains_key(ident) as usize;
+    let ref_count = domain_ref_count(ident).unwrap_or(0);
+    if ref_count > 1 + shim_count {
+        println!(
+            "[unload_domain] Domain {} is still in use, it has {} references",
+            ident, ref_count
+        );
+        return Err(LinuxError::EBUSY);
+    }
+    let prev = set_domain_state(domain_id, DomainState::Unloaded)?;
+    println!("[unload_domain] Domain {}: {} -> unloaded", ident, prev);
+    unregister_domain(ident);
+    KSHIM_OBJ.write().remove(ident);
+    unregister_domain_stats(ident);
+    Ok(())
+}
+
+/// A domain loaded by [load_domains]
+pub struct LoadSpec {
+    pub elf_ident: String,
+    pub ident: String,
+    pub ty: DomainTypeRaw,
+    pub args: LoadArgs,
+}
+
+/// Load the domains, each one after the domains it requires. The domains are named by their
+/// `ident`.
+///
+/// If one of them fails, the domains loaded by this call are unloaded again.
+pub fn load_domains(specs: Vec<LoadSpec>) -> LinuxResult<Vec<String>> {
+    let mut pending = BTreeMap::new();
+    let mut requires = BTreeMap::new();
+    for spec in specs {
+        if query_domain(&spec.ident).is_some() || pending.contains_key(&spec.ident) {
+            pr_err!("[load_domains] Domain {} already exists", spec.ident);
+            return Err(LinuxError::EEXIST);
+        }
+        requires.insert(spec.ident.clone(), spec.args.requires.clone());
+        pending.insert(spec.ident.clone(), spec);
+    }
+    let names = pending.keys().cloned().collect::<Vec<_>>();
+    let mut loaded = Vec::with_capacity(names.len());
+    for ident in dependency_order(&names, &requires)? {
+        let spec = pending.remove(&ident).unwrap();
+        match load_domain(&spec.elf_ident, &spec.ident, spec.ty, spec.args, true) {
+            Ok(name) => loaded.push(name),
+            Err(e) => {
+                pr_err!("[load_domains] Load {} failed: {:?}", ident, e);
+                if let Err(e) = unload_domains(&loaded) {
+                    pr_err!("[load_domains] Unload {:?} failed: {:?}", loaded, e);
+                }
+                return Err(e);
+            }
+        }
+    }
+    Ok(loaded)
+}
+
+/// Unload the domains, each one before the domains it depends on.
+///
+/// Stop at the first domain which can't be unloaded.
+pub fn unload_domains(names: &[String]) -> LinuxResult<()> {
+    let order = dependency_order(names, &BTreeMap::new())?;
+    order.iter().rev().try_for_each(|name| unload_domain(name))
+}
+
+/// Unload the domain `ident` and all domains which depend on it
+pub fn unload_domain_tree(ident: &str) -> LinuxResult<()> {
+    unload_domains(&dependents_closure(ident))
+}
@@ -0,0 +1,100 @@
+use alloc::{collections::BTreeMap, string::String, vec::Vec};
+
+use bitflags::bitflags;
+use corelib::{LinuxError, LinuxResult};
+use ksync::Mutex;
+
+bitflags! {
+    /// The privileged syscalls a domain may use
+    #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+    pub struct Privilege: u32 {
+        const CREATE = 1 << 0;
+        const REGISTER = 1 << 1;
+        const UPDATE = 1 << 2;
+        const RELOAD = 1 << 3;
+    }
+}
+
+impl Privilege {
+    pub fn from_name(name: &str) -> Option<Self> {
+        let privilege = match name {
+            "create" => Self::CREATE,
+            "register" => Self::REGISTER,
+            "update" => Self::UPDATE,
+            "reload" => Self::RELOAD,
+            _ => return None,
+        };
+        Some(privilege)
+    }
+}
+
+/// What a domain may do besides serving its own calls.
+///
+/// It is declared when the domain is loaded, a domain without any declaration can't look up
+/// other domains or use the privileged syscalls.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Capability {
+    /// The names of the domains it may look up, `*` allows all domains
+    pub lookup: Vec<String>,
+    pub privilege: Privilege,
+}
+
+impl Capability {
+    fn may_lookup(&self, name: &str) -> bool {
+        self.lookup
+            .iter()
+            .any(|allowed| allowed == "*" || allowed == name)
+    }
+}
+
+static DOMAIN_CAPABILITY: Mutex<BTreeMap<u64, Capability>> = Mutex::new(BTreeMap::new());
+
+pub fn set_domain_capability(domain_id: u64, capability: Capability) {
+    DOMAIN_CAPABILITY.lock().insert(domain_id, capability);
+}
+
+/// The new domain created by an update or a reload keeps the capability of the old one
+pub fn inherit_domain_capability(old_id: u64, new_id: u64) {
+    let mut capabilities = DOMAIN_CAPABILITY.lock();
+    if let Some(capability) = capabilities.get(&old_id).cloned() {
+        capabilities.insert(new_id, capability);
+    }
+}
+
+pub(super) fn remove_domain_capability(domain_id: u64) {
+    DOMAIN_CAPABILITY.lock().remove(&domain_id);
+}
+
+/// Check whether the domain `caller` may look up the domain `name`.
+///
+/// `caller` is `None` if the tcb calls the syscall itself, which is always allowed.
+pub fn check_lookup(caller: Option<u64>, name: &str) -> LinuxResult<()> {
+    let Some(caller) = caller else {
+        return Ok(());
+    };
+    let allowed = DOMAIN_CAPABILITY
+        .lock()
+        .get(&caller)
+        .is_some_and(|capability| capability.may_lookup(name));
+    if !allowed {
+        warn!("[Domain: {}] denied to look up domain {}", caller, name);
+        return Err(LinuxError::EPERM);
+    }
+    Ok(())
+}
+
+/// Check whether the domain `caller` may use the privileged syscalls `privilege`
+pub fn check_privilege(caller: Option<u64>, privilege: Privilege) -> LinuxResult<()> {
+    let Some(caller) = caller else {
+        return Ok(());
+    };
+    let allowed = DOMAIN_CAPABILITY
+        .lock()
+        .get(&caller)
+        .is_some_and(|capability| capability.privilege.contains(privilege));
+    if !allowed {
+        warn!("[Domain: {}] denied to use {:?}", caller, privilege);
+        return Err(LinuxError::EPERM);
+    }
+    Ok(())
+}
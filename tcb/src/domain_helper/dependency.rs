@@ -0,0 +1,133 @@
+use alloc::{
+    collections::{BTreeMap, BTreeSet},
+    string::String,
+    vec::Vec,
+};
+
+use corelib::{LinuxError, LinuxResult};
+use ksync::Mutex;
+
+use crate::domain_helper::DOMAIN_INFO;
+
+/// The domains each domain depends on, keyed by the domain name.
+///
+/// The edges are kept by name, so they survive the updates and the reloads of both sides.
+static DOMAIN_DEPENDENCY: Mutex<BTreeMap<String, BTreeSet<String>>> = Mutex::new(BTreeMap::new());
+
+/// Record that the domain `dependent` uses the domain `provider`
+pub fn add_dependency(dependent: &str, provider: &str) {
+    if dependent == provider {
+        return;
+    }
+    let added = DOMAIN_DEPENDENCY
+        .lock()
+        .entry(dependent.into())
+        .or_default()
+        .insert(provider.into());
+    if added {
+        println!("[Dependency] {} -> {}", dependent, provider);
+    }
+}
+
+/// Record that the domain `domain_id` acquired the domain `provider`
+pub fn add_dependency_by_id(domain_id: u64, provider: &str) {
+    let dependent = DOMAIN_INFO
+        .lock()
+        .domain_list
+        .get(&domain_id)
+        .map(|data| data.name.clone());
+    if let Some(dependent) = dependent {
+        add_dependency(&dependent, provider);
+    }
+}
+
+/// Forget the domain `name` and all edges from or to it
+pub(super) fn remove_dependency(name: &str) {
+    let mut deps = DOMAIN_DEPENDENCY.lock();
+    deps.remove(name);
+    deps.values_mut().for_each(|providers| {
+        providers.remove(name);
+    });
+}
+
+/// The domains which the domain `name` depends on
+pub fn domain_providers(name: &str) -> Vec<String> {
+    DOMAIN_DEPENDENCY
+        .lock()
+        .get(name)
+        .map(|providers| providers.iter().cloned().collect())
+        .unwrap_or_default()
+}
+
+/// The domains which depend on the domain `name`
+pub fn domain_dependents(name: &str) -> Vec<String> {
+    DOMAIN_DEPENDENCY
+        .lock()
+        .iter()
+        .filter(|(_, providers)| providers.contains(name))
+        .map(|(dependent, _)| dependent.clone())
+        .collect()
+}
+
+/// Sort `names` so that every domain comes after the domains it depends on.
+///
+/// `extra` gives the dependencies which are not recorded yet, e.g. the ones declared by the
+/// domains going to be loaded. Only the dependencies inside `names` decide the order, return
+/// `EINVAL` if they form a cycle.
+pub fn dependency_order(
+    names: &[String],
+    extra: &BTreeMap<String, Vec<String>>,
+) -> LinuxResult<Vec<String>> {
+    let deps = DOMAIN_DEPENDENCY.lock();
+    let providers = |name: &String| {
+        deps.get(name)
+            .into_iter()
+            .flatten()
+            .chain(extra.get(name).into_iter().flatten())
+            .filter(|provider| names.contains(*provider))
+            .cloned()
+            .collect::<BTreeSet<_>>()
+    };
+    let mut pending = names
+        .iter()
+        .map(|name| (name.clone(), providers(name)))
+        .collect::<BTreeMap<_, _>>();
+    drop(deps);
+    let mut order = Vec::with_capacity(names.len());
+    while !pending.is_empty() {
+        let ready = pending
+            .iter()
+            .filter(|(_, providers)| providers.is_empty())
+            .map(|(name, _)| name.clone())
+            .collect::<Vec<_>>();
+        if ready.is_empty() {
+            pr_err!(
+                "[Dependency] cycle in {:?}",
+                pending.keys().collect::<Vec<_>>()
+            );
+            return Err(LinuxError::EINVAL);
+        }
+        for name in ready {
+            pending.remove(&name);
+            pending.values_mut().for_each(|providers| {
+                providers.remove(&name);
+            });
+            order.push(name);
+        }
+    }
+    Ok(order)
+}
+
+/// The domain `name` and all domains which depend on it directly or indirectly
+pub fn dependents_closure(name: &str) -> Vec<String> {
+    let mut closure = Vec::new();
+    let mut next = alloc::vec![String::from(name)];
+    while let Some(name) = next.pop() {
+        if closure.contains(&name) {
+            continue;
+        }
+        next.extend(domain_dependents(&name));
+        closure.push(name);
+    }
+    closure
+}
@@ -1,3 +1,6 @@
+mod capability;
+mod dependency;
+mod quota;
 mod resource;
 mod sheap;
 mod storage_heap;
@@ -15,16 +18,22 @@ use alloc::{
 use core::sync::atomic::AtomicU64;
 
 use basic::DomainInfoSet;
+pub use capability::*;
 use corelib::{
-    domain_info::{DomainDataInfo, DomainFileInfo, DomainInfo},
-    LinuxResult,
+    domain_info::{DomainDataInfo, DomainFileInfo, DomainInfo, DomainState},
+    LinuxError, LinuxResult,
 };
+pub use dependency::*;
 pub use interface::DomainType;
 use ksync::{Lazy, Mutex, Once};
+pub use quota::*;
 pub use resource::*;
-pub use sheap::{checkout_shared_data, FreeShared, SHARED_HEAP_ALLOCATOR};
+pub use sheap::{
+    checkout_shared_data, domain_shared_heap_usage, FreeShared, SharedHeapReport,
+    SHARED_HEAP_ALLOCATOR, SHARED_HEAP_DEBUG,
+};
 pub use storage_heap::*;
-pub use syscall::DOMAIN_SYS;
+pub use syscall::{domain_syscall, DOMAIN_SYS};
 
 static DOMAIN_IDS: AtomicU64 = AtomicU64::new(0);
 
@@ -112,6 +121,7 @@ pub fn register_domain(
         ty,
         panic_count: 0,
         file_info: domain_file,
+        state: DomainState::Created,
     };
 
     DOMAIN_INFO
@@ -130,6 +140,92 @@ pub fn unregister_domain(identifier: &str) {
         let domain_id = domain.domain_id();
         DOMAIN_INFO.lock().domain_list.remove(&domain_id);
     }
+    dependency::remove_dependency(identifier);
+}
+
+/// Move the information of a reloaded domain to its new id.
+pub fn reload_domain_info(old_id: u64, new_id: u64, file_info: DomainFileInfo) {
+    let mut info = DOMAIN_INFO.lock();
+    if let Some(mut data) = info.domain_list.remove(&old_id) {
+        data.file_info = file_info;
+        data.state = DomainState::Serving;
+        info.domain_list.insert(new_id, data);
+    }
+}
+
+/// Move the domain `domain_id` to the `next` lifecycle state and return the previous one.
+///
+/// Return `EBUSY` if the transition is not allowed in the current state.
+pub fn set_domain_state(domain_id: u64, next: DomainState) -> LinuxResult<DomainState> {
+    let mut info = DOMAIN_INFO.lock();
+    let data = info
+        .domain_list
+        .get_mut(&domain_id)
+        .ok_or(LinuxError::ENOENT)?;
+    let prev = data.state;
+    if !prev.can_transition_to(next) {
+        println!(
+            "[set_domain_state] Domain {} can't move from {} to {}",
+            data.name, prev, next
+        );
+        return Err(LinuxError::EBUSY);
+    }
+    data.state = next;
+    Ok(prev)
+}
+
+/// Mark the domain `domain_id` as crashed and count the crash.
+///
+/// The crash is counted even if the domain is crashed already. It doesn't sleep, so it can
+/// be called on any call path.
+pub fn crash_domain(domain_id: u64) -> LinuxResult<()> {
+    let mut info = DOMAIN_INFO.lock();
+    let data = info
+        .domain_list
+        .get_mut(&domain_id)
+        .ok_or(LinuxError::ENOENT)?;
+    data.panic_count += 1;
+    if data.state == DomainState::Crashed {
+        return Ok(());
+    }
+    if !data.state.can_transition_to(DomainState::Crashed) {
+        println!(
+            "[crash_domain] Domain {} can't move from {} to crashed",
+            data.name, data.state
+        );
+        return Err(LinuxError::EBUSY);
+    }
+    data.state = DomainState::Crashed;
+    Ok(())
+}
+
+/// Initialize the registered domain `identifier` with `init` and mark it as initialized.
+///
+/// The domain is unregistered if `init` fails.
+pub fn init_registered_domain(
+    identifier: &str,
+    init: impl FnOnce() -> LinuxResult<()>,
+) -> LinuxResult<()> {
+    let domain_id = query_domain(identifier)
+        .ok_or(LinuxError::ENOENT)?
+        .domain_id();
+    if let Err(e) = init() {
+        println!("Init domain {} failed: {:?}", identifier, e);
+        set_domain_state(domain_id, DomainState::Unloaded)?;
+        unregister_domain(identifier);
+        return Err(e);
+    }
+    set_domain_state(domain_id, DomainState::Initialized)?;
+    Ok(())
+}
+
+/// Mark the initialized domain `identifier` as ready to serve requests
+pub fn serve_domain(identifier: &str) -> LinuxResult<()> {
+    let domain_id = query_domain(identifier)
+        .ok_or(LinuxError::ENOENT)?
+        .domain_id();
+    set_domain_state(domain_id, DomainState::Serving)?;
+    Ok(())
 }
 
 /// Get the reference count of the domain
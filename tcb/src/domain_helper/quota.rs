@@ -0,0 +1,123 @@
+use alloc::{collections::BTreeMap, vec::Vec};
+
+use corelib::{LinuxError, LinuxResult};
+use ksync::Mutex;
+
+/// The memory limits of a domain, `None` means unlimited
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct QuotaLimit {
+    /// The max number of private pages
+    pub pages: Option<usize>,
+    /// The max bytes of shared heap
+    pub heap_bytes: Option<usize>,
+}
+
+/// The memory usage of a domain and its limits
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DomainQuota {
+    pub limit: QuotaLimit,
+    pub pages: usize,
+    pub pages_peak: usize,
+    pub heap_bytes: usize,
+    pub heap_peak: usize,
+}
+
+static DOMAIN_QUOTA: Mutex<BTreeMap<u64, DomainQuota>> = Mutex::new(BTreeMap::new());
+
+fn charge(used: &mut usize, peak: &mut usize, limit: Option<usize>, n: usize) -> LinuxResult<()> {
+    let next = used.checked_add(n).ok_or(LinuxError::ENOMEM)?;
+    if let Some(limit) = limit
+        && next > limit
+    {
+        return Err(LinuxError::ENOMEM);
+    }
+    *used = next;
+    *peak = (*peak).max(next);
+    Ok(())
+}
+
+/// Charge `n` private pages to the domain, fail with `ENOMEM` if the domain exceeds its quota
+pub fn charge_pages(domain_id: u64, n: usize) -> LinuxResult<()> {
+    let mut quotas = DOMAIN_QUOTA.lock();
+    let quota = quotas.entry(domain_id).or_default();
+    charge(
+        &mut quota.pages,
+        &mut quota.pages_peak,
+        quota.limit.pages,
+        n,
+    )
+    .inspect_err(|_| {
+        warn!(
+            "[Domain: {}] page quota exceeded: {} + {} > {:?}",
+            domain_id, quota.pages, n, quota.limit.pages
+        );
+    })
+}
+
+pub fn uncharge_pages(domain_id: u64, n: usize) {
+    if let Some(quota) = DOMAIN_QUOTA.lock().get_mut(&domain_id) {
+        quota.pages = quota.pages.saturating_sub(n);
+    }
+}
+
+/// Charge `bytes` of shared heap to the domain, fail with `ENOMEM` if the domain exceeds its quota
+pub fn charge_shared_heap(domain_id: u64, bytes: usize) -> LinuxResult<()> {
+    let mut quotas = DOMAIN_QUOTA.lock();
+    let quota = quotas.entry(domain_id).or_default();
+    charge(
+        &mut quota.heap_bytes,
+        &mut quota.heap_peak,
+        quota.limit.heap_bytes,
+        bytes,
+    )
+    .inspect_err(|_| {
+        warn!(
+            "[Domain: {}] shared heap quota exceeded: {} + {} > {:?}",
+            domain_id, quota.heap_bytes, bytes, quota.limit.heap_bytes
+        );
+    })
+}
+
+pub fn uncharge_shared_heap(domain_id: u64, bytes: usize) {
+    if let Some(quota) = DOMAIN_QUOTA.lock().get_mut(&domain_id) {
+        quota.heap_bytes = quota.heap_bytes.saturating_sub(bytes);
+    }
+}
+
+/// Move the shared heap charge of the data kept by the new version of a domain
+pub(super) fn move_shared_heap_charge(old_id: u64, new_id: u64, bytes: usize) {
+    uncharge_shared_heap(old_id, bytes);
+    let mut quotas = DOMAIN_QUOTA.lock();
+    let quota = quotas.entry(new_id).or_default();
+    // the data is already allocated, so the limit is not checked here
+    quota.heap_bytes += bytes;
+    quota.heap_peak = quota.heap_peak.max(quota.heap_bytes);
+}
+
+/// Set the limits of the domain.
+///
+/// The new limits only apply to the following allocations, the memory already allocated is kept.
+pub fn set_domain_quota(domain_id: u64, limit: QuotaLimit) {
+    DOMAIN_QUOTA.lock().entry(domain_id).or_default().limit = limit;
+}
+
+/// The new domain created by an update or a reload keeps the limits of the old one
+pub fn inherit_domain_quota(old_id: u64, new_id: u64) {
+    let mut quotas = DOMAIN_QUOTA.lock();
+    if let Some(limit) = quotas.get(&old_id).map(|quota| quota.limit) {
+        quotas.entry(new_id).or_default().limit = limit;
+    }
+}
+
+pub(super) fn remove_domain_quota(domain_id: u64) {
+    DOMAIN_QUOTA.lock().remove(&domain_id);
+}
+
+/// The memory usage of all domains, keyed by the domain id
+pub fn domain_quotas() -> Vec<(u64, DomainQuota)> {
+    DOMAIN_QUOTA
+        .lock()
+        .iter()
+        .map(|(id, quota)| (*id, *quota))
+        .collect()
+}
@@ -5,8 +5,11 @@ use ksync::Mutex;
 use crate::{
     config::FRAME_BITS,
     domain_helper::{
+        capability::remove_domain_capability,
+        quota::remove_domain_quota,
         sheap::{free_domain_shared_data, FreeShared},
-        storage_heap::DomainDataMap,
+        storage_heap::{move_domain_database, DomainDataMap},
+        syscall::remove_domain_syscall,
     },
 };
 
@@ -29,9 +32,14 @@ impl DomainResource {
         vec.push(page);
     }
 
-    pub fn free_page_map(&mut self, domain_id: u64, page: usize) {
-        let vec = self.page_map.get_mut(&domain_id).unwrap();
+    /// Remove `page` from the pages of `domain_id`, return false if the domain doesn't own it
+    pub fn free_page_map(&mut self, domain_id: u64, page: usize) -> bool {
+        let Some(vec) = self.page_map.get_mut(&domain_id) else {
+            return false;
+        };
+        let len = vec.len();
         vec.retain(|(s, _)| *s != page);
+        vec.len() != len
     }
 
     pub fn insert_box_data(&mut self, domain_id: u64, data: usize) {
@@ -71,4 +79,17 @@ pub fn free_domain_resource(domain_id: u64, free_shared: FreeShared) {
         drop(data_map);
         println_color!(31, "[Domain: {}] free DomainDataMap resource", domain_id);
     }
+    remove_domain_quota(domain_id);
+    remove_domain_capability(domain_id);
+    remove_domain_syscall(domain_id);
+}
+
+/// Free the new domain `new_id` which failed to replace `old_id`.
+///
+/// The database moved to the new domain is given back to the old one, the shared data owned
+/// by the old domain is not touched.
+pub fn discard_new_domain(old_id: u64, new_id: u64) {
+    println!("discard new domain {}, keep domain {}", new_id, old_id);
+    move_domain_database(new_id, old_id);
+    free_domain_resource(new_id, FreeShared::Free);
 }
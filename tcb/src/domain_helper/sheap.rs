@@ -1,107 +1,356 @@
+//! The shared heap used by [rref::RRef] and [rref::RRefVec].
+//!
+//! Every value is preceded by a [SharedHeapHeader] which keeps its owner, so the allocator
+//! finds the allocation from the value pointer alone. The small values come from slabs of
+//! power-of-two size classes, each CPU caches free blocks of every class and the CPUs only
+//! meet at the depot when they exchange a whole batch. The live values are linked into the
+//! owner index of the CPU which allocated them, the sweeps walk all of them. A slab goes back
+//! to the kernel when all of its blocks are in the depot and the depot grows too large. A
+//! pointer given to `dealloc` is looked up in the slabs and the large blocks before its
+//! header is read, so a stale pointer into returned memory is reported, not dereferenced.
+//!
+//! In the diagnostic mode, see `/proc/sys/rust/domain/shared_heap_debug`, every allocation is
+//! also recorded with its type and time, so the values a domain leaves behind can be traced.
 use alloc::{
     alloc::{alloc, dealloc},
     collections::BTreeMap,
-    sync::Arc,
+    string::{String, ToString},
     vec,
     vec::Vec,
 };
-use core::{alloc::Layout, any::TypeId};
+use core::{
+    alloc::Layout,
+    any::TypeId,
+    fmt,
+    fmt::{Display, Formatter, Write},
+    ptr,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+};
 
-use hashbrown::HashMap;
-use ksync::{Lazy, Mutex};
+use kernel::sync::CpuId;
+use ksync::{Lazy, Mutex, MutexGuard, RwLock};
 use rref::{SharedHeapAlloc, SharedHeapAllocation};
 
-use crate::config::FRAME_SIZE;
+use crate::{
+    config::FRAME_SIZE,
+    domain_helper::quota::{charge_shared_heap, move_shared_heap_charge, uncharge_shared_heap},
+    domain_proxy::now_ns,
+};
 
-static SHARED_HEAP: Mutex<BTreeMap<usize, SharedHeapAllocation>> = Mutex::new(BTreeMap::new());
 pub static SHARED_HEAP_ALLOCATOR: &'static dyn SharedHeapAlloc = &SharedHeapAllocator;
 
-struct SharedHeapAllocationPart {
-    value_pointer: *mut u8,
-    domain_id_pointer: *mut u64,
+/// Record every allocation, it only applies to the following allocations
+pub static SHARED_HEAP_DEBUG: AtomicBool = AtomicBool::new(false);
+/// The double frees and the frees of the pointers not on the shared heap
+static SHARED_HEAP_BAD_FREES: AtomicU64 = AtomicU64::new(0);
+
+/// The number of shards of the caches and the owner index, a CPU uses the shard
+/// `cpu % SHARDS`
+const SHARDS: usize = 64;
+/// The smallest size class is `1 << MIN_CLASS_SHIFT` bytes
+const MIN_CLASS_SHIFT: usize = 7;
+/// The largest size class is `1 << MAX_CLASS_SHIFT` bytes, larger blocks are allocated
+/// directly
+const MAX_CLASS_SHIFT: usize = 17;
+const CLASSES: usize = MAX_CLASS_SHIFT - MIN_CLASS_SHIFT + 1;
+/// The class of the blocks which are allocated directly
+const LARGE_CLASS: usize = usize::MAX;
+/// The min size of a slab, a slab holds at least [SLAB_MIN_BLOCKS] blocks
+const SLAB_SIZE: usize = 64 * 1024;
+const SLAB_MIN_BLOCKS: usize = 4;
+/// The number of blocks moved between a CPU cache and the depot at once
+const BATCH: usize = 32;
+/// The depot of a class is reclaimed when it holds more batches
+const DEPOT_MAX_BATCHES: usize = 16;
+
+const HEADER_LIVE: u64 = 0x5348_4541_505f_4c56;
+const HEADER_FREE: u64 = 0x5348_4541_505f_4652;
+/// The value is live and will be freed with its owner, see [free_domain_shared_data]
+const HEADER_FREEING: u64 = 0x5348_4541_505f_4647;
+
+/// The header placed right before every value of the shared heap.
+///
+/// [SharedHeapAllocation::domain_id_pointer] points to `domain_id`, so moving the value to
+/// another domain doesn't touch the allocator.
+#[repr(C)]
+struct SharedHeapHeader {
+    /// The domain which owns the value
+    domain_id: u64,
+    /// The domain which is charged for the value.
+    ///
+    /// The owner of the data may change when it is moved to another domain, but the charge
+    /// stays with the domain which allocated it.
+    charged: u64,
+    magic: u64,
+    layout: Layout,
+    type_id: TypeId,
+    drop_fn: fn(TypeId, *mut u8),
+    /// The start and the size of the block holding the header and the value
+    block: *mut u8,
+    block_size: usize,
+    class: usize,
+    /// The shard of the owner index which links the header
+    shard: usize,
+    prev: *mut SharedHeapHeader,
+    next: *mut SharedHeapHeader,
 }
-unsafe impl Send for SharedHeapAllocationPart {}
 
-pub struct SharedHeapCache {
-    cache: Mutex<HashMap<Layout, Arc<Mutex<Vec<SharedHeapAllocationPart>>>>>,
+const HEADER_SIZE: usize = size_of::<SharedHeapHeader>();
+
+impl SharedHeapHeader {
+    /// Find the header of the value at `ptr`
+    unsafe fn from_value(ptr: *mut u8) -> *mut SharedHeapHeader {
+        ptr.sub(HEADER_SIZE) as *mut SharedHeapHeader
+    }
+
+    fn value(&self) -> usize {
+        self as *const Self as usize + HEADER_SIZE
+    }
+
+    fn allocation(&mut self) -> SharedHeapAllocation {
+        SharedHeapAllocation {
+            value_pointer: unsafe { (self as *mut Self as *mut u8).add(HEADER_SIZE) },
+            domain_id_pointer: &mut self.domain_id,
+            layout: self.layout,
+            type_id: self.type_id,
+            drop_fn: self.drop_fn,
+        }
+    }
 }
 
-impl SharedHeapCache {
-    fn new() -> Self {
-        Self {
-            cache: Mutex::new(HashMap::new()),
+/// What the diagnostic mode records for an allocation
+#[derive(Debug)]
+struct AllocRecord {
+    /// The domain which allocated the value
+    domain_id: u64,
+    /// The name is copied, the domain which owns the string may be gone
+    type_name: String,
+    time_ns: u64,
+}
+
+/// The free blocks cached by a CPU and the live values allocated on it
+struct ShardInner {
+    /// The addresses of the free blocks of each size class
+    free: [Vec<usize>; CLASSES],
+    live: *mut SharedHeapHeader,
+    live_count: usize,
+    /// The records of the live values allocated in the diagnostic mode, keyed by the value
+    records: BTreeMap<usize, AllocRecord>,
+}
+
+unsafe impl Send for ShardInner {}
+
+impl ShardInner {
+    unsafe fn link(&mut self, header: *mut SharedHeapHeader) {
+        (*header).prev = ptr::null_mut();
+        (*header).next = self.live;
+        if !self.live.is_null() {
+            (*self.live).prev = header;
         }
+        self.live = header;
+        self.live_count += 1;
     }
-    fn get(&self, layout: &Layout) -> Option<SharedHeapAllocationPart> {
-        let vec = self.cache.lock().get(layout).cloned();
-        if let Some(vec) = vec {
-            let mut vec = vec.lock();
-            if let Some(part) = vec.pop() {
-                return Some(part);
-            }
+
+    unsafe fn unlink(&mut self, header: *mut SharedHeapHeader) {
+        let (prev, next) = ((*header).prev, (*header).next);
+        if prev.is_null() {
+            self.live = next;
+        } else {
+            (*prev).next = next;
+        }
+        if !next.is_null() {
+            (*next).prev = prev;
         }
-        None
+        self.live_count -= 1;
     }
-    fn insert(&self, layout: Layout, part: SharedHeapAllocationPart) {
-        let mut cache = self.cache.lock();
-        let vec = cache
-            .entry(layout)
-            .or_insert_with(|| Arc::new(Mutex::new(Vec::with_capacity(16))))
-            .clone();
-        drop(cache);
-        let mut vec = vec.lock();
-        vec.push(part);
+
+    fn for_each_live(&mut self, mut f: impl FnMut(&mut SharedHeapHeader, Option<&AllocRecord>)) {
+        let mut header = self.live;
+        while let Some(h) = unsafe { header.as_mut() } {
+            header = h.next;
+            let record = self.records.get(&h.value());
+            f(h, record);
+        }
     }
 }
 
-static SHARED_HEAP_CACHE: Lazy<SharedHeapCache> = Lazy::new(SharedHeapCache::new);
-pub struct SharedHeapAllocator;
+/// Keep each shard in its own cache line
+#[repr(align(64))]
+struct Shard(Mutex<ShardInner>);
 
-impl SharedHeapAllocator {
-    fn alloc_from_cache(
-        layout: &Layout,
-        type_id: TypeId,
-        drop_fn: fn(TypeId, *mut u8),
-    ) -> Option<(*mut u8, SharedHeapAllocation)> {
-        let part = SHARED_HEAP_CACHE.get(layout);
-        if let Some(part) = part {
-            let ptr = part.value_pointer;
-            let domain_id_pointer = part.domain_id_pointer;
-            let res = SharedHeapAllocation {
-                value_pointer: ptr,
-                domain_id_pointer,
-                layout: *layout,
-                type_id,
-                drop_fn,
+static SHARED_HEAP: Lazy<Vec<Shard>> = Lazy::new(|| {
+    (0..SHARDS)
+        .map(|_| {
+            Shard(Mutex::new(ShardInner {
+                free: core::array::from_fn(|_| Vec::new()),
+                live: ptr::null_mut(),
+                live_count: 0,
+                records: BTreeMap::new(),
+            }))
+        })
+        .collect()
+});
+
+/// The batches of free blocks shared by all CPUs, one depot for each size class
+static SHARED_HEAP_DEPOT: Lazy<Vec<Mutex<Vec<Vec<usize>>>>> =
+    Lazy::new(|| (0..CLASSES).map(|_| Mutex::new(Vec::new())).collect());
+
+/// The memory of the shared heap, the start address of every slab and large block to its
+/// size. A header is only read while it is found here, the memory is removed under the write
+/// lock before it's returned to the kernel.
+static SHARED_HEAP_SLABS: RwLock<BTreeMap<usize, usize>> = RwLock::new(BTreeMap::new());
+
+/// Whether the header of the value at `ptr` lies in the memory of the shared heap
+fn header_in_heap(slabs: &BTreeMap<usize, usize>, ptr: *mut u8) -> bool {
+    let Some(start) = (ptr as usize).checked_sub(HEADER_SIZE) else {
+        return false;
+    };
+    slabs
+        .range(..=start)
+        .next_back()
+        .is_some_and(|(slab, size)| ptr as usize <= slab + size)
+}
+
+/// Lock the shard which links the header.
+///
+/// The shard is checked again under its lock, the block may be freed and allocated on
+/// another CPU meanwhile. The caller holds [SHARED_HEAP_SLABS], so the header can be read.
+unsafe fn lock_shard(header: *const SharedHeapHeader) -> MutexGuard<'static, ShardInner> {
+    loop {
+        let shard = (*header).shard % SHARDS;
+        let inner = SHARED_HEAP[shard].0.lock();
+        if (*header).shard % SHARDS == shard {
+            return inner;
+        }
+    }
+}
+
+fn current_shard() -> usize {
+    CpuId::read(|cpu| cpu as usize % SHARDS)
+}
+
+fn class_of(block_size: usize) -> usize {
+    if block_size > 1 << MAX_CLASS_SHIFT {
+        return LARGE_CLASS;
+    }
+    let shift = block_size.next_power_of_two().trailing_zeros() as usize;
+    shift.max(MIN_CLASS_SHIFT) - MIN_CLASS_SHIFT
+}
+
+fn class_size(class: usize) -> usize {
+    1 << (class + MIN_CLASS_SHIFT)
+}
+
+fn slab_size(class: usize) -> usize {
+    SLAB_SIZE.max(class_size(class) * SLAB_MIN_BLOCKS)
+}
+
+/// The size class and the size of the block holding a value at `offset`
+fn block_layout(offset: usize, align: usize, size: usize) -> Option<(usize, usize)> {
+    let size = offset.checked_add(size)?;
+    match class_of(size) {
+        class if class != LARGE_CLASS && align <= FRAME_SIZE => Some((class, class_size(class))),
+        // the large block is only page aligned, leave room to align the value
+        _ => Some((LARGE_CLASS, size.checked_add(align)?)),
+    }
+}
+
+/// Get a batch of free blocks of `class` from the depot, or carve a new slab into blocks.
+///
+/// The blocks are reused by the later allocations of the same class, the slab is returned
+/// by [reclaim_slabs].
+unsafe fn refill(class: usize) -> Option<Vec<usize>> {
+    if let Some(batch) = SHARED_HEAP_DEPOT[class].lock().pop() {
+        return Some(batch);
+    }
+    let size = class_size(class);
+    let slab_size = slab_size(class);
+    // the slab is page aligned, so every block is aligned to its size up to a page
+    let slab = alloc(Layout::from_size_align(slab_size, FRAME_SIZE).ok()?);
+    if slab.is_null() {
+        log::error!("<SharedHeap> alloc slab of class {} failed", size);
+        return None;
+    }
+    SHARED_HEAP_SLABS.write().insert(slab as usize, slab_size);
+    Some(
+        (0..slab_size / size)
+            .map(|i| slab as usize + i * size)
+            .collect(),
+    )
+}
+
+/// Return the surplus of a CPU cache to the depot, return true if the depot should be
+/// reclaimed
+fn trim(free: &mut Vec<usize>, class: usize) -> bool {
+    if free.len() > 2 * BATCH {
+        let batch = free.split_off(free.len() - BATCH);
+        let mut depot = SHARED_HEAP_DEPOT[class].lock();
+        depot.push(batch);
+        return depot.len() > DEPOT_MAX_BATCHES;
+    }
+    false
+}
+
+/// Return the slabs of `class` whose blocks are all in the depot to the kernel.
+///
+/// The blocks cached by the CPUs are not counted, their slabs are kept. A double free of a
+/// value in a returned slab is reported as a free of a pointer not on the shared heap.
+unsafe fn reclaim_slabs(class: usize) {
+    let blocks = {
+        let mut depot = SHARED_HEAP_DEPOT[class].lock();
+        if depot.len() <= DEPOT_MAX_BATCHES {
+            return;
+        }
+        core::mem::take(&mut *depot)
+    };
+    let slab_size = slab_size(class);
+    let per_slab = slab_size / class_size(class);
+    let mut by_slab = BTreeMap::<usize, Vec<usize>>::new();
+    {
+        let slabs = SHARED_HEAP_SLABS.read();
+        for block in blocks.into_iter().flatten() {
+            let Some((slab, _)) = slabs.range(..=block).next_back() else {
+                continue;
             };
-            return Some((ptr, res));
-        };
-        None
+            by_slab.entry(*slab).or_default().push(block);
+        }
     }
+    let mut rest = Vec::new();
+    for (slab, blocks) in by_slab {
+        if blocks.len() == per_slab {
+            // no dealloc is reading a header in the slab once it's removed
+            SHARED_HEAP_SLABS.write().remove(&slab);
+            dealloc(
+                slab as *mut u8,
+                Layout::from_size_align_unchecked(slab_size, FRAME_SIZE),
+            );
+        } else {
+            rest.extend(blocks);
+        }
+    }
+    SHARED_HEAP_DEPOT[class]
+        .lock()
+        .extend(rest.chunks(BATCH).map(|batch| batch.to_vec()));
+}
 
-    unsafe fn alloc_from_heap(
-        layout: Layout,
-        type_id: TypeId,
-        drop_fn: fn(TypeId, *mut u8),
-    ) -> Option<(*mut u8, SharedHeapAllocation)> {
-        let ptr = alloc(layout);
-        if ptr.is_null() {
-            panic!("<SharedHeap> alloc layout: {:?} failed", layout);
-        }
-        log::error!(
-            "<SharedHeap> alloc size: {}, ptr: {:#x}",
-            layout.size(),
-            ptr as usize
-        );
-        let domain_id_pointer = alloc(Layout::for_value(&0u64)) as *mut u64;
-        let res = SharedHeapAllocation {
-            value_pointer: ptr,
-            domain_id_pointer,
-            layout,
-            type_id,
-            drop_fn,
-        };
-        Some((ptr, res))
+pub struct SharedHeapAllocator;
+
+impl SharedHeapAllocator {
+    /// Allocate a block of `block_size` bytes, return the block and the cached blocks left
+    /// by the refill
+    unsafe fn alloc_block(class: usize, block_size: usize) -> Option<(usize, Vec<usize>)> {
+        if class == LARGE_CLASS {
+            let block = alloc(Layout::from_size_align(block_size, FRAME_SIZE).ok()?);
+            if block.is_null() {
+                log::error!("<SharedHeap> alloc size: {} failed", block_size);
+                return None;
+            }
+            SHARED_HEAP_SLABS.write().insert(block as usize, block_size);
+            return Some((block as usize, Vec::new()));
+        }
+        let mut blocks = refill(class)?;
+        let block = blocks.pop()?;
+        Some((block, blocks))
     }
 }
 
@@ -110,70 +359,295 @@ impl SharedHeapAlloc for SharedHeapAllocator {
         &self,
         layout: Layout,
         type_id: TypeId,
+        type_name: &'static str,
         drop_fn: fn(TypeId, *mut u8),
+        domain_id: u64,
     ) -> Option<SharedHeapAllocation> {
-        if layout.size() > FRAME_SIZE {
-            let (ptr, res) = SharedHeapAllocator::alloc_from_heap(layout, type_id, drop_fn)?;
-            let mut shared_heap = SHARED_HEAP.lock();
-            shared_heap.insert(ptr as usize, res);
-            return Some(res);
-        }
-        let mut shared_heap = SHARED_HEAP.lock();
-        let res = SharedHeapAllocator::alloc_from_cache(&layout, type_id, drop_fn);
-        let (ptr, res) = if let Some((ptr, res)) = res {
-            (ptr, res)
-        } else {
-            SharedHeapAllocator::alloc_from_heap(layout, type_id, drop_fn)?
+        charge_shared_heap(domain_id, layout.size()).ok()?;
+        let record = SHARED_HEAP_DEBUG
+            .load(Ordering::Relaxed)
+            .then(|| AllocRecord {
+                domain_id,
+                type_name: type_name.to_string(),
+                time_ns: now_ns(),
+            });
+        let align = layout.align().max(align_of::<SharedHeapHeader>());
+        let offset = HEADER_SIZE.next_multiple_of(align);
+        let Some((class, block_size)) = block_layout(offset, align, layout.size()) else {
+            uncharge_shared_heap(domain_id, layout.size());
+            return None;
+        };
+
+        let shard_id = current_shard();
+        let shard = &SHARED_HEAP[shard_id].0;
+        let mut inner = shard.lock();
+        let cached = match class {
+            LARGE_CLASS => None,
+            class => inner.free[class].pop(),
+        };
+        let block = match cached {
+            Some(block) => block,
+            None => {
+                // don't hold the shard while the kernel allocates memory
+                drop(inner);
+                let Some((block, rest)) = SharedHeapAllocator::alloc_block(class, block_size)
+                else {
+                    uncharge_shared_heap(domain_id, layout.size());
+                    return None;
+                };
+                inner = shard.lock();
+                if !rest.is_empty() {
+                    inner.free[class].extend(rest);
+                }
+                block
+            }
         };
-        shared_heap.insert(ptr as usize, res);
-        Some(res)
+
+        let value = (block + offset).next_multiple_of(align);
+        let header = SharedHeapHeader::from_value(value as *mut u8);
+        header.write(SharedHeapHeader {
+            domain_id,
+            charged: domain_id,
+            magic: HEADER_LIVE,
+            layout,
+            type_id,
+            drop_fn,
+            block: block as *mut u8,
+            block_size,
+            class,
+            shard: shard_id,
+            prev: ptr::null_mut(),
+            next: ptr::null_mut(),
+        });
+        inner.link(header);
+        if let Some(record) = record {
+            inner.records.insert(value, record);
+        }
+        Some((*header).allocation())
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8) {
-        let mut heap = SHARED_HEAP.lock();
-        let allocation = heap.remove(&(ptr as usize));
-        drop(heap);
-        if let Some(allocation) = allocation {
-            log::error!("<SharedHeap> dealloc: {:p}", ptr);
-            assert_eq!(allocation.value_pointer, ptr);
-            if allocation.layout.size() > FRAME_SIZE {
-                dealloc(allocation.value_pointer, allocation.layout);
-                dealloc(
-                    allocation.domain_id_pointer as *mut u8,
-                    Layout::for_value(&0u64),
-                );
-            } else {
-                let part = SharedHeapAllocationPart {
-                    value_pointer: allocation.value_pointer,
-                    domain_id_pointer: allocation.domain_id_pointer,
-                };
-                SHARED_HEAP_CACHE.insert(allocation.layout, part);
+        let header = SharedHeapHeader::from_value(ptr);
+        let (charged, size, block, block_size, class) = {
+            let slabs = SHARED_HEAP_SLABS.read();
+            if !header_in_heap(&slabs, ptr) {
+                drop(slabs);
+                report_bad_free(ptr, None);
+                return;
             }
-        } else {
-            panic!(
-                "<SharedHeap> dealloc: {:#x}, but the data has been dropped",
-                ptr as usize
+            let mut inner = lock_shard(header);
+            if !matches!((*header).magic, HEADER_LIVE | HEADER_FREEING) {
+                let freed = ((*header).magic == HEADER_FREE).then(|| FreedValue {
+                    size: (*header).layout.size(),
+                    owner: (*header).domain_id,
+                    charged: (*header).charged,
+                });
+                drop(inner);
+                drop(slabs);
+                report_bad_free(ptr, freed);
+                return;
+            }
+            inner.unlink(header);
+            if !inner.records.is_empty() {
+                inner.records.remove(&(ptr as usize));
+            }
+            (*header).magic = HEADER_FREE;
+            let h = &*header;
+            (h.charged, h.layout.size(), h.block, h.block_size, h.class)
+        };
+        uncharge_shared_heap(charged, size);
+        if class == LARGE_CLASS {
+            SHARED_HEAP_SLABS.write().remove(&(block as usize));
+            dealloc(
+                block,
+                Layout::from_size_align_unchecked(block_size, FRAME_SIZE),
             );
+            return;
+        }
+        // the block goes to the cache of this CPU, wherever it was allocated
+        let reclaim = {
+            let mut inner = SHARED_HEAP[current_shard()].0.lock();
+            let free = &mut inner.free[class];
+            free.push(block as usize);
+            trim(free, class)
+        };
+        if reclaim {
+            reclaim_slabs(class);
+        }
+    }
+}
+
+/// What a freed header still tells about its value
+struct FreedValue {
+    size: usize,
+    owner: u64,
+    charged: u64,
+}
+
+/// Log a free of a value which is not live, the value is left alone.
+///
+/// The header of a freed block stays until the block is reused, so a double free still
+/// tells whose value it was. `freed` is copied from the header under the lock of its shard.
+fn report_bad_free(ptr: *mut u8, freed: Option<FreedValue>) {
+    SHARED_HEAP_BAD_FREES.fetch_add(1, Ordering::Relaxed);
+    match freed {
+        Some(freed) => pr_err!(
+            "<SharedHeap> double free of {:#x}: {} bytes, owned by domain {}, charged to domain {}",
+            ptr as usize,
+            freed.size,
+            freed.owner,
+            freed.charged
+        ),
+        None => pr_err!(
+            "<SharedHeap> free of {:#x} which is not on the shared heap",
+            ptr as usize
+        ),
+    }
+    unsafe { kernel::bindings::dump_stack() };
+}
+
+/// Take the value at `ptr` if it's still waiting to be freed with the domain `id`.
+///
+/// It's checked and taken under the lock of its shard, so the value is freed only once. It's
+/// false if the value was freed, even if its block is allocated again, the new value is not
+/// marked.
+unsafe fn claim_freeing(ptr: *mut u8, id: u64) -> bool {
+    let slabs = SHARED_HEAP_SLABS.read();
+    if !header_in_heap(&slabs, ptr) {
+        return false;
+    }
+    let header = SharedHeapHeader::from_value(ptr);
+    let _inner = lock_shard(header);
+    if (*header).magic != HEADER_FREEING || (*header).domain_id != id {
+        return false;
+    }
+    // it's live again until the dealloc below, a nested free of it is a double free
+    (*header).magic = HEADER_LIVE;
+    true
+}
+
+/// Visit the live values of all shards, each shard is locked while it is visited
+fn for_each_allocation(mut f: impl FnMut(&mut SharedHeapHeader, Option<&AllocRecord>)) {
+    SHARED_HEAP
+        .iter()
+        .for_each(|shard| shard.0.lock().for_each_live(&mut f));
+}
+
+/// The values of one type allocated by one domain
+#[derive(Debug, Default)]
+struct TypeUsage {
+    count: usize,
+    bytes: usize,
+    oldest_ns: u64,
+}
+
+/// The shared heap owned by a domain
+#[derive(Debug, Default)]
+struct DomainUsage {
+    count: usize,
+    bytes: usize,
+    /// The values recorded in the diagnostic mode, keyed by the allocating domain and the type
+    recorded: BTreeMap<(u64, String), TypeUsage>,
+}
+
+/// The live shared heap of the domains, grouped by the owner
+pub struct SharedHeapReport {
+    now_ns: u64,
+    domains: BTreeMap<u64, DomainUsage>,
+}
+
+impl SharedHeapReport {
+    /// Collect the values owned by the domains `owner` accepts
+    fn collect(owner: impl Fn(u64) -> bool) -> Self {
+        let mut domains = BTreeMap::<u64, DomainUsage>::new();
+        for_each_allocation(|header, record| {
+            if !owner(header.domain_id) {
+                return;
+            }
+            let usage = domains.entry(header.domain_id).or_default();
+            usage.count += 1;
+            usage.bytes += header.layout.size();
+            if let Some(record) = record {
+                let ty = usage
+                    .recorded
+                    .entry((record.domain_id, record.type_name.clone()))
+                    .or_default();
+                ty.count += 1;
+                ty.bytes += header.layout.size();
+                ty.oldest_ns = match ty.oldest_ns {
+                    0 => record.time_ns,
+                    oldest => oldest.min(record.time_ns),
+                };
+            }
+        });
+        Self {
+            now_ns: now_ns(),
+            domains,
+        }
+    }
+
+    pub fn all() -> Self {
+        Self::collect(|_| true)
+    }
+
+    fn write_domain(&self, out: &mut impl Write, id: u64, usage: &DomainUsage) -> fmt::Result {
+        writeln!(
+            out,
+            "Domain {}: {} values, {} bytes",
+            id, usage.count, usage.bytes
+        )?;
+        for ((allocator, type_name), ty) in usage.recorded.iter() {
+            writeln!(
+                out,
+                "  - {}: {} values, {} bytes, allocated by domain {}, oldest {} ms ago",
+                type_name,
+                ty.count,
+                ty.bytes,
+                allocator,
+                self.now_ns.saturating_sub(ty.oldest_ns) / 1_000_000
+            )?;
         }
+        Ok(())
+    }
+}
+
+impl Display for SharedHeapReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Diagnostics: {}, bad frees: {}",
+            if SHARED_HEAP_DEBUG.load(Ordering::Relaxed) {
+                "on"
+            } else {
+                "off"
+            },
+            SHARED_HEAP_BAD_FREES.load(Ordering::Relaxed)
+        )?;
+        for (id, usage) in self.domains.iter() {
+            self.write_domain(f, *id, usage)?;
+        }
+        Ok(())
     }
 }
 
 pub fn checkout_shared_data() {
-    let heap = SHARED_HEAP.lock();
-    let mut map = BTreeMap::new();
-    heap.iter().for_each(|(_, v)| {
-        let id = v.domain_id();
-        let count = map.get(&id).unwrap_or(&0) + 1;
-        map.insert(id, count);
+    let total: usize = SHARED_HEAP
+        .iter()
+        .map(|shard| shard.0.lock().live_count)
+        .sum();
+    println_color!(34, "{}", SharedHeapReport::all());
+    println_color!(34, "<checkout_shared_data> shared heap size: {}", total);
+}
+
+/// The bytes of shared heap owned by the domain
+pub fn domain_shared_heap_usage(id: u64) -> usize {
+    let mut usage = 0;
+    for_each_allocation(|header, _| {
+        if header.domain_id == id {
+            usage += header.layout.size();
+        }
     });
-    for (id, count) in map {
-        println_color!(34, "domain_id: {}, count: {}", id, count);
-    }
-    println_color!(
-        34,
-        "<checkout_shared_data> shared heap size: {}",
-        heap.len()
-    );
+    usage
 }
 
 pub enum FreeShared {
@@ -181,34 +655,61 @@ pub enum FreeShared {
     NotFree(u64),
 }
 
+/// Report what the domain `id` still owns when it is unloaded or updated
+fn report_domain_shared_data(id: u64, free_shared: &FreeShared) {
+    let report = SharedHeapReport::collect(|owner| owner == id);
+    let Some(usage) = report.domains.get(&id) else {
+        return;
+    };
+    let fate = match free_shared {
+        FreeShared::Free => "freed".to_string(),
+        FreeShared::NotFree(new_id) => alloc::format!("handed over to domain {}", new_id),
+    };
+    let mut text = String::new();
+    let _ = report.write_domain(&mut text, id, usage);
+    warn!(
+        "<SharedHeap> domain {} leaves its shared heap, {}",
+        id, fate
+    );
+    text.lines().for_each(|line| warn!("<SharedHeap> {}", line));
+}
+
 pub fn free_domain_shared_data(id: u64, free_shared: FreeShared) {
-    checkout_shared_data();
+    report_domain_shared_data(id, &free_shared);
     let mut data = vec![];
-    let heap = SHARED_HEAP.lock();
-    println_color!(
-        34,
-        "<free_domain_shared_data> shared heap size: {}",
-        heap.len()
-    );
-    heap.iter().for_each(|(_, v)| {
-        if v.domain_id() == id {
-            data.push(*v);
+    let mut charged = 0;
+    for_each_allocation(|header, _| {
+        if header.domain_id == id {
+            // mark it under the lock of its shard, so a value allocated later in the same
+            // block is not taken for it
+            if let FreeShared::Free = free_shared {
+                header.magic = HEADER_FREEING;
+            }
+            data.push(header.allocation());
+        }
+        // the data kept by the new domain is charged to it
+        if header.charged == id
+            && let FreeShared::NotFree(new_id) = free_shared
+        {
+            header.charged = new_id;
+            charged += header.layout.size();
         }
     });
-    drop(heap);
-    println_color!(34, "<free_domain_shared_data> for domain_id: {}", id);
-    println_color!(34, "domain has {} data", data.len());
+    if let FreeShared::NotFree(new_id) = free_shared {
+        move_shared_heap_charge(id, new_id, charged);
+    }
 
     match free_shared {
         FreeShared::Free => {
-            println_color!(34, "free_shared is Free, free {} data", data.len());
             data.into_iter().for_each(|v| unsafe {
-                v.drop_fn();
-                SharedHeapAllocator.dealloc(v.value_pointer);
+                // dropping a value may free the others it holds
+                if claim_freeing(v.value_pointer, id) {
+                    v.drop_fn();
+                    SharedHeapAllocator.dealloc(v.value_pointer);
+                }
             });
         }
         FreeShared::NotFree(domain_id) => {
-            println_color!(34, "free_shared is NotFree, do not free data");
             data.into_iter().for_each(|v| v.set_domain_id(domain_id));
         }
     }
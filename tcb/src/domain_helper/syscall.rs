@@ -1,32 +1,76 @@
-use alloc::{string::ToString, sync::Arc};
+use alloc::{boxed::Box, collections::BTreeMap, string::ToString, sync::Arc};
 use core::{
     any::Any,
     ffi::{c_char, c_int, c_long, c_uint, c_ulong, c_void},
-    sync::atomic::AtomicBool,
 };
 
-use corelib::{domain_info::DomainDataInfo, CoreFunction, LinuxError, LinuxResult};
-use interface::*;
+use corelib::{
+    domain_info::{DomainDataInfo, DomainState},
+    CoreFunction, LinuxError, LinuxResult,
+};
+use interface::{
+    empty_device::EmptyDeviceDomain, logger::LogDomain, null_block::BlockDeviceDomain, *,
+};
 use kernel::bindings::*;
+use ksync::Mutex;
 
 use crate::{
     config::FRAME_BITS,
-    domain_helper::{resource::DOMAIN_RESOURCE, DOMAIN_CREATE, DOMAIN_INFO},
-    domain_loader::creator,
+    domain_helper::{
+        add_dependency_by_id, charge_pages, check_lookup, check_privilege,
+        resource::DOMAIN_RESOURCE, uncharge_pages, Privilege, DOMAIN_CREATE, DOMAIN_INFO,
+    },
+    domain_loader::{creator, loader::DomainLoader},
     domain_proxy::{
         block_device::BlockDeviceDomainProxy, empty_device::EmptyDeviceDomainProxy,
-        logger::LogDomainProxy,
+        logger::LogDomainProxy, take_injected_panic,
     },
 };
 
-pub static DOMAIN_SYS: &'static dyn CoreFunction = &DomainSyscall;
+/// The syscalls used by the tcb itself, they are not limited by any capability
+pub static DOMAIN_SYS: &'static dyn CoreFunction = &DomainSyscall { caller: None };
+
+/// The syscall handles given to the domains, keyed by the domain id.
+///
+/// The handles are leaked on purpose, a domain may still hold its handle after its resources
+/// are freed (e.g. a domain dropped without running its destructors). A handle is only a few
+/// bytes and the domain ids are never reused.
+static DOMAIN_SYSCALL: Mutex<BTreeMap<u64, &'static DomainSyscall>> = Mutex::new(BTreeMap::new());
 
-pub struct DomainSyscall;
+pub struct DomainSyscall {
+    /// The domain which owns the handle, the capability of it is checked by the privileged
+    /// syscalls
+    caller: Option<u64>,
+}
+
+/// The syscall handle of the domain `domain_id`
+pub fn domain_syscall(domain_id: u64) -> &'static dyn CoreFunction {
+    let mut handles = DOMAIN_SYSCALL.lock();
+    *handles.entry(domain_id).or_insert_with(|| {
+        Box::leak(Box::new(DomainSyscall {
+            caller: Some(domain_id),
+        }))
+    })
+}
+
+/// Forget the handle of `domain_id`, the handle itself stays valid.
+pub(super) fn remove_domain_syscall(domain_id: u64) {
+    DOMAIN_SYSCALL.lock().remove(&domain_id);
+}
 
 impl CoreFunction for DomainSyscall {
     fn sys_alloc_pages(&self, domain_id: u64, n: usize) -> *mut u8 {
+        // a domain is always charged for its own pages, only the tcb may name the owner
+        let domain_id = self.caller.unwrap_or(domain_id);
         let n = n.next_power_of_two();
+        if charge_pages(domain_id, n).is_err() {
+            return core::ptr::null_mut();
+        }
         let page = crate::mem::alloc_frames(n);
+        if page.is_null() {
+            uncharge_pages(domain_id, n);
+            return page;
+        }
         // info!(
         //     "[Domain: {}] alloc pages: {}, range:[{:#x}-{:#x}]",
         //     domain_id,
@@ -41,12 +85,18 @@ impl CoreFunction for DomainSyscall {
     }
 
     fn sys_free_pages(&self, domain_id: u64, p: *mut u8, n: usize) {
+        let domain_id = self.caller.unwrap_or(domain_id);
         let n = n.next_power_of_two();
         debug!("[Domain: {}] free pages: {}, ptr: {:p}", domain_id, n, p);
-        DOMAIN_RESOURCE
+        if !DOMAIN_RESOURCE
             .lock()
-            .free_page_map(domain_id, p as usize >> FRAME_BITS);
+            .free_page_map(domain_id, p as usize >> FRAME_BITS)
+        {
+            warn!("[Domain: {}] free pages {:p} it doesn't own", domain_id, p);
+            return;
+        }
         crate::mem::free_frames(p, n);
+        uncharge_pages(domain_id, n);
     }
 
     fn sys_write_console(&self, s: &str) {
@@ -54,19 +104,21 @@ impl CoreFunction for DomainSyscall {
     }
 
     fn sys_backtrace(&self, domain_id: u64) {
-        let mut info = DOMAIN_INFO.lock();
-        info.domain_list
-            .get_mut(&domain_id)
-            .map(|d| d.panic_count += 1);
-        unwind();
+        // the panic count is recorded when the proxy reloads the crashed domain
+        warn!("[Domain: {}] panic, unwind to the proxy", domain_id);
     }
 
-    fn blk_crash_trick(&self) -> bool {
-        BLK_CRASH.load(core::sync::atomic::Ordering::Relaxed)
+    fn sys_take_injected_panic(&self) -> bool {
+        self.caller.is_some_and(take_injected_panic)
     }
 
-    fn sys_get_domain(&self, name: &str) -> Option<DomainType> {
-        super::query_domain(name)
+    fn sys_get_domain(&self, name: &str) -> LinuxResult<DomainType> {
+        check_lookup(self.caller, name)?;
+        let domain = super::query_domain(name).ok_or(LinuxError::ENOENT)?;
+        if let Some(caller) = self.caller {
+            add_dependency_by_id(caller, name);
+        }
+        Ok(domain)
     }
 
     fn sys_create_domain(
@@ -74,6 +126,7 @@ impl CoreFunction for DomainSyscall {
         domain_file_name: &str,
         identifier: &mut [u8],
     ) -> LinuxResult<DomainType> {
+        check_privilege(self.caller, Privilege::CREATE)?;
         DOMAIN_CREATE
             .get()
             .unwrap()
@@ -81,8 +134,9 @@ impl CoreFunction for DomainSyscall {
     }
 
     fn sys_register_domain(&self, ident: &str, ty: DomainTypeRaw, data: &[u8]) -> LinuxResult<()> {
-        creator::register_domain_elf(ident, data.to_vec(), ty);
-        Ok(())
+        check_privilege(self.caller, Privilege::REGISTER)?;
+        // the domain can't provide a signature, it only works without the public key
+        creator::register_domain_elf(ident, data.to_vec(), ty, &[])
     }
 
     fn sys_update_domain(
@@ -91,91 +145,110 @@ impl CoreFunction for DomainSyscall {
         new_domain_name: &str,
         ty: DomainTypeRaw,
     ) -> LinuxResult<()> {
-        let old_domain = super::query_domain(old_domain_name);
-        let old_domain_id = old_domain.as_ref().map(|d| d.domain_id());
-        let (domain_info, new_domain_id) = match old_domain {
-            Some(DomainType::LogDomain(logger)) => {
-                let old_domain_id = logger.domain_id();
-                let (id, new_domain, loader) = creator::create_domain_or_empty::<LogDomainProxy, _>(
-                    ty,
-                    new_domain_name,
-                    None,
-                    Some(old_domain_id),
-                );
-                let logger_proxy = logger.downcast_arc::<LogDomainProxy>().unwrap();
-                let domain_info = loader.domain_file_info();
-                logger_proxy.replace(new_domain, loader)?;
-                println!(
-                    "Try to replace logger domain {} with {} ok",
-                    old_domain_name, new_domain_name
-                );
-                Ok((domain_info, id))
-            }
-            Some(DomainType::EmptyDeviceDomain(empty_device)) => {
-                let old_domain_id = empty_device.domain_id();
-                let (id, new_domain, loader) = creator::create_domain_or_empty::<
-                    EmptyDeviceDomainProxy,
-                    _,
-                >(
-                    ty, new_domain_name, None, Some(old_domain_id)
-                );
-                let empty_device = empty_device
-                    .downcast_arc::<EmptyDeviceDomainProxy>()
-                    .unwrap();
-                let domain_info = loader.domain_file_info();
-                empty_device.replace(new_domain, loader)?;
-                println!(
-                    "Try to replace empty device domain {} with {} ok",
-                    old_domain_name, new_domain_name
-                );
-                Ok((domain_info, id))
-            }
-            Some(DomainType::BlockDeviceDomain(block_device)) => {
-                let old_domain_id = block_device.domain_id();
-                let (id, new_domain, loader) = creator::create_domain_or_empty::<
-                    BlockDeviceDomainProxy,
-                    _,
-                >(
-                    ty, new_domain_name, None, Some(old_domain_id)
-                );
-                let block_device = block_device
-                    .downcast_arc::<BlockDeviceDomainProxy>()
-                    .unwrap();
-                let domain_info = loader.domain_file_info();
-                block_device.replace(new_domain, loader)?;
-                println!(
-                    "Try to replace block device domain {} with {} ok",
-                    old_domain_name, new_domain_name
-                );
-                Ok((domain_info, id))
+        // only the domains it may look up can be replaced
+        check_privilege(self.caller, Privilege::UPDATE)?;
+        check_lookup(self.caller, old_domain_name)?;
+        let Some(old_domain) = super::query_domain(old_domain_name) else {
+            println!(
+                "<sys_update_domain> old domain {:?} not found",
+                old_domain_name
+            );
+            return Err(LinuxError::EINVAL);
+        };
+        let old_domain_id = old_domain.domain_id();
+        let prev = super::set_domain_state(old_domain_id, DomainState::Updating)?;
+        // the old domain keeps serving until the new one is initialized
+        let update = || -> LinuxResult<_> {
+            match old_domain {
+                DomainType::LogDomain(logger) => {
+                    let (id, new_domain, loader) =
+                        create_new_domain::<dyn LogDomain>(ty, new_domain_name, old_domain_id)?;
+                    let logger_proxy = logger.downcast_arc::<LogDomainProxy>().unwrap();
+                    let domain_info = loader.domain_file_info();
+                    logger_proxy.replace(new_domain, loader)?;
+                    println!(
+                        "Try to replace logger domain {} with {} ok",
+                        old_domain_name, new_domain_name
+                    );
+                    Ok((domain_info, id))
+                }
+                DomainType::EmptyDeviceDomain(empty_device) => {
+                    let (id, new_domain, loader) = create_new_domain::<dyn EmptyDeviceDomain>(
+                        ty,
+                        new_domain_name,
+                        old_domain_id,
+                    )?;
+                    let empty_device = empty_device
+                        .downcast_arc::<EmptyDeviceDomainProxy>()
+                        .unwrap();
+                    let domain_info = loader.domain_file_info();
+                    empty_device.replace(new_domain, loader)?;
+                    println!(
+                        "Try to replace empty device domain {} with {} ok",
+                        old_domain_name, new_domain_name
+                    );
+                    Ok((domain_info, id))
+                }
+                DomainType::BlockDeviceDomain(block_device) => {
+                    let (id, new_domain, loader) = create_new_domain::<dyn BlockDeviceDomain>(
+                        ty,
+                        new_domain_name,
+                        old_domain_id,
+                    )?;
+                    let block_device = block_device
+                        .downcast_arc::<BlockDeviceDomainProxy>()
+                        .unwrap();
+                    let domain_info = loader.domain_file_info();
+                    block_device.replace(new_domain, loader)?;
+                    println!(
+                        "Try to replace block device domain {} with {} ok",
+                        old_domain_name, new_domain_name
+                    );
+                    Ok((domain_info, id))
+                }
             }
-            None => {
-                println!(
-                    "<sys_update_domain> old domain {:?} not found",
-                    old_domain_name
-                );
-                Err(LinuxError::EINVAL)
+        };
+        let (domain_info, new_domain_id) = match update() {
+            Ok(res) => res,
+            Err(e) => {
+                // the old domain is still in use, e.g. still crashed
+                super::set_domain_state(old_domain_id, prev)?;
+                return Err(e);
             }
-        }?;
+        };
+        let mut info = DOMAIN_INFO.lock();
+        // the crashes of the old instances stay in the history of the domain
+        let panic_count = info
+            .domain_list
+            .remove(&old_domain_id)
+            .map_or(0, |data| data.panic_count);
         let domain_data = DomainDataInfo {
             name: old_domain_name.to_string(),
             ty,
-            panic_count: 0,
+            panic_count,
             file_info: domain_info,
+            state: DomainState::Serving,
         };
-
-        let mut info = DOMAIN_INFO.lock();
-        info.domain_list.remove(&old_domain_id.unwrap());
         info.domain_list.insert(new_domain_id, domain_data);
         Ok(())
     }
     fn sys_reload_domain(&self, domain_name: &str) -> LinuxResult<()> {
+        check_privilege(self.caller, Privilege::RELOAD)?;
+        check_lookup(self.caller, domain_name)?;
         let domain = super::query_domain(domain_name).ok_or(LinuxError::EINVAL)?;
         match domain {
-            // todo!(release old domain's resource)
-            ty => {
-                panic!("reload domain {:?} not support", ty);
-            }
+            DomainType::LogDomain(logger) => logger
+                .downcast_arc::<LogDomainProxy>()
+                .map_err(|_| LinuxError::EINVAL)?
+                .reload(),
+            DomainType::EmptyDeviceDomain(empty_device) => empty_device
+                .downcast_arc::<EmptyDeviceDomainProxy>()
+                .map_err(|_| LinuxError::EINVAL)?
+                .reload(),
+            DomainType::BlockDeviceDomain(block_device) => block_device
+                .downcast_arc::<BlockDeviceDomainProxy>()
+                .map_err(|_| LinuxError::EINVAL)?
+                .reload(),
         }
     }
 
@@ -401,7 +474,20 @@ impl CoreFunction for DomainSyscall {
     }
 }
 
-static BLK_CRASH: AtomicBool = AtomicBool::new(true);
-fn unwind() {
-    BLK_CRASH.store(false, core::sync::atomic::Ordering::Relaxed);
+/// Create the new version of the domain `old_id` from the registered elf `domain_file_name`.
+///
+/// The update fails if the elf is not registered, the old domain is never replaced by an
+/// empty one.
+fn create_new_domain<T: ?Sized>(
+    ty: DomainTypeRaw,
+    domain_file_name: &str,
+    old_id: u64,
+) -> LinuxResult<(u64, Box<T>, DomainLoader)> {
+    creator::create_domain(ty, domain_file_name, None, Some(old_id)).ok_or_else(|| {
+        println!(
+            "<sys_update_domain> create domain from {} failed",
+            domain_file_name
+        );
+        LinuxError::ENOENT
+    })
 }
@@ -6,13 +6,16 @@ use alloc::{
     vec::Vec,
 };
 
-use corelib::{domain_info::DomainFileInfo, LinuxResult};
+use corelib::{domain_info::DomainFileInfo, LinuxError, LinuxResult};
 use interface::*;
 use ksync::RwLock;
 
 use crate::{
     domain_helper::{alloc_domain_id, DomainCreate, DOMAIN_INFO},
-    domain_loader::loader::{DomainCall, DomainLoader},
+    domain_loader::{
+        loader::{DomainCall, DomainLoader},
+        verify::verify_domain_elf,
+    },
     domain_proxy::*,
 };
 
@@ -25,7 +28,15 @@ struct DomainData {
 }
 
 /// Register the domain elf data with the given identifier.
-pub fn register_domain_elf(domain_file_name: &str, elf: Vec<u8>, ty: DomainTypeRaw) {
+///
+/// The elf is rejected if its `signature` can't be verified, see [`verify_domain_elf`].
+pub fn register_domain_elf(
+    domain_file_name: &str,
+    elf: Vec<u8>,
+    ty: DomainTypeRaw,
+    signature: &[u8],
+) -> LinuxResult<()> {
+    verify_domain_elf(domain_file_name, &elf, signature)?;
     let elf_len = elf.len();
     let mut binding = DOMAIN_ELF.write();
 
@@ -34,7 +45,7 @@ pub fn register_domain_elf(domain_file_name: &str, elf: Vec<u8>, ty: DomainTypeR
         .any(|(k, f)| k == domain_file_name && elf.len() == f.data.len())
     {
         println!("Domain {} already registered", domain_file_name);
-        return;
+        return Ok(());
     }
     println!("<register domain>: {}", domain_file_name);
     binding.insert(
@@ -52,6 +63,7 @@ pub fn register_domain_elf(domain_file_name: &str, elf: Vec<u8>, ty: DomainTypeR
         .entry(ty)
         .or_default()
         .push(file_info);
+    Ok(())
 }
 
 /// Unregister the domain elf data with the given identifier.
@@ -117,6 +129,35 @@ where
     Ok(res)
 }
 
+/// Create the domain from the registered elf file `domain_file_name`.
+///
+/// Unlike [create_domain_special], it doesn't fall back to an empty domain: `ENOENT` is
+/// returned if no elf file of type `ty` is registered as `domain_file_name`.
+pub fn create_domain_registered<P, T>(
+    ty: DomainTypeRaw,
+    domain_file_name: &str,
+) -> LinuxResult<(Arc<P>, DomainFileInfo)>
+where
+    P: ProxyBuilder<T = Box<T>>,
+    T: ?Sized,
+{
+    let registered = DOMAIN_ELF
+        .read()
+        .get(domain_file_name)
+        .is_some_and(|data| data.ty == ty);
+    if !registered {
+        println!(
+            "Domain file {} of type {:?} is not registered",
+            domain_file_name, ty
+        );
+        return Err(LinuxError::ENOENT);
+    }
+    let (_id, domain, loader) =
+        create_domain(ty, domain_file_name, None, None).ok_or(LinuxError::EINVAL)?;
+    let file_info = loader.domain_file_info();
+    Ok((Arc::new(P::build(domain, loader)), file_info))
+}
+
 pub struct DomainCreateImpl;
 
 impl DomainCreate for DomainCreateImpl {
@@ -140,7 +181,7 @@ pub fn create_domain<T: ?Sized>(
     use_old_id: Option<u64>,
 ) -> Option<(u64, Box<T>, DomainLoader)> {
     if let Some(data) = elf {
-        register_domain_elf(domain_file_name, data, ty);
+        register_domain_elf(domain_file_name, data, ty, &[]).ok()?;
     }
     let data = DOMAIN_ELF.read().get(domain_file_name)?.clone();
     if data.ty != ty {
@@ -148,7 +189,10 @@ pub fn create_domain<T: ?Sized>(
     }
     info!("Load {:?} domain, size: {}KB", ty, data.data.len() / 1024);
     let mut domain_loader = DomainLoader::new(data.data, domain_file_name);
-    domain_loader.load().unwrap();
+    domain_loader
+        .load()
+        .map_err(|e| println!("Load domain {} failed: {}", domain_file_name, e))
+        .ok()?;
     let id = alloc_domain_id();
     let domain = domain_loader.call_main(id, use_old_id);
     Some((id, domain, domain_loader))
@@ -169,8 +213,9 @@ where
         None => {
             println!("Create empty domain: {}", domain_file_name);
             let loader = DomainLoader::empty();
-            let domain = P::build_empty_no_proxy();
-            (u64::MAX, domain, loader)
+            let id = alloc_domain_id();
+            let domain = P::build_empty_no_proxy(id);
+            (id, domain, loader)
         }
     }
 }
@@ -179,8 +224,33 @@ pub fn create_domain_with_loader<T: ?Sized>(
     mut domain_loader: DomainLoader,
     use_old_id: Option<u64>,
 ) -> Option<(u64, Box<T>, DomainLoader)> {
-    domain_loader.load().unwrap();
+    domain_loader
+        .load()
+        .map_err(|e| {
+            let name = domain_loader.domain_file_info().name;
+            println!("Load domain {} failed: {}", name, e)
+        })
+        .ok()?;
     let id = alloc_domain_id();
     let domain = domain_loader.call_main(id, use_old_id);
     Some((id, domain, domain_loader))
 }
+
+/// Re-create a domain from the elf file which `domain_loader` was built from.
+///
+/// The elf data is looked up in `DOMAIN_ELF` again, so the new instance starts from a
+/// clean image while still inheriting the database of `old_id`.
+pub fn recreate_domain<T: ?Sized>(
+    ty: DomainTypeRaw,
+    domain_loader: &DomainLoader,
+    old_id: u64,
+) -> LinuxResult<(u64, Box<T>, DomainLoader)> {
+    let domain_file_name = domain_loader.domain_file_info().name;
+    create_domain(ty, &domain_file_name, None, Some(old_id)).ok_or_else(|| {
+        println!(
+            "<recreate_domain> domain file {} is not registered",
+            domain_file_name
+        );
+        LinuxError::ENOENT
+    })
+}
@@ -8,7 +8,7 @@ use storage::StorageArg;
 
 use crate::{
     domain_helper,
-    domain_helper::{DOMAIN_DATA_ALLOCATOR, DOMAIN_SYS, SHARED_HEAP_ALLOCATOR},
+    domain_helper::{DOMAIN_DATA_ALLOCATOR, SHARED_HEAP_ALLOCATOR},
 };
 
 pub type DomainLoader = loader::DomainLoader<VmOpsImpl>;
@@ -22,13 +22,15 @@ pub trait DomainCall {
 impl DomainCall for DomainLoader {
     fn call_main<T: ?Sized>(&self, id: u64, use_old_id: Option<u64>) -> Box<T> {
         let callback = |use_old_id: Option<u64>| {
-            let syscall = DOMAIN_SYS;
+            let syscall = domain_helper::domain_syscall(id);
             let heap = SHARED_HEAP_ALLOCATOR;
+            // a placeholder domain has no database, the new domain starts with an empty one
             let data_map = if let Some(old_id) = use_old_id
-                && old_id != u64::MAX
+                && let Some(database) = domain_helper::get_domain_database(old_id)
             {
-                let database = domain_helper::get_domain_database(old_id).unwrap();
                 domain_helper::move_domain_database(old_id, id);
+                domain_helper::inherit_domain_quota(old_id, id);
+                domain_helper::inherit_domain_capability(old_id, id);
                 database
             } else {
                 domain_helper::create_domain_database(id);
@@ -81,4 +83,12 @@ impl DomainVmOps for VmOpsImpl {
     fn set_memory_x(start: usize, pages: usize) -> Result<(), &'static str> {
         mm::set_memory_x(start, pages).map_err(|_| "set_memory_x failed")
     }
+
+    fn set_memory_ro(start: usize, pages: usize) -> Result<(), &'static str> {
+        mm::set_memory_ro(start, pages).map_err(|_| "set_memory_ro failed")
+    }
+
+    fn set_memory_nx(start: usize, pages: usize) -> Result<(), &'static str> {
+        mm::set_memory_nx(start, pages).map_err(|_| "set_memory_nx failed")
+    }
 }
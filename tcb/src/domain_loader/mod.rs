@@ -1,2 +1,4 @@
 pub mod creator;
 pub mod loader;
+pub mod path;
+pub mod verify;
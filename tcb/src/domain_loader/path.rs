@@ -0,0 +1,112 @@
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+
+use corelib::{LinuxError, LinuxResult};
+use interface::DomainTypeRaw;
+use kernel::{error::linux_err, fs::read_file_from_path, str::CString};
+use ksync::{Lazy, RwLock};
+
+use crate::domain_loader::creator::register_domain_elf;
+
+/// The directories searched for a domain file given by name, separated by `:`.
+///
+/// It can be changed by `/proc/sys/rust/domain/search_path`.
+static DOMAIN_SEARCH_PATH: Lazy<RwLock<String>> =
+    Lazy::new(|| RwLock::new(String::from("/lib/domains")));
+
+/// The max size of a domain file, read from the file system or uploaded by the command channel
+pub const MAX_DOMAIN_FILE_SIZE: usize = 64 << 20;
+
+pub fn domain_search_path() -> String {
+    DOMAIN_SEARCH_PATH.read().clone()
+}
+
+/// Set the search path, every directory should be absolute
+pub fn set_domain_search_path(path: &str) -> LinuxResult<()> {
+    let path = path.trim();
+    if path.split(':').any(|dir| !dir.starts_with('/')) {
+        return Err(LinuxError::EINVAL);
+    }
+    println!("Domain search path: {}", path);
+    *DOMAIN_SEARCH_PATH.write() = path.to_string();
+    Ok(())
+}
+
+/// The errors of reading a file, the others are reported as `EIO`
+const READ_ERRORS: [LinuxError; 11] = [
+    LinuxError::ENOENT,
+    LinuxError::EACCES,
+    LinuxError::EPERM,
+    LinuxError::ENOMEM,
+    LinuxError::EFBIG,
+    LinuxError::EISDIR,
+    LinuxError::ENOTDIR,
+    LinuxError::ENAMETOOLONG,
+    LinuxError::ELOOP,
+    LinuxError::EINVAL,
+    LinuxError::ETXTBSY,
+];
+
+fn read_file(path: &str) -> LinuxResult<Vec<u8>> {
+    let c_path = CString::try_from_fmt(fmt!("{}", path)).map_err(|_| LinuxError::ENOMEM)?;
+    read_file_from_path(&c_path, MAX_DOMAIN_FILE_SIZE).map_err(|e| {
+        if e.to_errno() != linux_err::ENOENT.to_errno() {
+            pr_err!("Read domain file {} failed: {:?}", path, e);
+        }
+        READ_ERRORS
+            .into_iter()
+            .find(|err| *err as i32 == e.to_errno())
+            .unwrap_or(LinuxError::EIO)
+    })
+}
+
+/// Find the file `file` and read it, return the path it is read from.
+///
+/// An absolute `file` is read directly, otherwise the directories of the search path are
+/// tried in order. If no directory has the file, the first error other than `ENOENT` is
+/// returned.
+pub fn read_search_path_file(file: &str) -> LinuxResult<(String, Vec<u8>)> {
+    if file.is_empty() || file.split('/').any(|part| part == "..") {
+        return Err(LinuxError::EINVAL);
+    }
+    let candidates = if file.starts_with('/') {
+        alloc::vec![file.to_string()]
+    } else {
+        domain_search_path()
+            .split(':')
+            .map(|dir| alloc::format!("{}/{}", dir.trim_end_matches('/'), file))
+            .collect()
+    };
+    let mut error = LinuxError::ENOENT;
+    for path in candidates {
+        match read_file(&path) {
+            Ok(data) => return Ok((path, data)),
+            Err(LinuxError::ENOENT) => {}
+            Err(e) if matches!(error, LinuxError::ENOENT) => error = e,
+            Err(_) => {}
+        }
+    }
+    Err(error)
+}
+
+/// Find the domain file `file` and read it with its detached signature `<file>.sig`.
+///
+/// The signature is empty if the file is not signed.
+pub fn read_domain_file(file: &str) -> LinuxResult<(Vec<u8>, Vec<u8>)> {
+    let (path, elf) = read_search_path_file(file).inspect_err(|e| {
+        if matches!(e, LinuxError::ENOENT) {
+            pr_err!("Domain file {} not found", file);
+        }
+    })?;
+    let signature = read_file(&alloc::format!("{}.sig", path)).unwrap_or_default();
+    println!("Read domain file {}: {} bytes", path, elf.len());
+    Ok((elf, signature))
+}
+
+/// Read the domain file `file` and register it as `ident`
+pub fn register_domain_path(ident: &str, ty: DomainTypeRaw, file: &str) -> LinuxResult<()> {
+    let (elf, signature) = read_domain_file(file)?;
+    register_domain_elf(ident, elf, ty, &signature)
+}
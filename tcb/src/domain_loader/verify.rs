@@ -0,0 +1,76 @@
+//! Verify the detached Ed25519 signature of the domain elf.
+//!
+//! The public key is compiled into the tcb by setting `DOMAIN_PUBLIC_KEY` (hex) when
+//! building it, and parsed by [init_domain_key] when the module is loaded. Without the key
+//! every domain elf is accepted, with the key the elf without a valid signature is rejected.
+use corelib::{LinuxError, LinuxResult};
+use ed25519_compact::{PublicKey, Signature};
+use ksync::Once;
+
+use crate::config::DOMAIN_PUBLIC_KEY;
+
+/// The parsed `DOMAIN_PUBLIC_KEY`, `None` if no key is configured.
+static DOMAIN_KEY: Once<Option<PublicKey>> = Once::new();
+
+fn decode_hex<const N: usize>(hex: &str) -> Option<[u8; N]> {
+    let hex = hex.as_bytes();
+    if hex.len() != N * 2 {
+        return None;
+    }
+    let mut res = [0u8; N];
+    for (i, byte) in res.iter_mut().enumerate() {
+        let high = (hex[i * 2] as char).to_digit(16)?;
+        let low = (hex[i * 2 + 1] as char).to_digit(16)?;
+        *byte = (high << 4 | low) as u8;
+    }
+    Some(res)
+}
+
+/// Parse the public key compiled into the tcb.
+///
+/// Return `EINVAL` if `DOMAIN_PUBLIC_KEY` is not a valid ed25519 public key, the module
+/// should refuse to load rather than run with a key it can't use.
+pub fn init_domain_key() -> LinuxResult<()> {
+    let Some(hex) = DOMAIN_PUBLIC_KEY else {
+        pr_warn!("<verify> DOMAIN_PUBLIC_KEY is not set, the domain signatures are NOT checked");
+        DOMAIN_KEY.call_once(|| None);
+        return Ok(());
+    };
+    let key = decode_hex::<{ PublicKey::BYTES }>(hex)
+        .and_then(|key| PublicKey::from_slice(&key).ok())
+        .ok_or_else(|| {
+            pr_err!("<verify> DOMAIN_PUBLIC_KEY is not a valid ed25519 public key");
+            LinuxError::EINVAL
+        })?;
+    DOMAIN_KEY.call_once(|| Some(key));
+    Ok(())
+}
+
+/// Verify the domain elf with its signature.
+///
+/// Every elf is rejected with `EPERM` before [init_domain_key] succeeds.
+pub fn verify_domain_elf(domain_file_name: &str, elf: &[u8], signature: &[u8]) -> LinuxResult<()> {
+    let Some(key) = DOMAIN_KEY.get().ok_or(LinuxError::EPERM)? else {
+        return Ok(());
+    };
+    if signature.is_empty() {
+        pr_err!("<verify> domain {} is not signed", domain_file_name);
+        return Err(LinuxError::EPERM);
+    }
+    let signature = Signature::from_slice(signature).map_err(|_| {
+        pr_err!(
+            "<verify> signature of domain {} is malformed",
+            domain_file_name
+        );
+        LinuxError::EPERM
+    })?;
+    key.verify(elf, &signature).map_err(|_| {
+        pr_err!(
+            "<verify> signature of domain {} is invalid",
+            domain_file_name
+        );
+        LinuxError::EPERM
+    })?;
+    println!("<verify> domain {} is verified", domain_file_name);
+    Ok(())
+}
@@ -0,0 +1,274 @@
+use alloc::{collections::BTreeMap, vec::Vec};
+use core::{
+    fmt::{Display, Formatter},
+    sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+};
+
+use corelib::{LinuxError, LinuxResult};
+use kernel::{bindings::pid_t, current, time::mdelay};
+use ksync::Mutex;
+
+use crate::domain_proxy::now_ns;
+
+/// The longest delay of a fault, the call may be in atomic context so it busy waits
+pub const MAX_FAULT_DELAY_MS: u32 = 10;
+
+/// What happens to a call hit by a fault
+#[derive(Debug, Clone, Copy)]
+pub enum FaultAction {
+    /// The call panics in the domain, the proxy reloads the domain as usual
+    Panic,
+    /// The call returns the error without entering the domain
+    Error(LinuxError),
+    /// The call enters the domain after busy waiting for the given milliseconds, at most
+    /// [MAX_FAULT_DELAY_MS]
+    Delay(u32),
+}
+
+/// The panics injected into the calls in flight, keyed by the task making the call. The value
+/// is the id of the domain the call enters.
+static INJECTED_PANICS: Mutex<BTreeMap<pid_t, u64>> = Mutex::new(BTreeMap::new());
+/// The number of panics in `INJECTED_PANICS`, the domain calls only load it if it's zero
+static PENDING_PANICS: AtomicUsize = AtomicUsize::new(0);
+
+/// Take the panic injected into the current call of the domain `domain_id`.
+///
+/// It's called by the domain when a call enters it, the call panics if it returns true. Only
+/// the call the panic was injected into takes it, the concurrent calls of other tasks don't.
+pub fn take_injected_panic(domain_id: u64) -> bool {
+    if PENDING_PANICS.load(Ordering::Acquire) == 0 {
+        return false;
+    }
+    let pid = current!().pid();
+    let mut panics = INJECTED_PANICS.lock();
+    if panics.get(&pid) != Some(&domain_id) {
+        return false;
+    }
+    panics.remove(&pid);
+    PENDING_PANICS.fetch_sub(1, Ordering::Release);
+    true
+}
+
+/// Make the call `f` into the domain, it panics in the domain if `inject_panic` is set.
+///
+/// The panic is bound to the current task and the domain, and raised by the unwind wrapper
+/// of the domain. It's dropped if the call doesn't reach the wrapper, e.g. the domain is a
+/// placeholder.
+#[inline]
+pub fn enter_domain<R>(
+    inject_panic: bool,
+    domain_id: impl FnOnce() -> u64,
+    f: impl FnOnce() -> R,
+) -> R {
+    if !inject_panic {
+        return f();
+    }
+    let domain_id = domain_id();
+    let pid = current!().pid();
+    if INJECTED_PANICS.lock().insert(pid, domain_id).is_none() {
+        PENDING_PANICS.fetch_add(1, Ordering::Release);
+    }
+    let r = f();
+    if take_injected_panic(domain_id) {
+        warn!(
+            "[Fault] the panic injected into domain {} is dropped",
+            domain_id
+        );
+    }
+    r
+}
+
+/// Which calls are hit by a fault
+#[derive(Debug, Clone, Copy)]
+pub enum FaultTrigger {
+    /// Only the nth call after the fault is armed
+    Nth(u64),
+    /// Every call with the probability in percent
+    Percent(u32),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct FaultSpec {
+    pub action: FaultAction,
+    pub trigger: FaultTrigger,
+    /// The fault is disarmed after it hits this many calls, `None` means unlimited
+    pub times: Option<u64>,
+    /// The seed of the random trigger, the same seed hits the same calls
+    pub seed: Option<u64>,
+}
+
+#[derive(Debug)]
+struct ArmedFault {
+    spec: FaultSpec,
+    calls: u64,
+    hits: u64,
+    rng: u64,
+}
+
+impl ArmedFault {
+    fn new(spec: FaultSpec) -> Self {
+        // xorshift never leaves zero
+        let rng = spec.seed.unwrap_or_else(now_ns).max(1);
+        Self {
+            spec,
+            calls: 0,
+            hits: 0,
+            rng,
+        }
+    }
+
+    fn next_random(&mut self) -> u64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        self.rng
+    }
+
+    /// Count a call and return whether it is hit
+    fn hit(&mut self) -> bool {
+        self.calls += 1;
+        let hit = match self.spec.trigger {
+            FaultTrigger::Nth(n) => self.calls == n,
+            FaultTrigger::Percent(p) => self.next_random() % 100 < p as u64,
+        };
+        if hit {
+            self.hits += 1;
+        }
+        hit
+    }
+
+    fn exhausted(&self) -> bool {
+        match (self.spec.trigger, self.spec.times) {
+            (_, Some(times)) if self.hits >= times => true,
+            (FaultTrigger::Nth(n), _) => self.calls >= n,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct MethodFault {
+    armed: AtomicBool,
+    fault: Mutex<Option<ArmedFault>>,
+    /// The calls hit by all faults of this method
+    hits: AtomicU64,
+}
+
+/// The faults armed on the methods of a proxy.
+///
+/// The faults are injected by the proxy, so the domain doesn't know about them. A call
+/// which is not armed only pays an atomic load.
+#[derive(Debug)]
+pub struct FaultInjector {
+    methods: &'static [&'static str],
+    faults: Vec<MethodFault>,
+}
+
+impl FaultInjector {
+    pub fn new(methods: &'static [&'static str]) -> Self {
+        Self {
+            methods,
+            faults: methods
+                .iter()
+                .map(|_| MethodFault {
+                    armed: AtomicBool::new(false),
+                    fault: Mutex::new(None),
+                    hits: AtomicU64::new(0),
+                })
+                .collect(),
+        }
+    }
+
+    fn method_index(&self, method: &str) -> LinuxResult<usize> {
+        self.methods
+            .iter()
+            .position(|name| *name == method)
+            .ok_or(LinuxError::ENOENT)
+    }
+
+    pub fn arm(&self, method: &str, spec: FaultSpec) -> LinuxResult<()> {
+        let fault = &self.faults[self.method_index(method)?];
+        *fault.fault.lock() = Some(ArmedFault::new(spec));
+        fault.armed.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    /// Disarm the fault of `method`, or all faults if `method` is `None`
+    pub fn disarm(&self, method: Option<&str>) -> LinuxResult<()> {
+        let disarm = |fault: &MethodFault| {
+            fault.armed.store(false, Ordering::Release);
+            fault.fault.lock().take();
+        };
+        match method {
+            Some(method) => disarm(&self.faults[self.method_index(method)?]),
+            None => self.faults.iter().for_each(disarm),
+        }
+        Ok(())
+    }
+
+    /// Check the fault of the `method`-th method before the call enters the domain.
+    ///
+    /// Return the error the call should fail with, or whether a panic should be injected into
+    /// the call with [enter_domain]. A delayed call goes on after the delay.
+    #[inline]
+    pub fn inject(&self, method: usize, domain: &str) -> LinuxResult<bool> {
+        let fault = &self.faults[method];
+        if !fault.armed.load(Ordering::Acquire) {
+            return Ok(false);
+        }
+        let action = {
+            let mut guard = fault.fault.lock();
+            let Some(armed) = guard.as_mut() else {
+                return Ok(false);
+            };
+            let hit = armed.hit();
+            let (action, hits) = (armed.spec.action, armed.hits);
+            if armed.exhausted() {
+                fault.armed.store(false, Ordering::Release);
+                guard.take();
+            }
+            if !hit {
+                return Ok(false);
+            }
+            fault.hits.fetch_add(1, Ordering::Relaxed);
+            warn!(
+                "[Fault] {}::{} hit by {:?} ({} hits)",
+                domain, self.methods[method], action, hits
+            );
+            action
+        };
+        match action {
+            FaultAction::Panic => Ok(true),
+            FaultAction::Error(e) => Err(e),
+            FaultAction::Delay(ms) => {
+                mdelay(ms.min(MAX_FAULT_DELAY_MS));
+                Ok(false)
+            }
+        }
+    }
+}
+
+impl Display for FaultInjector {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        for (name, fault) in self.methods.iter().zip(self.faults.iter()) {
+            let hits = fault.hits.load(Ordering::Relaxed);
+            let guard = fault.fault.lock();
+            match guard.as_ref() {
+                Some(armed) => writeln!(
+                    f,
+                    "{}: {:?} {:?}, times {:?}, calls {}, hits {} (total hits {})",
+                    name,
+                    armed.spec.action,
+                    armed.spec.trigger,
+                    armed.spec.times,
+                    armed.calls,
+                    armed.hits,
+                    hits
+                )?,
+                None if hits != 0 => writeln!(f, "{}: disarmed (total hits {})", name, hits)?,
+                None => {}
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,18 +1,127 @@
 use alloc::boxed::Box;
-use core::any::Any;
+use core::{
+    any::Any,
+    fmt::{Display, Formatter},
+    sync::atomic::{AtomicU32, Ordering},
+};
 
-use corelib::LinuxResult;
+use corelib::{LinuxError, LinuxResult};
+pub use fault::{
+    enter_domain, take_injected_panic, FaultAction, FaultInjector, FaultSpec, FaultTrigger,
+    MAX_FAULT_DELAY_MS,
+};
+use kernel::time::{msleep, Ktime};
+pub use recovery::{
+    exit_recovery, init_recovery, retry_recovery, schedule_recovery, MAX_RECOVERY_ATTEMPTS,
+};
+pub use stats::ProxyStats;
 
 use crate::domain_loader::loader::DomainLoader;
 
 pub mod block_device;
 pub mod empty_device;
+mod fault;
 pub mod logger;
+mod recovery;
+pub mod runtime;
+mod stats;
 
 pub trait ProxyBuilder {
     type T;
     fn build(domain: Self::T, domain_loader: DomainLoader) -> Self;
     fn build_empty(domain_loader: DomainLoader) -> Self;
-    fn build_empty_no_proxy() -> Self::T;
+    fn build_empty_no_proxy(domain_id: u64) -> Self::T;
     fn init_by_box(&self, argv: Box<dyn Any + Send + Sync>) -> LinuxResult<()>;
 }
+
+/// How long `replace` waits for the callers of the old domain, in milliseconds.
+///
+/// It can be changed by `/proc/sys/rust/domain/update_timeout_ms`.
+pub static UPDATE_TIMEOUT_MS: AtomicU32 = AtomicU32::new(3000);
+
+pub fn update_timeout_ms() -> u32 {
+    UPDATE_TIMEOUT_MS.load(Ordering::Relaxed)
+}
+
+/// The current time in nanoseconds
+pub fn now_ns() -> u64 {
+    Ktime::ktime_get().to_ns() as u64
+}
+
+/// Sleep until `quiescent` returns true and return the time waited in nanoseconds.
+///
+/// Return `EBUSY` if it doesn't happen in [update_timeout_ms].
+pub fn wait_quiescent(quiescent: impl Fn() -> bool) -> LinuxResult<u64> {
+    let timeout_ns = update_timeout_ms() as u64 * 1_000_000;
+    let start = now_ns();
+    while !quiescent() {
+        let waited = now_ns() - start;
+        if waited >= timeout_ns {
+            pr_err!("The callers of the old domain don't finish in {}ns", waited);
+            return Err(LinuxError::EBUSY);
+        }
+        msleep(1);
+    }
+    Ok(now_ns() - start)
+}
+
+/// The statistics of the updates of a proxy, the time is in nanoseconds.
+///
+/// `wait` is the time the update waits for the callers of the old domain and `latency` is the
+/// time of the whole update.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct UpdateStats {
+    pub updates: u64,
+    pub timeouts: u64,
+    pub failures: u64,
+    pub last_wait: u64,
+    pub max_wait: u64,
+    pub last_latency: u64,
+    pub max_latency: u64,
+}
+
+impl UpdateStats {
+    pub const fn new() -> Self {
+        Self {
+            updates: 0,
+            timeouts: 0,
+            failures: 0,
+            last_wait: 0,
+            max_wait: 0,
+            last_latency: 0,
+            max_latency: 0,
+        }
+    }
+
+    pub fn record(&mut self, wait: u64, latency: u64) {
+        self.updates += 1;
+        self.last_wait = wait;
+        self.max_wait = self.max_wait.max(wait);
+        self.last_latency = latency;
+        self.max_latency = self.max_latency.max(latency);
+    }
+
+    pub fn record_abort(&mut self, err: LinuxError) {
+        if matches!(err, LinuxError::EBUSY) {
+            self.timeouts += 1;
+        } else {
+            self.failures += 1;
+        }
+    }
+}
+
+impl Display for UpdateStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{} (timeouts: {}, failures: {}), wait: {}us (max: {}us), latency: {}us (max: {}us)",
+            self.updates,
+            self.timeouts,
+            self.failures,
+            self.last_wait / 1000,
+            self.max_wait / 1000,
+            self.last_latency / 1000,
+            self.max_latency / 1000
+        )
+    }
+}
@@ -0,0 +1,87 @@
+//! Reload the crashed domains from the system workqueue.
+//!
+//! A domain may crash on a call made in atomic context, e.g. a block request completed in
+//! softirq, where the reload can't sleep. The proxy only marks the domain crashed and queues
+//! the recovery work, which reloads every crashed domain in process context.
+use alloc::{boxed::Box, string::String, vec::Vec};
+use core::pin::Pin;
+
+use corelib::domain_info::DomainState;
+use interface::DomainType;
+use kernel::{
+    time::{msleep, Msecs},
+    workqueue::Work,
+};
+use ksync::Mutex;
+
+use super::{
+    block_device::BlockDeviceDomainProxy, empty_device::EmptyDeviceDomainProxy,
+    logger::LogDomainProxy,
+};
+use crate::domain_helper::{query_domain, DOMAIN_INFO};
+
+/// The times the reload of a crashed domain is tried before the recovery gives up
+pub const MAX_RECOVERY_ATTEMPTS: usize = 5;
+/// The time to wait before the reload of a crashed domain is tried again
+const RECOVERY_RETRY_MS: Msecs = 100;
+
+static RECOVERY_WORK: Mutex<Option<Pin<Box<Work>>>> = Mutex::new(None);
+
+/// Create the recovery work, the crashed domains are not reloaded before it.
+pub fn init_recovery() {
+    *RECOVERY_WORK.lock() = Some(Work::new(recover_crashed_domains));
+}
+
+/// Cancel the recovery work and wait for it to finish.
+pub fn exit_recovery() {
+    let work = RECOVERY_WORK.lock().take();
+    drop(work);
+}
+
+/// Queue the reload of the crashed domains, it doesn't sleep or allocate memory.
+pub fn schedule_recovery() {
+    match RECOVERY_WORK.lock().as_ref() {
+        Some(work) => {
+            work.schedule();
+        }
+        None => pr_err!("The recovery work is not initialized"),
+    }
+}
+
+/// Queue the reload again after a failed one, it's called from the recovery work.
+///
+/// It waits a bit first, so a domain that fails to reload is not retried in a loop.
+pub fn retry_recovery() {
+    msleep(RECOVERY_RETRY_MS);
+    schedule_recovery();
+}
+
+fn recover_crashed_domains() {
+    let crashed = DOMAIN_INFO
+        .lock()
+        .domain_list
+        .values()
+        .filter(|data| data.state == DomainState::Crashed)
+        .map(|data| data.name.clone())
+        .collect::<Vec<String>>();
+    for name in crashed {
+        match query_domain(&name) {
+            Some(DomainType::LogDomain(logger)) => {
+                if let Ok(proxy) = logger.downcast_arc::<LogDomainProxy>() {
+                    proxy.recover();
+                }
+            }
+            Some(DomainType::EmptyDeviceDomain(empty_device)) => {
+                if let Ok(proxy) = empty_device.downcast_arc::<EmptyDeviceDomainProxy>() {
+                    proxy.recover();
+                }
+            }
+            Some(DomainType::BlockDeviceDomain(block_device)) => {
+                if let Ok(proxy) = block_device.downcast_arc::<BlockDeviceDomainProxy>() {
+                    proxy.recover();
+                }
+            }
+            None => pr_err!("Crashed domain {} is not registered", name),
+        }
+    }
+}
@@ -0,0 +1,14 @@
+//! The items of the tcb used by the proxies generated by `gproxy`.
+//!
+//! The path of this module is given to the `gen_for_<Trait>!` macros.
+pub use super::{
+    enter_domain, now_ns, retry_recovery, schedule_recovery, update_timeout_ms, wait_quiescent,
+    FaultInjector, ProxyBuilder, ProxyStats, UpdateStats, MAX_RECOVERY_ATTEMPTS,
+};
+pub use crate::{
+    domain_helper::{
+        alloc_domain_id, crash_domain, discard_new_domain, free_domain_resource,
+        reload_domain_info, set_domain_state, FreeShared,
+    },
+    domain_loader::{creator::recreate_domain, loader::DomainLoader},
+};
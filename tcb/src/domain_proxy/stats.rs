@@ -0,0 +1,123 @@
+use alloc::vec::Vec;
+use core::fmt::{Display, Formatter};
+
+use corelib::{LinuxError, LinuxResult};
+use kernel::sync::LongLongPerCpu;
+
+use crate::domain_proxy::now_ns;
+
+/// The bucket `i` counts the calls which take `[2^i, 2^(i+1))` nanoseconds
+const HISTOGRAM_BUCKETS: usize = 32;
+/// Only one of every `LATENCY_SAMPLE` calls on a cpu is timed
+const LATENCY_SAMPLE: i64 = 64;
+
+/// The counters are per-cpu, so the calls on different cpus don't share a cache line. They
+/// are summed when the stats are read.
+#[derive(Debug)]
+struct MethodStats {
+    calls: LongLongPerCpu,
+    errors: LongLongPerCpu,
+    crashes: LongLongPerCpu,
+    histogram: [LongLongPerCpu; HISTOGRAM_BUCKETS],
+}
+
+impl MethodStats {
+    fn new() -> Self {
+        Self {
+            calls: LongLongPerCpu::new(),
+            errors: LongLongPerCpu::new(),
+            crashes: LongLongPerCpu::new(),
+            histogram: core::array::from_fn(|_| LongLongPerCpu::new()),
+        }
+    }
+
+    fn reset(&self) {
+        self.calls.for_each_cpu(|v| *v = 0);
+        self.errors.for_each_cpu(|v| *v = 0);
+        self.crashes.for_each_cpu(|v| *v = 0);
+        self.histogram
+            .iter()
+            .for_each(|count| count.for_each_cpu(|v| *v = 0));
+    }
+}
+
+/// The call statistics of every method of a proxy.
+///
+/// `DOMAINCRASH` is counted both as an error and as a crash. The latency histogram only
+/// counts the sampled calls, one of every `LATENCY_SAMPLE` calls on each cpu.
+#[derive(Debug)]
+pub struct ProxyStats {
+    methods: &'static [&'static str],
+    stats: Vec<MethodStats>,
+}
+
+impl ProxyStats {
+    pub fn new(methods: &'static [&'static str]) -> Self {
+        Self {
+            methods,
+            stats: methods.iter().map(|_| MethodStats::new()).collect(),
+        }
+    }
+
+    /// Count a call of the `method`-th method, the returned start time is passed to
+    /// [ProxyStats::record]. It's `0` if the call is not timed.
+    #[inline]
+    pub fn start(&self, method: usize) -> u64 {
+        let calls = self.stats[method].calls.get_with(|calls| {
+            *calls += 1;
+            *calls
+        });
+        if calls % LATENCY_SAMPLE == 0 {
+            now_ns()
+        } else {
+            0
+        }
+    }
+
+    /// Record the result of a call of the `method`-th method which started at `start`
+    #[inline]
+    pub fn record<T>(&self, method: usize, start: u64, r: &LinuxResult<T>) {
+        let stats = &self.stats[method];
+        if start != 0 {
+            let ns = now_ns().saturating_sub(start);
+            let bucket = (u64::BITS - ns.leading_zeros()).saturating_sub(1) as usize;
+            stats.histogram[bucket.min(HISTOGRAM_BUCKETS - 1)].get_with(|count| *count += 1);
+        }
+        if let Err(e) = r {
+            stats.errors.get_with(|errors| *errors += 1);
+            if matches!(e, LinuxError::DOMAINCRASH) {
+                stats.crashes.get_with(|crashes| *crashes += 1);
+            }
+        }
+    }
+
+    pub fn reset(&self) {
+        self.stats.iter().for_each(MethodStats::reset);
+    }
+}
+
+impl Display for ProxyStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        for (name, stats) in self.methods.iter().zip(self.stats.iter()) {
+            let calls = stats.calls.sum();
+            if calls == 0 {
+                continue;
+            }
+            writeln!(
+                f,
+                "{}: calls {}, errors {}, crashes {}",
+                name,
+                calls,
+                stats.errors.sum(),
+                stats.crashes.sum()
+            )?;
+            for (i, count) in stats.histogram.iter().enumerate() {
+                let count = count.sum();
+                if count != 0 {
+                    writeln!(f, "  [{}ns, {}ns): {}", 1u64 << i, 1u64 << (i + 1), count)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,6 +1,6 @@
 use alloc::sync::Arc;
 
-use interface::logger::LogDomain;
+use interface::logger::{Level, LogDomain, LogRecord};
 use kernel::{
     buf::KernelSlicePtrWriter,
     error::KernelResult,
@@ -8,11 +8,24 @@ use kernel::{
     sync::{CpuId, LongLongPerCpu},
     sysctl::SysctlStorage,
 };
-use rref::RRefVec;
+use ksync::Mutex;
+use rref::RRefRing;
+
+/// The number of records the log ring holds, they are handed to the log domain at once
+const LOG_RING_SIZE: usize = 16;
+
+/// The records waiting for the log domain
+struct LogQueue {
+    /// The ring is reused until the log domain fails
+    ring: Option<RRefRing<LogRecord>>,
+    /// A writer is handing the records to the log domain, the others queue theirs meanwhile
+    flushing: bool,
+}
 
 pub struct EntropySource {
     log_domain: Arc<dyn LogDomain>,
     counter: LongLongPerCpu,
+    log_queue: Mutex<LogQueue>,
 }
 
 impl EntropySource {
@@ -20,8 +33,93 @@ impl EntropySource {
         Self {
             log_domain,
             counter: LongLongPerCpu::new(),
+            log_queue: Mutex::new(LogQueue {
+                ring: None,
+                flushing: false,
+            }),
+        }
+    }
+
+    /// Log `msg` through the log ring.
+    ///
+    /// A write is logged at once when no other writer is in the log domain. Otherwise its
+    /// record waits in the ring for that writer, which hands over the ring again before it
+    /// returns, or until the ring is full. So the records are only batched under concurrent
+    /// writes and no record waits for a later write.
+    fn log(&self, msg: &str) {
+        let record = LogRecord::new(Level::Info, msg);
+        let (ring, drain) = {
+            let mut queue = self.log_queue.lock();
+            if queue.ring.is_none() {
+                queue.ring = RRefRing::try_new(LOG_RING_SIZE);
+            }
+            let Some(ring) = queue.ring.as_ref() else {
+                pr_err!("EntropySource: failed to allocate the log ring, drop the record");
+                return;
+            };
+            // the ring is handed over once it's full, so it only fails if the log domain
+            // gave back a ring it didn't drain
+            if ring.push(record).is_err() {
+                pr_warn!("EntropySource: the log ring is full, drop the record");
+            }
+            let drain = !queue.flushing;
+            if !drain && !ring.is_full() {
+                return;
+            }
+            queue.flushing = true;
+            (queue.ring.take(), drain)
+        };
+        if let Some(ring) = ring {
+            self.log_batch(ring);
+        }
+        if drain {
+            self.drain();
+        }
+    }
+
+    /// Hand the records queued by the other writers meanwhile to the log domain, until no
+    /// record is left
+    fn drain(&self) {
+        loop {
+            let ring = {
+                let mut queue = self.log_queue.lock();
+                match queue.ring.take_if(|ring| !ring.is_empty()) {
+                    Some(ring) => ring,
+                    None => {
+                        queue.flushing = false;
+                        return;
+                    }
+                }
+            };
+            self.log_batch(ring);
         }
     }
+
+    /// Hand the queued records to the log domain
+    pub fn flush(&self) {
+        let ring = self.log_queue.lock().ring.take_if(|ring| !ring.is_empty());
+        if let Some(ring) = ring {
+            self.log_batch(ring);
+        }
+    }
+
+    /// Pass `ring` to the log domain out of the lock, a concurrent writer queues its records
+    /// in a new ring meanwhile. If the call fails, the records are lost and the ring is
+    /// reclaimed with the log domain.
+    fn log_batch(&self, ring: RRefRing<LogRecord>) {
+        match self.log_domain.log_batch(ring) {
+            Ok(ring) => {
+                self.log_queue.lock().ring.get_or_insert(ring);
+            }
+            Err(e) => pr_err!("EntropySource: log_batch failed: {:?}", e),
+        }
+    }
+}
+
+impl Drop for EntropySource {
+    fn drop(&mut self) {
+        self.flush();
+    }
 }
 
 impl SysctlStorage for EntropySource {
@@ -36,10 +134,7 @@ impl SysctlStorage for EntropySource {
             *counter += 1;
         });
         let str = core::str::from_utf8(data).unwrap();
-        let log_message = RRefVec::from_slice(str.as_bytes());
-        let _r = self
-            .log_domain
-            .log(interface::logger::Level::Info, &log_message);
+        self.log(str);
         random::add_randomness(data);
         (data.len(), Ok(()))
     }
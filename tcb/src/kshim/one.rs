@@ -30,7 +30,7 @@ impl SysctlStorage for OneDevice {
             Ok(r) => (r, Ok(())),
             Err(e) => {
                 if e == LinuxErrno::DOMAINCRASH {
-                    pr_err!("OneDevice::store_value: domain crash\n");
+                    pr_err!("OneDevice::store_value: domain crash, it has been reloaded\n");
                 }
                 (0, Err(EINVAL))
             }
@@ -21,13 +21,28 @@ mod kshim;
 mod mem;
 
 use alloc::{borrow::ToOwned, string::String};
+use core::sync::atomic::{AtomicBool, AtomicU32};
 
 use kernel::{code, sysctl::Sysctl, ThisModule};
 
-use crate::{channel::CommandChannel, kshim::KObj};
+use crate::{
+    channel::{
+        CommandChannel, FaultChannel, InfoChannel, QuotaChannel, RegisterChannel,
+        SearchPathChannel, SharedHeapChannel,
+    },
+    kshim::KObj,
+};
 
 struct TcbModule {
     _sysctl_domain_command: Sysctl<CommandChannel>,
+    _sysctl_domain_info: Sysctl<InfoChannel>,
+    _sysctl_domain_quota: Sysctl<QuotaChannel>,
+    _sysctl_domain_fault: Sysctl<FaultChannel>,
+    _sysctl_domain_register: Sysctl<RegisterChannel>,
+    _sysctl_domain_search_path: Sysctl<SearchPathChannel>,
+    _sysctl_update_timeout: Sysctl<&'static AtomicU32>,
+    _sysctl_shared_heap: Sysctl<SharedHeapChannel>,
+    _sysctl_shared_heap_debug: Sysctl<&'static AtomicBool>,
     kobj: KObj,
     message: String,
 }
@@ -39,14 +54,35 @@ impl kernel::Module for TcbModule {
         println_color!(32, "This is a green message");
         println_color!(33, "This is a yellow message");
         // kbind::logger::init_logger();
+        domain_loader::verify::init_domain_key().map_err(|_| code::EINVAL)?;
         let channel = channel::init_domain_channel()?;
+        let info = channel::init_domain_info()?;
+        let quota = channel::init_domain_quota()?;
+        let fault = channel::init_domain_fault()?;
+        let register = channel::init_domain_register()?;
+        let search_path = channel::init_domain_search_path()?;
+        let update_timeout = channel::init_update_timeout()?;
+        let shared_heap = channel::init_shared_heap()?;
+        let shared_heap_debug = channel::init_shared_heap_debug()?;
+        // the default domains may crash as soon as they are loaded
+        domain_proxy::init_recovery();
         domain::init_domain_system().map_err(|e| {
             error!("Failed to init domain system: {:?}", e);
+            domain_proxy::exit_recovery();
             code::EINVAL
         })?;
+        autoload_domains();
         let kobj = kshim::init_kernel_shim()?;
         Ok(TcbModule {
             _sysctl_domain_command: channel,
+            _sysctl_domain_info: info,
+            _sysctl_domain_quota: quota,
+            _sysctl_domain_fault: fault,
+            _sysctl_domain_register: register,
+            _sysctl_domain_search_path: search_path,
+            _sysctl_update_timeout: update_timeout,
+            _sysctl_shared_heap: shared_heap,
+            _sysctl_shared_heap_debug: shared_heap_debug,
             kobj,
             message: "on the heap!".to_owned(),
         })
@@ -55,15 +91,48 @@ impl kernel::Module for TcbModule {
 
 impl Drop for TcbModule {
     fn drop(&mut self) {
+        domain_proxy::exit_recovery();
+        channel::clear_domain_stats();
         println!("My message is {}", self.message);
         println!("Goodbye kernel module!");
     }
 }
 
+/// Load the domains of the manifest given by the module parameters.
+///
+/// The module works without the manifest, the failures are only reported.
+fn autoload_domains() {
+    let search_path = String::from_utf8_lossy(domain_search_path.read());
+    if let Err(e) = domain_loader::path::set_domain_search_path(&search_path) {
+        error!("Invalid domain search path {}: {:?}", search_path, e);
+    }
+    let manifest = String::from_utf8_lossy(domain_manifest.read());
+    if manifest.is_empty() {
+        return;
+    }
+    match domain::autoload_domains(&manifest) {
+        Ok(0) => {}
+        Ok(failed) => error!("{} domains of {} failed to load", failed, manifest),
+        Err(e) => println!("No domain manifest {}: {:?}", manifest, e),
+    }
+}
+
 module! {
     type: TcbModule,
     name: "TcbModule",
     author: "godones",
     description: "TCB kernel module",
     license: "GPL",
+    params: {
+        domain_manifest: str {
+            default: b"manifest",
+            permissions: 0o444,
+            description: "The manifest of the domains loaded with the module, empty to disable",
+        },
+        domain_search_path: str {
+            default: b"/lib/domains",
+            permissions: 0o444,
+            description: "The directories searched for the domain files, separated by ':'",
+        },
+    },
 }
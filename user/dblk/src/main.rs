@@ -2,48 +2,58 @@ use domain_helper::{DomainHelperBuilder, DomainTypeRaw};
 
 fn main() {
     let argv: Vec<String> = std::env::args().collect();
-    if argv.len() != 2 {
-        println!("Usage: dblk [load]/[unload]/[test]");
+    if argv.len() < 2 {
+        println!("Usage: dblk [load <key=value>...]/[unload <name>]/[test]");
         return;
     }
     let option = argv[1].as_str();
     match option {
         "load" => {
             println!("Load block device domain");
-            load_block_device_domain();
+            // e.g. `dblk load param_irq_mode=2 param_completion_time_nsec=10000`
+            load_block_device_domain(&argv[2..]);
         }
         "unload" => {
             println!("Unload block device domain");
-            unload_block_device_domain();
+            // the first instance is named `block_device-1`
+            let name = argv.get(2).map(|s| s.as_str()).unwrap_or("block_device-1");
+            unload_block_device_domain(name);
         }
         "test" => {
             println!("Run block device domain test");
             run_block_device_domain_test();
         }
         _ => {
-            println!("Usage: dblk [load]/[unload]/[test]");
+            println!("Usage: dblk [load <key=value>...]/[unload <name>]/[test]");
             return;
         }
     }
 }
 
-fn load_block_device_domain() {
+fn load_block_device_domain(args: &[String]) {
     println!("Load block device domain");
-    let builder = DomainHelperBuilder::new()
+    let mut builder = DomainHelperBuilder::new()
         .ty(DomainTypeRaw::BlockDeviceDomain)
         .domain_name("block_device")
         .domain_file_name("rnull")
         .domain_register_ident("rnull");
+    for arg in args {
+        let Some((key, value)) = arg.split_once('=') else {
+            println!("Invalid argument: {}, it should be key=value", arg);
+            return;
+        };
+        builder = builder.arg(key, value);
+    }
     builder.clone().register_domain_file().unwrap();
-    builder.clone().load_domain().unwrap();
-    println!("Load block device domain successfully");
+    let name = builder.clone().load_domain().unwrap();
+    println!("Load block device domain {} successfully", name);
 }
 
-fn unload_block_device_domain() {
-    println!("Unload block device domain");
+fn unload_block_device_domain(name: &str) {
+    println!("Unload block device domain {}", name);
     DomainHelperBuilder::new()
         .ty(DomainTypeRaw::BlockDeviceDomain)
-        .domain_name("block_device")
+        .domain_name(name)
         .unload_domain()
         .unwrap();
     println!("Unload block device domain successfully");
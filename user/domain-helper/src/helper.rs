@@ -1,17 +1,18 @@
 use std::{
     fs,
     fs::OpenOptions,
-    io::{Read, Write},
+    io::{ErrorKind, Read, Write},
     path::Path,
+    sync::atomic::{AtomicU64, Ordering},
 };
 
 use command::{
-    Command, LoadCommand, Response, SendCommand, StartCommand, StopCommand, UnloadCommand,
-    UpdateCommand,
+    checksum, Command, ExitCommand, LoadCommand, RegisterPathCommand, Response, SendCommand,
+    StartCommand, StopCommand, UnloadCommand, UpdateCommand,
 };
 
 use super::Result;
-use crate::{DOMAIN_TYPE, PATH};
+use crate::{DOMAIN_TYPE, FAULT_PATH, PATH};
 
 fn find_path(name: &str) -> Option<String> {
     for ty in DOMAIN_TYPE {
@@ -24,98 +25,111 @@ fn find_path(name: &str) -> Option<String> {
     None
 }
 
+/// The size of the domain data sent in one `send` command
+const CHUNK_SIZE: usize = 4096;
+
+static REQUEST_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Send the command and wait for the response of it
+fn request(command: Command) -> Result<Response> {
+    let request_id = REQUEST_ID.fetch_add(1, Ordering::Relaxed);
+    write_to_channel(&command.encode(request_id))?;
+    let res_buf = read_from_channel()?;
+    let (id, response) =
+        Response::decode(&res_buf).map_err(|e| format!("Parse response failed: {}", e))?;
+    if id != request_id {
+        return Err(format!("Invalid request id {}, expect {}", id, request_id).into());
+    }
+    match response {
+        Response::Error(code) => Err(code.to_string().into()),
+        response => Ok(response),
+    }
+}
+
 /// Register a domain to the kernel
 ///
 /// It will communicate with the kernel module using a file in /proc/sys/rust/domain/command
 /// and send the domain file to the kernel module
 pub fn register_domain(name: &str, ty: u8, register_domain_elf_ident: &str) -> Result<()> {
     let path = find_path(name).ok_or_else(|| format!("Domain file {} not found", name))?;
-    let mut file = fs::File::open(path)?;
+    let mut file = fs::File::open(&path)?;
     let file_size = file.metadata()?.len();
 
-    // send start command
-    let start_command = StartCommand {
+    // start an upload session
+    let start_command = Command::Start(StartCommand {
         register_domain_elf_ident,
         domain_type: ty,
         domain_size: file_size as usize,
+    });
+    let session = match request(start_command)? {
+        Response::Ok(session) => session,
+        response => return Err(format!("Invalid response: {:?}", response).into()),
     };
-    let start_command = Command::Start(start_command);
-    let start_command = start_command.to_bytes();
-
-    write_to_channel(&start_command)?;
-
-    let res_buf = read_from_channel()?;
-    let response = Response::parse(&res_buf).ok_or("Parse response failed")?;
-    println!("Response: {:?}", response);
-    let id = match response {
-        Response::Ok(id) => id,
-        _ => {
-            println!("Invalid response");
-            return Err("Invalid response".into());
+    println!("Upload session: {}", session);
+    match send_domain_data(&mut file, session, file_size as usize) {
+        Ok(()) => {}
+        Err(e) => {
+            // abort the session, the error of the upload is more useful
+            let _ = request(Command::Exit(ExitCommand { session }));
+            return Err(e);
         }
+    }
+    // finish the session with the detached signature generated by `xtask sign`
+    let signature = match fs::read(format!("{}.sig", path)) {
+        Ok(signature) => signature,
+        Err(e) if e.kind() == ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e.into()),
     };
-    // send file data
+    request(Command::Stop(StopCommand {
+        session,
+        signature: &signature,
+    }))?;
+    println!("Domain registered: {}", register_domain_elf_ident);
+    Ok(())
+}
+
+fn send_domain_data(file: &mut fs::File, session: u64, file_size: usize) -> Result<()> {
     let mut count = 0;
-    let mut buf = [0; 512];
+    let mut buf = vec![0; CHUNK_SIZE];
     let mut data_id = 0;
-    while count < file_size as usize {
+    while count < file_size {
         let res = file.read(&mut buf)?;
         if res == 0 {
             break;
         }
         let send_command = Command::Send(SendCommand {
-            id: id as u64,
+            session,
             data_id,
-            bytes: res,
+            checksum: checksum(&buf[..res]),
             data: &buf[..res],
         });
-        let send_command = send_command.to_bytes();
-        write_to_channel(&send_command)?;
         // read response to make sure the data is sent
-        let res_buf = read_from_channel()?;
-        let response = Response::parse(&res_buf).ok_or("Parse response failed")?;
-        println!("Response: {:?}", response);
-
-        match response {
-            Response::Receive(id, data_id, bytes) => {
-                if id != id {
-                    println!("Invalid id");
-                    return Err("Invalid id".into());
-                }
-                if data_id != data_id {
-                    println!("Invalid data id");
-                    return Err("Invalid data id".into());
-                }
-                if bytes != res {
-                    println!("Invalid data length");
-                    return Err("Invalid data length".into());
-                }
-            }
-            _ => {
-                println!("Invalid response");
-                return Err("Invalid response".into());
-            }
+        match request(send_command)? {
+            Response::Receive {
+                session: s,
+                data_id: d,
+                bytes,
+            } if s == session && d == data_id && bytes as usize == res => {}
+            response => return Err(format!("Invalid response: {:?}", response).into()),
         }
         count += res;
         data_id += 1;
     }
-    // send stop command
-    let stop_command = StopCommand { id: id as u64 };
-    let stop_command = Command::Stop(stop_command);
-    let stop_command = stop_command.to_bytes();
-    write_to_channel(&stop_command)?;
+    Ok(())
+}
 
-    let res_buf = read_from_channel()?;
-    let response = Response::parse(&res_buf).ok_or("Parse response failed")?;
+/// Let the kernel read the domain file `path` itself and register it
+///
+/// `path` is an absolute path or a file name in the domain search path of the kernel, see
+/// /proc/sys/rust/domain/search_path
+pub fn register_domain_path(path: &str, ty: u8, register_domain_elf_ident: &str) -> Result<()> {
+    let register_command = Command::RegisterPath(RegisterPathCommand {
+        register_domain_elf_ident,
+        domain_type: ty,
+        path,
+    });
+    let response = request(register_command)?;
     println!("Response: {:?}", response);
-    let id = match response {
-        Response::Ok(id) => id,
-        _ => {
-            println!("Invalid response");
-            return Err("Invalid response".into());
-        }
-    };
-    println!("Domain registered: {}", id);
     Ok(())
 }
 
@@ -125,37 +139,50 @@ pub fn update_domain(old_ident: &str, register_domain_elf_ident: &str, ty: u8) -
         register_domain_elf_ident,
         domain_type: ty,
     });
-    let update_command = update_command.to_bytes();
-    write_to_channel(&update_command)?;
-
-    let res_buf = read_from_channel()?;
-    let response = Response::parse(&res_buf).ok_or("Parse response failed")?;
+    let response = request(update_command)?;
     println!("Response: {:?}", response);
     Ok(())
 }
 
-pub fn load_domain(register_domain_elf_ident: &str, domain_ident: &str, ty: u8) -> Result<()> {
+/// Load a domain instance and return its name
+pub fn load_domain(
+    register_domain_elf_ident: &str,
+    domain_ident: &str,
+    ty: u8,
+    args: &str,
+) -> Result<String> {
     let load_command = Command::Load(LoadCommand {
         register_domain_elf_ident,
         domain_ident,
         domain_type: ty,
+        args,
     });
-    let load_command = load_command.to_bytes();
-    write_to_channel(&load_command)?;
-
-    let res_buf = read_from_channel()?;
-    let response = Response::parse(&res_buf).ok_or("Parse response failed")?;
+    let response = request(load_command)?;
     println!("Response: {:?}", response);
-    Ok(())
+    match response {
+        Response::Ok(instance) => Ok(format!("{}-{}", domain_ident, instance)),
+        _ => Err("Unexpected response".into()),
+    }
 }
 
 pub fn unload_domain(domain_ident: &str) -> Result<()> {
     let unload_command = Command::Unload(UnloadCommand { domain_ident });
-    let unload_command = unload_command.to_bytes();
-    write_to_channel(&unload_command)?;
+    let response = request(unload_command)?;
+    println!("Response: {:?}", response);
+    Ok(())
+}
 
-    let res_buf = read_from_channel()?;
-    let response = Response::parse(&res_buf).ok_or("Parse response failed")?;
+/// Write a command to /proc/sys/rust/domain/fault
+pub fn write_fault(command: &str) -> Result<()> {
+    let mut file = OpenOptions::new().write(true).open(FAULT_PATH)?;
+    file.write_all(command.as_bytes())?;
+    Ok(())
+}
+
+/// Unload the domain and all domains which depend on it
+pub fn unload_domain_tree(domain_ident: &str) -> Result<()> {
+    let unload_command = Command::UnloadTree(UnloadCommand { domain_ident });
+    let response = request(unload_command)?;
     println!("Response: {:?}", response);
     Ok(())
 }
@@ -167,7 +194,7 @@ fn open_channel() -> Result<fs::File> {
 
 fn write_to_channel(data: &[u8]) -> Result<()> {
     let mut file = open_channel()?;
-    file.write(data)?;
+    file.write_all(data)?;
     Ok(())
 }
 
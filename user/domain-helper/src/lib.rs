@@ -1,7 +1,10 @@
 mod helper;
 use std::error::Error;
 
-use crate::helper::{load_domain, register_domain, unload_domain, update_domain};
+use crate::helper::{
+    load_domain, register_domain, register_domain_path, unload_domain, unload_domain_tree,
+    update_domain, write_fault,
+};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Ord, PartialOrd)]
 #[repr(u8)]
@@ -22,6 +25,7 @@ impl From<u8> for DomainTypeRaw {
 }
 
 const PATH: &str = "/proc/sys/rust/domain/command";
+const FAULT_PATH: &str = "/proc/sys/rust/domain/fault";
 const DOMAIN_TYPE: &[&str] = &["disk", "init"];
 
 type Result<T> = core::result::Result<T, Box<dyn Error>>;
@@ -32,6 +36,7 @@ pub struct DomainHelperBuilder {
     domain_file_name: Option<String>,
     domain_register_ident: Option<String>,
     domain_name: Option<String>,
+    args: Vec<(String, String)>,
 }
 
 impl DomainHelperBuilder {
@@ -41,6 +46,7 @@ impl DomainHelperBuilder {
             domain_file_name: None,
             domain_name: None,
             domain_register_ident: None,
+            args: Vec::new(),
         }
     }
 
@@ -62,6 +68,37 @@ impl DomainHelperBuilder {
         self
     }
 
+    /// Add a `key=value` argument which is passed to the domain when it is loaded
+    pub fn arg(mut self, key: &str, value: impl ToString) -> Self {
+        self.args.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    /// Limit the private pages of the loaded domain
+    pub fn page_quota(self, pages: usize) -> Self {
+        self.arg("quota_pages", pages)
+    }
+
+    /// Limit the shared heap bytes of the loaded domain
+    pub fn heap_quota(self, bytes: usize) -> Self {
+        self.arg("quota_heap", bytes)
+    }
+
+    /// Declare that the loaded domain depends on the domain `name`, which must be loaded
+    pub fn requires(self, name: &str) -> Self {
+        self.arg("requires", name)
+    }
+
+    /// Allow the loaded domain to look up the domain `name`, `*` allows all domains
+    pub fn allow_lookup(self, name: &str) -> Self {
+        self.arg("cap_lookup", name)
+    }
+
+    /// Allow the loaded domain to use a privileged syscall: create, register, update or reload
+    pub fn allow_syscall(self, name: &str) -> Self {
+        self.arg("cap_syscall", name)
+    }
+
     /// Set the domain file path which will be opened and registered
     pub fn domain_register_ident(mut self, domain_register_ident: &str) -> Self {
         self.domain_register_ident = Some(domain_register_ident.to_string());
@@ -83,6 +120,17 @@ impl DomainHelperBuilder {
         register_domain(domain_file_name, ty as u8, domain_register_ident)?;
         Ok(())
     }
+
+    /// Register the domain file which the kernel reads from `path` itself
+    pub fn register_domain_path(self, path: &str) -> Result<()> {
+        let ty = self.ty.ok_or("Domain type is not set")?;
+        let domain_register_ident = self
+            .domain_register_ident
+            .as_ref()
+            .ok_or("Domain file path is not set")?;
+        register_domain_path(path, ty as u8, domain_register_ident)?;
+        Ok(())
+    }
     pub fn update_domain(self) -> Result<()> {
         let ty = self.ty.ok_or("Domain type is not set")?;
         let domain_name = self.domain_name.as_ref().ok_or("Domain name is not set")?;
@@ -94,15 +142,21 @@ impl DomainHelperBuilder {
         Ok(())
     }
 
-    pub fn load_domain(self) -> Result<()> {
+    /// Load a domain instance and return its name
+    pub fn load_domain(self) -> Result<String> {
         let domain_name = self.domain_name.as_ref().ok_or("Domain name is not set")?;
         let ty = self.ty.ok_or("Domain type is not set")?;
         let domain_register_ident = self
             .domain_register_ident
             .as_ref()
             .ok_or("Domain file name is not set")?;
-        load_domain(domain_register_ident, domain_name, ty as u8)?;
-        Ok(())
+        let args = self
+            .args
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect::<Vec<_>>()
+            .join(",");
+        load_domain(domain_register_ident, domain_name, ty as u8, &args)
     }
 
     pub fn unload_domain(self) -> Result<()> {
@@ -110,4 +164,23 @@ impl DomainHelperBuilder {
         unload_domain(domain_name)?;
         Ok(())
     }
+
+    /// Unload the domain together with the domains which depend on it
+    pub fn unload_domain_tree(self) -> Result<()> {
+        let domain_name = self.domain_name.as_ref().ok_or("Domain name is not set")?;
+        unload_domain_tree(domain_name)?;
+        Ok(())
+    }
+
+    /// Arm a fault on `method` of the loaded domain, e.g. `panic nth=3` or `error=EIO prob=10`
+    pub fn inject_fault(self, method: &str, fault: &str) -> Result<()> {
+        let domain_name = self.domain_name.as_ref().ok_or("Domain name is not set")?;
+        write_fault(&format!("{} {} {}", domain_name, method, fault))
+    }
+
+    /// Disarm all faults of the loaded domain
+    pub fn clear_faults(self) -> Result<()> {
+        let domain_name = self.domain_name.as_ref().ok_or("Domain name is not set")?;
+        write_fault(&format!("{} off", domain_name))
+    }
 }
@@ -27,6 +27,9 @@ enum Commands {
         /// The target architecture, default is riscv64
         #[arg(short, long, value_name = "ARCH")]
         arch: Option<String>,
+        /// Sign the domain with the key generated by `keygen`
+        #[arg(short, long, value_name = "KEY")]
+        key: Option<String>,
     },
     BuildAll {
         /// The log level, default is INFO
@@ -35,6 +38,9 @@ enum Commands {
         /// The target architecture, default is riscv64
         #[arg(short, long, value_name = "ARCH")]
         arch: Option<String>,
+        /// Sign the domain with the key generated by `keygen`
+        #[arg(short, long, value_name = "KEY")]
+        key: Option<String>,
     },
     Move {
         /// The name of the domain project
@@ -51,6 +57,21 @@ enum Commands {
         #[arg(short, long, value_name = "NAME", default_value = "")]
         name: String,
     },
+    /// Generates a key to sign the domains
+    Keygen {
+        /// The file to save the signing key
+        #[arg(short, long, value_name = "OUT")]
+        out: String,
+    },
+    /// Signs a built domain
+    Sign {
+        /// The name of the domain project
+        #[arg(short, long, value_name = "NAME")]
+        name: String,
+        /// The signing key generated by `keygen`
+        #[arg(short, long, value_name = "KEY")]
+        key: String,
+    },
 }
 
 fn main() {
@@ -63,16 +84,21 @@ fn main() {
             println!("Creating new domain project: {}", name);
             subcommand::new::create_domain(name);
         }
-        Some(Commands::BuildAll { log, arch }) => {
+        Some(Commands::BuildAll { log, arch, key }) => {
             println!("Building all domain projects, LOG: {log}, ARCH: {:?}", arch);
-            subcommand::build::build_all(log.to_string(), arch.clone());
+            subcommand::build::build_all(log.to_string(), arch.clone(), key.as_deref());
         }
-        Some(Commands::Build { name, log, arch }) => {
+        Some(Commands::Build {
+            name,
+            log,
+            arch,
+            key,
+        }) => {
             println!(
                 "Building domain project: {}, LOG: {}, ARCH: {:?}",
                 name, log, arch
             );
-            subcommand::build::build_single(name, log, arch.clone());
+            subcommand::build::build_single(name, log, arch.clone(), key.as_deref());
         }
         Some(Commands::Move { name }) => {
             println!("Moving domain project: {}", name);
@@ -86,6 +112,14 @@ fn main() {
             println!("Formatting domain project: {}", name);
             subcommand::fmt::fmt_domain(name.to_string());
         }
+        Some(Commands::Keygen { out }) => {
+            println!("Generating signing key: {}", out);
+            subcommand::sign::keygen(out);
+        }
+        Some(Commands::Sign { name, key }) => {
+            println!("Signing domain project: {}", name);
+            subcommand::sign::sign_domain(name, key);
+        }
         None => {}
     }
 }
@@ -1,8 +1,8 @@
 use std::{fs, path::Path};
 
-use crate::subcommand::{Arch, Config, DOMAIN_SET};
+use crate::subcommand::{sign, Arch, Config, DOMAIN_SET};
 
-pub fn build_single(name: &str, log: &str, arch: Option<String>) {
+pub fn build_single(name: &str, log: &str, arch: Option<String>, key: Option<&str>) {
     let domain_list = fs::read_to_string("./domains/domain-list.toml").unwrap();
     let config: Config = toml::from_str(&domain_list).unwrap();
     let all_members = config.domains.get("members").unwrap();
@@ -16,11 +16,12 @@ pub fn build_single(name: &str, log: &str, arch: Option<String>) {
     }
     let init_members = config.domains.get("init_members").unwrap();
     if init_members.contains(&r_name.to_string()) {
-        build_domain(name, log.to_string(), "init", arch.into());
+        build_domain(name, log.to_string(), "init", arch.into(), key);
+        write_init_manifest(init_members, all_members);
     } else {
         let disk_members = config.domains.get("disk_members").unwrap();
         if disk_members.contains(&r_name.to_string()) {
-            build_domain(name, log.to_string(), "disk", arch.into());
+            build_domain(name, log.to_string(), "disk", arch.into(), key);
         } else {
             println!(
                 "Domain [{}] is not in the init or disk members list, skip building",
@@ -30,7 +31,7 @@ pub fn build_single(name: &str, log: &str, arch: Option<String>) {
     }
 }
 
-pub fn build_domain(name: &str, log: String, dir: &str, arch: Arch) {
+pub fn build_domain(name: &str, log: String, dir: &str, arch: Arch, key: Option<&str>) {
     // change the directory to the domain project
     // run cargo build
     println!("Building domain [{}] project", name);
@@ -66,17 +67,61 @@ pub fn build_domain(name: &str, log: String, dir: &str, arch: Arch) {
                 .status()
                 .expect("failed to execute cp");
             println!("Copy domain [{}] project success", name);
+            if let Some(key) = key {
+                let path = format!("./build/{}/g{}", dir, name);
+                sign::sign_file(Path::new(&path), key);
+            }
             return;
         }
     }
 }
 
-pub fn build_all(log: String, arch: Option<String>) {
+/// The file name of the manifest of the init domains, it's the default `domain_manifest` of
+/// the tcb
+const INIT_MANIFEST: &str = "manifest";
+
+/// The domain type of `name`, it's the trait returned by the `main` of the domain
+fn domain_type(name: &str) -> Option<String> {
+    for ty in DOMAIN_SET {
+        let path = format!("./domains/{}/{}/g{}/src/main.rs", ty, name, name);
+        if let Ok(main) = fs::read_to_string(&path) {
+            let (_, ret) = main.split_once(") -> Box<dyn ")?;
+            let end = ret.find(|c: char| !c.is_alphanumeric() && c != '_')?;
+            return Some(ret[..end].to_string());
+        }
+    }
+    None
+}
+
+/// Write the manifest which loads the init domains with the tcb, a line
+/// `<ident> <type> <file>` for each domain.
+fn write_init_manifest(init_members: &[String], all_members: &[String]) {
+    let mut manifest = String::from("# generated by `xtask build` from the init_members\n");
+    for name in init_members
+        .iter()
+        .filter(|name| all_members.contains(name))
+    {
+        match domain_type(name) {
+            Some(ty) => manifest.push_str(&format!("{} {} g{}\n", name, ty, name)),
+            None => println!(
+                "The type of domain [{}] is unknown, skip it in the manifest",
+                name
+            ),
+        }
+    }
+    fs::create_dir_all("./build/init").expect("failed to create the init directory");
+    let path = format!("./build/init/{}", INIT_MANIFEST);
+    fs::write(&path, manifest).expect("failed to write the manifest");
+    println!("Write the manifest of the init domains to {}", path);
+}
+
+pub fn build_all(log: String, arch: Option<String>, key: Option<&str>) {
     let domain_list = fs::read_to_string("./domains/domain-list.toml").unwrap();
     let config: Config = toml::from_str(&domain_list).unwrap();
     println!("Start building all domains");
     let all_members = config.domains.get("members").unwrap().clone();
     let init_members = config.domains.get("init_members").unwrap().clone();
+    write_init_manifest(&init_members, &all_members);
     for domain_name in init_members {
         if !all_members.contains(&domain_name) {
             println!(
@@ -89,7 +134,7 @@ pub fn build_all(log: String, arch: Option<String>) {
         // pool.spawn(move || build_domain(&domain_name, value, "init"));
         // build_domain(&domain_name, value, "init")
         let target_arch = arch.clone().into();
-        build_domain(&domain_name, value, "init", target_arch);
+        build_domain(&domain_name, value, "init", target_arch, key);
     }
     let disk_members = config.domains.get("disk_members").unwrap().clone();
     if !disk_members.is_empty() {
@@ -105,7 +150,7 @@ pub fn build_all(log: String, arch: Option<String>) {
             // pool.spawn(move || build_domain(&domain_name, value, "disk"));
             // build_domain(&domain_name, value, "disk")
             let target_arch = arch.clone().into();
-            build_domain(&domain_name, value, "disk", target_arch)
+            build_domain(&domain_name, value, "disk", target_arch, key)
         }
     }
 }
@@ -7,6 +7,7 @@ pub mod clean;
 pub mod fmt;
 pub mod r#move;
 pub mod new;
+pub mod sign;
 
 #[derive(Deserialize)]
 pub struct Config {
@@ -0,0 +1,51 @@
+use std::{fs, path::Path};
+
+use ed25519_compact::{KeyPair, Seed};
+
+fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(hex: &str) -> Vec<u8> {
+    let hex = hex.trim();
+    assert_eq!(hex.len() % 2, 0, "Invalid hex string");
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).expect("Invalid hex string"))
+        .collect()
+}
+
+/// Generate a new signing key, the seed is written to `out` in hex.
+///
+/// The printed public key should be set to `DOMAIN_PUBLIC_KEY` when building the tcb.
+pub fn keygen(out: &str) {
+    let key_pair = KeyPair::generate();
+    fs::write(out, to_hex(&key_pair.sk.seed()[..])).expect("failed to write key file");
+    println!("Signing key is written to {}", out);
+    println!("DOMAIN_PUBLIC_KEY={}", to_hex(&key_pair.pk[..]));
+}
+
+/// Sign the domain file, the detached signature is written to `<file>.sig`.
+pub fn sign_file(file: &Path, key: &str) {
+    let seed = from_hex(&fs::read_to_string(key).expect("failed to read key file"));
+    let seed = Seed::from_slice(&seed).expect("Invalid signing key");
+    let key_pair = KeyPair::from_seed(seed);
+    let data = fs::read(file).expect("failed to read domain file");
+    let signature = key_pair.sk.sign(&data, None);
+    let sig_path = format!("{}.sig", file.display());
+    fs::write(&sig_path, &signature[..]).expect("failed to write signature");
+    println!("Sign {} success, signature: {}", file.display(), sig_path);
+}
+
+/// Sign the built domain in `./build/init` or `./build/disk`.
+pub fn sign_domain(name: &str, key: &str) {
+    for dir in ["init", "disk"] {
+        let path = format!("./build/{}/g{}", dir, name);
+        let path = Path::new(&path);
+        if path.exists() {
+            sign_file(path, key);
+            return;
+        }
+    }
+    println!("Domain [{}] is not built, skip signing", name);
+}